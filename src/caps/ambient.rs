@@ -32,10 +32,40 @@ pub fn lower(cap: Cap) -> crate::Result<()> {
     Ok(())
 }
 
+/// Raise the given capability in the current thread's ambient capability set, then verify that it
+/// is actually raised.
+///
+/// This fails with `EINVAL` if the capability is not raised afterwards.
+pub fn raise_strict(cap: Cap) -> crate::Result<()> {
+    raise(cap)?;
+
+    if is_set(cap) == Some(true) {
+        Ok(())
+    } else {
+        Err(crate::Error::from_code(libc::EINVAL))
+    }
+}
+
+/// Lower the given capability in the current thread's ambient capability set, then verify that it
+/// is actually lowered.
+///
+/// This fails with `EINVAL` if the capability is not lowered afterwards.
+pub fn lower_strict(cap: Cap) -> crate::Result<()> {
+    lower(cap)?;
+
+    if is_set(cap) == Some(false) {
+        Ok(())
+    } else {
+        Err(crate::Error::from_code(libc::EINVAL))
+    }
+}
+
 /// Check whether the given capability is raised in the current thread's ambient capability set.
 ///
 /// This returns `Some(true)` if the given capability is raised, `Some(false)` if it is lowered,
 /// and `None` if it is not supported.
+// With "sc", the None arm is just `None`
+#[cfg_attr(feature = "sc", allow(clippy::manual_map))]
 #[inline]
 pub fn is_set(cap: Cap) -> Option<bool> {
     match unsafe {
@@ -75,6 +105,20 @@ pub fn clear() -> crate::Result<()> {
     Ok(())
 }
 
+/// Clear the current thread's ambient capability set, then verify that it is actually empty
+/// (including any capabilities that this library is not aware of).
+///
+/// This fails with `EINVAL` if any capabilities are still raised afterwards.
+pub fn clear_strict() -> crate::Result<()> {
+    clear()?;
+
+    if probe() == Some(CapSet::empty()) && !has_unknown() {
+        Ok(())
+    } else {
+        Err(crate::Error::from_code(libc::EINVAL))
+    }
+}
+
 /// Check whether ambient capabilities are supported on the running kernel.
 #[inline]
 pub fn is_supported() -> bool {
@@ -109,6 +153,28 @@ pub fn probe() -> Option<CapSet> {
     Some(set)
 }
 
+/// Check whether any capabilities that this library is not aware of are raised in the current
+/// thread's ambient capability set.
+pub(crate) fn has_unknown() -> bool {
+    for cap in (super::CAP_MAX as libc::c_ulong + 1)..64 {
+        match unsafe {
+            crate::raw_prctl_opt(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_IS_SET as libc::c_ulong,
+                cap,
+                0,
+                0,
+            )
+        } {
+            Some(0) => (),
+            Some(_) => return true,
+            None => break,
+        }
+    }
+
+    false
+}
+
 /// Drop all bounding capabilities that are supported by the kernel but which this library is not
 /// aware of from the current thread's ambient capability set.
 ///
@@ -209,4 +275,53 @@ mod tests {
             assert_eq!(clear_unknown().unwrap_err().code(), libc::EINVAL);
         }
     }
+
+    #[test]
+    fn test_ambient_strict() {
+        if is_supported() {
+            let orig_caps = probe().unwrap();
+            let supported_caps = Cap::probe_supported();
+
+            let orig_state = crate::caps::CapState::get_current().unwrap();
+            let mut state = orig_state;
+            state.inheritable = state.permitted;
+            state.set_current().unwrap();
+
+            clear_strict().unwrap();
+            assert_eq!(probe().unwrap(), CapSet::empty());
+
+            for cap in state.inheritable {
+                raise_strict(cap).unwrap();
+                assert_eq!(is_set(cap), Some(true));
+            }
+            assert_eq!(probe().unwrap(), state.inheritable & supported_caps);
+
+            for cap in state.inheritable {
+                lower_strict(cap).unwrap();
+                assert_eq!(is_set(cap), Some(false));
+            }
+            assert_eq!(probe().unwrap(), CapSet::empty());
+
+            // Capabilities that aren't in the inheritable set can't be raised
+            state.inheritable.clear();
+            state.set_current().unwrap();
+            for cap in supported_caps {
+                assert_eq!(raise_strict(cap).unwrap_err().code(), libc::EPERM);
+            }
+
+            for cap in !supported_caps {
+                assert_eq!(raise_strict(cap).unwrap_err().code(), libc::EINVAL);
+                assert_eq!(lower_strict(cap).unwrap_err().code(), libc::EINVAL);
+            }
+
+            orig_state.set_current().unwrap();
+            for cap in orig_caps.iter() {
+                raise(cap).unwrap();
+            }
+        } else {
+            assert_eq!(raise_strict(Cap::CHOWN).unwrap_err().code(), libc::EINVAL);
+            assert_eq!(lower_strict(Cap::CHOWN).unwrap_err().code(), libc::EINVAL);
+            assert_eq!(clear_strict().unwrap_err().code(), libc::EINVAL);
+        }
+    }
 }
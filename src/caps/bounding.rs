@@ -12,6 +12,20 @@ pub fn drop(cap: Cap) -> crate::Result<()> {
     Ok(())
 }
 
+/// Drop the given capability from the current thread's bounding capability set, then verify that
+/// it is actually lowered.
+///
+/// This fails with `EINVAL` if the capability is still raised afterwards.
+pub fn drop_strict(cap: Cap) -> crate::Result<()> {
+    drop(cap)?;
+
+    if read(cap) == Some(false) {
+        Ok(())
+    } else {
+        Err(crate::Error::from_code(libc::EINVAL))
+    }
+}
+
 /// Check if the given capability is raised in the current thread's bounding capability set.
 ///
 /// This returns `Some(true)` if the given capability is raised, `Some(false)` if it is lowered, and
@@ -21,6 +35,8 @@ pub fn read(cap: Cap) -> Option<bool> {
     read_raw(cap as _)
 }
 
+// With "sc", the None arm is just `None`
+#[cfg_attr(feature = "sc", allow(clippy::manual_map))]
 #[inline]
 fn read_raw(cap: libc::c_ulong) -> Option<bool> {
     match unsafe { crate::raw_prctl_opt(libc::PR_CAPBSET_READ, cap, 0, 0, 0) } {
@@ -98,6 +114,15 @@ fn ensure_dropped_raw(cap: libc::c_ulong) -> crate::Result<()> {
     }
 }
 
+/// Check whether any capabilities that this library is not aware of are raised in the current
+/// thread's bounding capability set.
+pub(crate) fn has_unknown() -> bool {
+    (super::CAP_MAX as libc::c_ulong + 1..64)
+        .map(read_raw)
+        .take_while(Option::is_some)
+        .any(|res| res == Some(true))
+}
+
 fn clear_from(low: libc::c_ulong) -> crate::Result<()> {
     for cap in low..(super::CAP_MAX as libc::c_ulong * 2) {
         match ensure_dropped_raw(cap) {
@@ -138,6 +163,21 @@ pub fn clear() -> crate::Result<()> {
     clear_from(0)
 }
 
+/// Drop all capabilities supported by the kernel from the current thread's bounding capability
+/// set (like [`clear()`]), then verify that the set is actually empty.
+///
+/// This fails with `EINVAL` if any capabilities (including ones that this library is not aware
+/// of) are still raised afterwards.
+pub fn clear_strict() -> crate::Result<()> {
+    clear()?;
+
+    if probe().is_empty() && !has_unknown() {
+        Ok(())
+    } else {
+        Err(crate::Error::from_code(libc::EINVAL))
+    }
+}
+
 /// Drop all capabilities that are supported by the kernel but which this library is not aware of
 /// from the current thread's bounding capability set.
 ///
@@ -181,6 +221,8 @@ mod tests {
             .has(crate::caps::Cap::SETPCAP)
         {
             assert!(read(crate::caps::Cap::SETPCAP).unwrap());
+            drop_strict(crate::caps::Cap::NET_RAW).unwrap();
+            assert!(!read(crate::caps::Cap::NET_RAW).unwrap());
             drop(crate::caps::Cap::SETPCAP).unwrap();
             assert!(!read(crate::caps::Cap::SETPCAP).unwrap());
         } else {
@@ -188,6 +230,10 @@ mod tests {
                 drop(crate::caps::Cap::SETPCAP).unwrap_err().code(),
                 libc::EPERM
             );
+            assert_eq!(
+                drop_strict(crate::caps::Cap::SETPCAP).unwrap_err().code(),
+                libc::EPERM
+            );
         }
     }
 
@@ -197,7 +243,7 @@ mod tests {
         if state.effective.has(crate::caps::Cap::SETPCAP) || probe().is_empty() {
             clear().unwrap();
             assert_eq!(probe(), crate::caps::CapSet::empty());
-            clear().unwrap();
+            clear_strict().unwrap();
             assert_eq!(probe(), crate::caps::CapSet::empty());
 
             state.effective.drop(crate::caps::Cap::SETPCAP);
@@ -1,14 +1,24 @@
 use core::fmt;
 
-use crate::caps::{CapSet, CapState, NUM_CAPS};
+use crate::caps::{CapSet, CapState, RawCap, RawCapState, CAP_BITMASK, NUM_CAPS};
 
 pub fn caps_from_text(s: &str) -> Result<CapState, ParseCapsError> {
+    let state = raw_caps_from_text(s)?;
+
+    if state.unknown() != RawCapState::empty() {
+        return Err(ParseCapsError::UnknownCapability);
+    }
+
+    Ok(state.known())
+}
+
+pub fn raw_caps_from_text(s: &str) -> Result<RawCapState, ParseCapsError> {
     let s = s.trim();
     if s.is_empty() {
         return Err(ParseCapsError::InvalidFormat);
     }
 
-    let mut res = CapState::empty();
+    let mut res = RawCapState::empty();
 
     for part in s.split_whitespace() {
         update_capstate_single(part, &mut res)?;
@@ -17,8 +27,8 @@ pub fn caps_from_text(s: &str) -> Result<CapState, ParseCapsError> {
     Ok(res)
 }
 
-fn update_capstate_single(s: &str, state: &mut CapState) -> Result<(), ParseCapsError> {
-    let index = match s.find(|c| c == '+' || c == '-' || c == '=') {
+fn update_capstate_single(s: &str, state: &mut RawCapState) -> Result<(), ParseCapsError> {
+    let index = match s.find(['+', '-', '=']) {
         Some(i) => i,
         None => return Err(ParseCapsError::InvalidFormat),
     };
@@ -48,9 +58,9 @@ fn update_capstate_single(s: &str, state: &mut CapState) -> Result<(), ParseCaps
 
         let set = match ch {
             '=' => {
-                state.effective -= spec_caps;
-                state.inheritable -= spec_caps;
-                state.permitted -= spec_caps;
+                state.effective &= !spec_caps;
+                state.inheritable &= !spec_caps;
+                state.permitted &= !spec_caps;
 
                 should_raise = true;
                 None
@@ -76,7 +86,7 @@ fn update_capstate_single(s: &str, state: &mut CapState) -> Result<(), ParseCaps
             if should_raise {
                 *set |= spec_caps;
             } else {
-                *set -= spec_caps;
+                *set &= !spec_caps;
             }
         }
 
@@ -86,16 +96,17 @@ fn update_capstate_single(s: &str, state: &mut CapState) -> Result<(), ParseCaps
     Ok(())
 }
 
-fn parse_capset(s: &str) -> Result<CapSet, ParseCapsError> {
+fn parse_capset(s: &str) -> Result<u64, ParseCapsError> {
+    // Note: "all" only includes the capabilities we know about
     if s.is_empty() || s.eq_ignore_ascii_case("all") {
-        return Ok(!CapSet::empty());
+        return Ok(CAP_BITMASK);
     }
 
-    let mut res = CapSet::empty();
+    let mut res = 0;
 
     for part in s.split(',') {
-        match part.parse() {
-            Ok(cap) => res.add(cap),
+        match part.parse::<RawCap>() {
+            Ok(cap) => res |= cap.bitmask(),
             Err(_) => {
                 return Err(ParseCapsError::UnknownCapability);
             }
@@ -369,6 +380,81 @@ pub fn caps_to_text(mut state: CapState, f: &mut fmt::Formatter) -> fmt::Result
     Ok(())
 }
 
+pub fn raw_caps_to_text(state: RawCapState, f: &mut fmt::Formatter) -> fmt::Result {
+    use core::fmt::Write;
+
+    let known = state.known();
+    let unknown = state.unknown();
+
+    let mut first = true;
+
+    if known != CapState::empty() || unknown == RawCapState::empty() {
+        caps_to_text(known, f)?;
+        first = false;
+    }
+
+    // The capabilities we don't know about are grouped by exactly which sets they are present in,
+    // so (unlike in caps_to_text()) we never have to drop anything.
+    for &(effective, inheritable, permitted) in [
+        (true, true, true),
+        (true, true, false),
+        (false, true, true),
+        (true, false, true),
+        (true, false, false),
+        (false, true, false),
+        (false, false, true),
+    ]
+    .iter()
+    {
+        let mut caps = !CAP_BITMASK;
+
+        for &(flag, set) in [
+            (effective, unknown.effective),
+            (inheritable, unknown.inheritable),
+            (permitted, unknown.permitted),
+        ]
+        .iter()
+        {
+            if flag {
+                caps &= set;
+            } else {
+                caps &= !set;
+            }
+        }
+
+        if caps == 0 {
+            continue;
+        }
+
+        if !first {
+            f.write_char(' ')?;
+        }
+
+        let mut first_cap = true;
+        for i in NUM_CAPS..64 {
+            if caps & (1 << i) != 0 {
+                write!(f, "{}cap_{}", if first_cap { "" } else { "," }, i)?;
+                first_cap = false;
+            }
+        }
+
+        f.write_char(if first { '=' } else { '+' })?;
+        first = false;
+
+        if effective {
+            f.write_char('e')?;
+        }
+        if inheritable {
+            f.write_char('i')?;
+        }
+        if permitted {
+            f.write_char('p')?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
@@ -378,15 +464,23 @@ mod tests {
 
     #[test]
     fn test_parse_capset() {
-        assert_eq!(parse_capset("").unwrap(), !CapSet::empty());
-        assert_eq!(parse_capset("all").unwrap(), !CapSet::empty());
-        assert_eq!(parse_capset("ALL").unwrap(), !CapSet::empty());
+        assert_eq!(parse_capset("").unwrap(), (!CapSet::empty()).bits);
+        assert_eq!(parse_capset("all").unwrap(), (!CapSet::empty()).bits);
+        assert_eq!(parse_capset("ALL").unwrap(), (!CapSet::empty()).bits);
 
-        assert_eq!(parse_capset("cap_chown").unwrap(), capset!(Cap::CHOWN));
-        assert_eq!(parse_capset("CAP_CHOWN").unwrap(), capset!(Cap::CHOWN));
+        assert_eq!(parse_capset("cap_chown").unwrap(), capset!(Cap::CHOWN).bits);
+        assert_eq!(parse_capset("CAP_CHOWN").unwrap(), capset!(Cap::CHOWN).bits);
         assert_eq!(
             parse_capset("cap_chown,cap_syslog").unwrap(),
-            capset!(Cap::CHOWN, Cap::SYSLOG),
+            capset!(Cap::CHOWN, Cap::SYSLOG).bits,
+        );
+        assert_eq!(
+            parse_capset("cap_0,5").unwrap(),
+            capset!(Cap::CHOWN, Cap::KILL).bits
+        );
+        assert_eq!(
+            parse_capset("cap_41,cap_63").unwrap(),
+            (1 << 41) | (1 << 63)
         );
 
         assert_eq!(
@@ -397,6 +491,10 @@ mod tests {
             parse_capset(",").unwrap_err().to_string(),
             "Unknown capability"
         );
+        assert_eq!(
+            parse_capset("cap_64").unwrap_err().to_string(),
+            "Unknown capability"
+        );
     }
 
     #[test]
@@ -497,4 +595,49 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_parse_raw_capstate() {
+        assert_eq!(
+            raw_caps_from_text("cap_41+p").unwrap(),
+            RawCapState {
+                permitted: 1 << 41,
+                effective: 0,
+                inheritable: 0,
+            }
+        );
+
+        assert_eq!(
+            raw_caps_from_text("cap_chown,cap_41=eip cap_41-i").unwrap(),
+            RawCapState {
+                permitted: (1 << 41) | 1,
+                effective: (1 << 41) | 1,
+                inheritable: 1,
+            }
+        );
+
+        // "all" only covers known capabilities
+        assert_eq!(
+            raw_caps_from_text("=ep 63+e").unwrap(),
+            RawCapState {
+                permitted: CAP_BITMASK,
+                effective: CAP_BITMASK | (1 << 63),
+                inheritable: 0,
+            }
+        );
+
+        // Numeric capabilities work with caps_from_text() as long as they're known
+        assert_eq!(
+            caps_from_text("cap_0=p").unwrap(),
+            caps_from_text("cap_chown=p").unwrap()
+        );
+        assert_eq!(
+            caps_from_text("cap_41=p cap_41-p").unwrap(),
+            CapState::empty()
+        );
+        assert_eq!(
+            caps_from_text("cap_63+p").unwrap_err().to_string(),
+            "Unknown capability"
+        );
+    }
 }
@@ -142,6 +142,7 @@ impl CapSet {
         }
     }
 
+    #[cfg(feature = "std")]
     #[inline]
     pub(crate) fn from_bitmasks_u32(lower: u32, upper: u32) -> Self {
         Self::from_bitmask_truncate(((upper as u64) << 32) | (lower as u64))
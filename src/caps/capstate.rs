@@ -2,8 +2,10 @@ use core::fmt;
 
 use crate::sys;
 
-use super::cap_text::{caps_from_text, caps_to_text, ParseCapsError};
-use super::CapSet;
+use super::cap_text::{
+    caps_from_text, caps_to_text, raw_caps_from_text, raw_caps_to_text, ParseCapsError,
+};
+use super::{CapSet, CAP_BITMASK};
 
 /// Represents the permitted, effective, and inheritable capability sets of a thread.
 ///
@@ -43,6 +45,127 @@ impl CapState {
     /// Get the capability state of the process (or thread) with the given PID (or TID).
     ///
     /// If `pid` is 0, this method gets the capability state of the current thread.
+    ///
+    /// Any capabilities that this library is not aware of are discarded; see [`RawCapState`] if
+    /// you need to preserve them.
+    #[inline]
+    pub fn get_for_pid(pid: libc::pid_t) -> crate::Result<Self> {
+        RawCapState::get_for_pid(pid).map(|raw| raw.known())
+    }
+
+    /// Get the capability state of the process referred to by the given pidfd.
+    ///
+    /// Unlike [`get_for_pid()`](#method.get_for_pid), this is immune to PID reuse; see
+    /// [`RawCapState::get_for_pidfd()`].
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn get_for_pidfd(pidfd: std::os::unix::io::BorrowedFd) -> crate::Result<Self> {
+        RawCapState::get_for_pidfd(pidfd).map(|raw| raw.known())
+    }
+
+    /// Set the current capability state to the state represented by this object.
+    ///
+    /// Note that this will remove any capabilities that this library is not aware of from the
+    /// current thread's permitted, effective, and inheritable sets. See [`RawCapState`] for a way
+    /// to avoid this.
+    #[inline]
+    pub fn set_current(&self) -> crate::Result<()> {
+        RawCapState::from(*self).set_current()
+    }
+
+    /// Set the current capability state, then re-read it and return the capabilities that were
+    /// requested but not applied.
+    ///
+    /// The kernel silently removes unsupported capabilities from the new sets (see [Handling of
+    /// capabilities not supported by the
+    /// kernel](../index.html#handling-of-capabilities-not-supported-by-the-kernel)). The returned
+    /// `CapState` contains the capabilities in each set that were requested but are not present
+    /// after the change; it will be empty if everything was applied.
+    pub fn set_current_verified(&self) -> crate::Result<Self> {
+        self.set_current()?;
+        let actual = Self::get_current()?;
+
+        Ok(Self {
+            effective: self.effective - actual.effective,
+            permitted: self.permitted - actual.permitted,
+            inheritable: self.inheritable - actual.inheritable,
+        })
+    }
+
+    /// Set the current capability state, then re-read it and fail with `EINVAL` if it does not
+    /// match the requested state.
+    ///
+    /// Note that if this fails with `EINVAL`, the state has still been changed (as far as the
+    /// kernel allowed).
+    pub fn set_current_strict(&self) -> crate::Result<()> {
+        self.set_current()?;
+
+        if Self::get_current()? == *self {
+            Ok(())
+        } else {
+            Err(crate::Error::from_code(libc::EINVAL))
+        }
+    }
+}
+
+/// Represents the permitted, effective, and inheritable capability sets of a thread as raw
+/// bitmasks.
+///
+/// Unlike [`CapState`], this preserves the full 64-bit masks reported by the kernel, including any
+/// capabilities that this library is not aware of. This makes it possible to perform a
+/// "read-modify-write" of the current thread's capabilities without removing capabilities added in
+/// newer kernels (see [Handling of newly-added
+/// capabilities](../index.html#handling-of-newly-added-capabilities)). For example:
+///
+/// ```
+/// # use capctl::caps::{Cap, RawCapState};
+/// let mut raw = RawCapState::get_current().unwrap();
+///
+/// let mut state = raw.known();
+/// state.effective.drop(Cap::CHOWN);
+/// raw.set_known(state);
+///
+/// raw.set_current().unwrap();
+/// ```
+///
+/// # `FromStr` and `Display` implementations
+///
+/// These use the same format as [`CapState`'s
+/// implementations](./struct.CapState.html#fromstr-and-display-implementations), except that
+/// capabilities this library is not aware of are represented by number (for example, `cap_41+p`;
+/// see [`RawCap`](./struct.RawCap.html)). Note that `all` (or an empty list of capabilities, as in
+/// `=eip`) only refers to the capabilities that this library is aware of.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RawCapState {
+    pub effective: u64,
+    pub permitted: u64,
+    pub inheritable: u64,
+}
+
+impl RawCapState {
+    /// Construct an empty `RawCapState` object.
+    #[inline]
+    pub fn empty() -> Self {
+        Self {
+            effective: 0,
+            permitted: 0,
+            inheritable: 0,
+        }
+    }
+
+    /// Get the raw capability state of the current thread.
+    ///
+    /// This is equivalent to `RawCapState::get_for_pid(0)`.
+    #[inline]
+    pub fn get_current() -> crate::Result<Self> {
+        Self::get_for_pid(0)
+    }
+
+    /// Get the raw capability state of the process (or thread) with the given PID (or TID).
+    ///
+    /// If `pid` is 0, this method gets the capability state of the current thread.
     pub fn get_for_pid(pid: libc::pid_t) -> crate::Result<Self> {
         let mut header = sys::cap_user_header_t {
             version: sys::_LINUX_CAPABILITY_VERSION_3,
@@ -67,13 +190,39 @@ impl CapState {
             }
         }
 
+        #[inline]
+        fn combine(lower: u32, upper: u32) -> u64 {
+            ((upper as u64) << 32) | (lower as u64)
+        }
+
         Ok(Self {
-            effective: CapSet::from_bitmasks_u32(raw_dat[0].effective, raw_dat[1].effective),
-            permitted: CapSet::from_bitmasks_u32(raw_dat[0].permitted, raw_dat[1].permitted),
-            inheritable: CapSet::from_bitmasks_u32(raw_dat[0].inheritable, raw_dat[1].inheritable),
+            effective: combine(raw_dat[0].effective, raw_dat[1].effective),
+            permitted: combine(raw_dat[0].permitted, raw_dat[1].permitted),
+            inheritable: combine(raw_dat[0].inheritable, raw_dat[1].inheritable),
         })
     }
 
+    /// Get the raw capability state of the process referred to by the given pidfd.
+    ///
+    /// Unlike [`get_for_pid()`](#method.get_for_pid), this is immune to PID reuse: after the
+    /// capability state is retrieved, this checks that the process referred to by `pidfd` is still
+    /// alive (so its PID cannot have been reused in the meantime). This fails with `ESRCH` if the
+    /// process has exited, and with `EINVAL` if `pidfd` is not a pidfd.
+    ///
+    /// This requires Linux 5.4 or newer.
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    #[cfg(feature = "std")]
+    pub fn get_for_pidfd(pidfd: std::os::unix::io::BorrowedFd) -> crate::Result<Self> {
+        // These only fail with OS errors, except if the fdinfo file is malformed
+        let to_err =
+            |e: std::io::Error| crate::Error::from_code(e.raw_os_error().unwrap_or(libc::EIO));
+
+        let pid = crate::procs::pidfd_getpid(pidfd).map_err(to_err)?;
+        let state = Self::get_for_pid(pid)?;
+        crate::procs::pidfd_check_alive(pidfd).map_err(to_err)?;
+        Ok(state)
+    }
+
     /// Set the current capability state to the state represented by this object.
     pub fn set_current(&self) -> crate::Result<()> {
         let mut header = sys::cap_user_header_t {
@@ -81,20 +230,16 @@ impl CapState {
             pid: 0,
         };
 
-        let effective = self.effective.bits;
-        let permitted = self.permitted.bits;
-        let inheritable = self.inheritable.bits;
-
         let raw_dat = [
             sys::cap_user_data_t {
-                effective: effective as u32,
-                permitted: permitted as u32,
-                inheritable: inheritable as u32,
+                effective: self.effective as u32,
+                permitted: self.permitted as u32,
+                inheritable: self.inheritable as u32,
             },
             sys::cap_user_data_t {
-                effective: (effective >> 32) as u32,
-                permitted: (permitted >> 32) as u32,
-                inheritable: (inheritable >> 32) as u32,
+                effective: (self.effective >> 32) as u32,
+                permitted: (self.permitted >> 32) as u32,
+                inheritable: (self.inheritable >> 32) as u32,
             },
         ];
 
@@ -112,6 +257,78 @@ impl CapState {
 
         Ok(())
     }
+
+    /// Set the current raw capability state, then re-read it and return the capabilities that were
+    /// requested but not applied.
+    ///
+    /// See [`CapState::set_current_verified()`].
+    pub fn set_current_verified(&self) -> crate::Result<Self> {
+        self.set_current()?;
+        let actual = Self::get_current()?;
+
+        Ok(Self {
+            effective: self.effective & !actual.effective,
+            permitted: self.permitted & !actual.permitted,
+            inheritable: self.inheritable & !actual.inheritable,
+        })
+    }
+
+    /// Set the current raw capability state, then re-read it and fail with `EINVAL` if it does not
+    /// match the requested state.
+    ///
+    /// See [`CapState::set_current_strict()`].
+    pub fn set_current_strict(&self) -> crate::Result<()> {
+        self.set_current()?;
+
+        if Self::get_current()? == *self {
+            Ok(())
+        } else {
+            Err(crate::Error::from_code(libc::EINVAL))
+        }
+    }
+
+    /// Get the portion of this state that consists of capabilities this library is aware of.
+    #[inline]
+    pub fn known(&self) -> CapState {
+        CapState {
+            effective: CapSet::from_bitmask_truncate(self.effective),
+            permitted: CapSet::from_bitmask_truncate(self.permitted),
+            inheritable: CapSet::from_bitmask_truncate(self.inheritable),
+        }
+    }
+
+    /// Get the portion of this state that consists of capabilities this library is *not* aware
+    /// of.
+    #[inline]
+    pub fn unknown(&self) -> Self {
+        Self {
+            effective: self.effective & !CAP_BITMASK,
+            permitted: self.permitted & !CAP_BITMASK,
+            inheritable: self.inheritable & !CAP_BITMASK,
+        }
+    }
+
+    /// Replace the capabilities this library is aware of with the ones in `state`, leaving any
+    /// unknown capabilities untouched.
+    #[inline]
+    pub fn set_known(&mut self, state: CapState) {
+        let unknown = self.unknown();
+
+        self.effective = unknown.effective | state.effective.bits;
+        self.permitted = unknown.permitted | state.permitted.bits;
+        self.inheritable = unknown.inheritable | state.inheritable.bits;
+    }
+}
+
+impl From<CapState> for RawCapState {
+    #[inline]
+    fn from(state: CapState) -> Self {
+        Self {
+            effective: state.effective.bits,
+            permitted: state.permitted.bits,
+            inheritable: state.inheritable.bits,
+        }
+    }
 }
 
 impl fmt::Display for CapState {
@@ -129,7 +346,22 @@ impl core::str::FromStr for CapState {
     }
 }
 
-/// Represents an error when parsing a `CapState` object from a string.
+impl fmt::Display for RawCapState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        raw_caps_to_text(*self, f)
+    }
+}
+
+impl core::str::FromStr for RawCapState {
+    type Err = ParseCapStateError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        raw_caps_from_text(s).map_err(ParseCapStateError)
+    }
+}
+
+/// Represents an error when parsing a `CapState` (or `RawCapState`) object from a string.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct ParseCapStateError(ParseCapsError);
 
@@ -176,6 +408,112 @@ mod tests {
         state.set_current().unwrap();
     }
 
+    #[test]
+    fn test_rawcapstate_getset_current() {
+        let raw = RawCapState::get_current().unwrap();
+        assert_eq!(raw, RawCapState::get_for_pid(0).unwrap());
+        assert_eq!(raw.known(), CapState::get_current().unwrap());
+        raw.set_current().unwrap();
+        assert_eq!(RawCapState::get_current().unwrap(), raw);
+
+        assert_eq!(
+            RawCapState::get_for_pid(-1).unwrap_err().code(),
+            libc::EINVAL
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_capstate_get_for_pidfd() {
+        use std::os::unix::io::{AsFd, FromRawFd, OwnedFd};
+
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) } as i32;
+        if pidfd < 0 {
+            return;
+        }
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd) };
+
+        assert_eq!(
+            CapState::get_for_pidfd(pidfd.as_fd()).unwrap(),
+            CapState::get_for_pid(unsafe { libc::getpid() }).unwrap()
+        );
+        assert_eq!(
+            RawCapState::get_for_pidfd(pidfd.as_fd()).unwrap(),
+            RawCapState::get_for_pid(unsafe { libc::getpid() }).unwrap()
+        );
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id(), 0) } as i32;
+        assert!(pidfd >= 0);
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd) };
+        child.wait().unwrap();
+        assert_eq!(
+            CapState::get_for_pidfd(pidfd.as_fd()).unwrap_err().code(),
+            libc::ESRCH
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_capstate_set_verified() {
+        std::thread::spawn(|| {
+            let state = CapState::get_current().unwrap();
+            assert_eq!(state.set_current_verified().unwrap(), CapState::empty());
+            state.set_current_strict().unwrap();
+
+            // The kernel silently ignores unsupported capabilities
+            let raw = RawCapState::get_current().unwrap();
+            let mut req = raw;
+            req.effective |= 1 << 63;
+            assert_eq!(
+                req.set_current_verified().unwrap(),
+                RawCapState {
+                    effective: 1 << 63,
+                    permitted: 0,
+                    inheritable: 0,
+                }
+            );
+            assert_eq!(req.set_current_strict().unwrap_err().code(), libc::EINVAL);
+            assert_eq!(RawCapState::get_current().unwrap(), raw);
+            raw.set_current_strict().unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rawcapstate_known() {
+        let state = CapState {
+            effective: capset!(Cap::CHOWN),
+            permitted: capset!(Cap::CHOWN, Cap::SYSLOG),
+            inheritable: capset!(Cap::SYSLOG),
+        };
+
+        let unknown = RawCapState {
+            effective: 1 << 63,
+            permitted: 1 << 63,
+            inheritable: 1 << 62,
+        };
+
+        assert_eq!(RawCapState::empty().known(), CapState::empty());
+        assert_eq!(RawCapState::from(state).known(), state);
+        assert_eq!(RawCapState::from(state).unknown(), RawCapState::empty());
+        assert_eq!(unknown.known(), CapState::empty());
+        assert_eq!(unknown.unknown(), unknown);
+
+        let mut raw = unknown;
+        raw.set_known(state);
+        assert_eq!(raw.known(), state);
+        assert_eq!(raw.unknown(), unknown);
+        assert_eq!(
+            raw.permitted,
+            (1 << 63) | (1 << Cap::CHOWN as u8) | (1 << Cap::SYSLOG as u8)
+        );
+
+        raw.set_known(CapState::empty());
+        assert_eq!(raw, unknown);
+    }
+
     #[test]
     fn test_capstate_get_bad_pid() {
         assert_eq!(CapState::get_for_pid(-1).unwrap_err().code(), libc::EINVAL);
@@ -331,6 +669,68 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rawcapstate_display() {
+        assert_eq!(RawCapState::empty().to_string(), "=");
+        assert_eq!(
+            RawCapState::from(CapState::from_str("cap_chown=ep").unwrap()).to_string(),
+            "cap_chown=ep"
+        );
+
+        assert_eq!(
+            RawCapState {
+                effective: 1 << 41,
+                permitted: 1 << 41,
+                inheritable: 0,
+            }
+            .to_string(),
+            "cap_41=ep"
+        );
+
+        assert_eq!(
+            RawCapState {
+                effective: 1,
+                permitted: (1 << 41) | (1 << 42) | 1,
+                inheritable: 1 << 63,
+            }
+            .to_string(),
+            "cap_chown=ep cap_63+i cap_41,cap_42+p"
+        );
+
+        for state in [
+            RawCapState::empty(),
+            RawCapState {
+                effective: u64::MAX,
+                permitted: u64::MAX,
+                inheritable: u64::MAX,
+            },
+            RawCapState {
+                effective: 1 << 63,
+                permitted: u64::MAX,
+                inheritable: CAP_BITMASK,
+            },
+            RawCapState {
+                effective: 0,
+                permitted: (1 << 41) | (1 << 50),
+                inheritable: (1 << 50) | 1,
+            },
+            RawCapState::get_current().unwrap(),
+            RawCapState::get_for_pid(1).unwrap(),
+        ]
+        .iter()
+        {
+            let s = state.to_string();
+
+            assert_eq!(s.parse::<RawCapState>().unwrap(), *state, "{:?}", s);
+        }
+
+        assert_eq!(
+            CapState::from_str("cap_41+p").unwrap_err().to_string(),
+            "Unknown capability"
+        );
+    }
+
     #[cfg(feature = "std")]
     #[test]
     fn test_capstate_display_names() {
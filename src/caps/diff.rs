@@ -0,0 +1,477 @@
+use core::fmt;
+use core::fmt::Write;
+
+use super::{Cap, CapSet, CapState, FileCaps, FullCapState};
+
+/// Represents the differences between two capability sets.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CapSetDiff {
+    /// The capabilities present in the new set but not in the old set.
+    pub added: CapSet,
+    /// The capabilities present in the old set but not in the new set.
+    pub removed: CapSet,
+}
+
+impl CapSetDiff {
+    /// Compute the differences between `old` and `new`.
+    #[inline]
+    pub fn between(old: CapSet, new: CapSet) -> Self {
+        Self {
+            added: new - old,
+            removed: old - new,
+        }
+    }
+
+    /// Check whether the two sets were identical.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Write clauses describing the given (labeled) set differences in `libcap`'s text format.
+///
+/// Capabilities that were added to and removed from the same sets are grouped together; for
+/// example, `cap_chown,cap_kill+ep cap_setuid-i`.
+fn write_clauses(
+    f: &mut fmt::Formatter,
+    diffs: &[(char, CapSetDiff)],
+    first: &mut bool,
+) -> fmt::Result {
+    // Get bitmasks (indexed by position in `diffs`) of the sets a capability was added to/removed
+    // from
+    let flags = |cap: Cap| {
+        diffs
+            .iter()
+            .enumerate()
+            .fold((0u8, 0u8), |(added, removed), (i, (_, diff))| {
+                (
+                    added | ((diff.added.has(cap) as u8) << i),
+                    removed | ((diff.removed.has(cap) as u8) << i),
+                )
+            })
+    };
+
+    let write_letters = |f: &mut fmt::Formatter, mask: u8| -> fmt::Result {
+        for (i, (ch, _)) in diffs.iter().enumerate() {
+            if mask & (1 << i) != 0 {
+                f.write_char(*ch)?;
+            }
+        }
+        Ok(())
+    };
+
+    let mut done = CapSet::empty();
+
+    for cap in Cap::iter() {
+        let (added, removed) = flags(cap);
+        if done.has(cap) || (added == 0 && removed == 0) {
+            continue;
+        }
+
+        let group: CapSet = Cap::iter()
+            .filter(|&other| flags(other) == (added, removed))
+            .collect();
+        done |= group;
+
+        write_separator(f, first)?;
+
+        for (i, cap) in group.iter().enumerate() {
+            if i != 0 {
+                f.write_char(',')?;
+            }
+
+            f.write_str("cap_")?;
+            for ch in cap.name().chars() {
+                f.write_char(ch.to_ascii_lowercase())?;
+            }
+        }
+
+        if added != 0 {
+            f.write_char('+')?;
+            write_letters(f, added)?;
+        }
+        if removed != 0 {
+            f.write_char('-')?;
+            write_letters(f, removed)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn write_separator(f: &mut fmt::Formatter, first: &mut bool) -> fmt::Result {
+    if !*first {
+        f.write_char(' ')?;
+    }
+    *first = false;
+    Ok(())
+}
+
+/// Represents the differences between two [`CapState`]s.
+///
+/// # `Display` implementation
+///
+/// This is displayed in a format similar to `libcap`'s text format, listing only the changes. For
+/// example, `cap_chown+ep cap_kill-i` means that `CAP_CHOWN` was added to the effective and
+/// permitted sets, and `CAP_KILL` was removed from the inheritable set. If there are no
+/// differences, the result is an empty string.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CapStateDiff {
+    pub effective: CapSetDiff,
+    pub permitted: CapSetDiff,
+    pub inheritable: CapSetDiff,
+}
+
+impl CapStateDiff {
+    /// Compute the differences between `old` and `new`.
+    #[inline]
+    pub fn between(old: &CapState, new: &CapState) -> Self {
+        Self {
+            effective: CapSetDiff::between(old.effective, new.effective),
+            permitted: CapSetDiff::between(old.permitted, new.permitted),
+            inheritable: CapSetDiff::between(old.inheritable, new.inheritable),
+        }
+    }
+
+    /// Check whether the two states were identical.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.effective.is_empty() && self.permitted.is_empty() && self.inheritable.is_empty()
+    }
+}
+
+impl fmt::Display for CapStateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_clauses(
+            f,
+            &[
+                ('e', self.effective),
+                ('i', self.inheritable),
+                ('p', self.permitted),
+            ],
+            &mut true,
+        )
+    }
+}
+
+/// Represents the differences between two [`FullCapState`]s.
+///
+/// # `Display` implementation
+///
+/// This uses the same format as [`CapStateDiff`'s
+/// implementation](./struct.CapStateDiff.html#display-implementation), except that changes to the
+/// ambient and bounding sets are indicated with the letters `a` and `b`, respectively, and changes
+/// to the "no new privileges" flag are indicated with `no_new_privs+` or `no_new_privs-`. For
+/// example: `cap_net_raw+a cap_sys_admin-b no_new_privs+`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FullCapStateDiff {
+    pub effective: CapSetDiff,
+    pub permitted: CapSetDiff,
+    pub inheritable: CapSetDiff,
+    pub ambient: CapSetDiff,
+    pub bounding: CapSetDiff,
+    /// The new value of the "no new privileges" flag, if it changed.
+    pub no_new_privs: Option<bool>,
+}
+
+impl FullCapStateDiff {
+    /// Compute the differences between `old` and `new`.
+    pub fn between(old: &FullCapState, new: &FullCapState) -> Self {
+        Self {
+            effective: CapSetDiff::between(old.effective, new.effective),
+            permitted: CapSetDiff::between(old.permitted, new.permitted),
+            inheritable: CapSetDiff::between(old.inheritable, new.inheritable),
+            ambient: CapSetDiff::between(old.ambient, new.ambient),
+            bounding: CapSetDiff::between(old.bounding, new.bounding),
+            no_new_privs: if old.no_new_privs != new.no_new_privs {
+                Some(new.no_new_privs)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Check whether the two states were identical.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.effective.is_empty()
+            && self.permitted.is_empty()
+            && self.inheritable.is_empty()
+            && self.ambient.is_empty()
+            && self.bounding.is_empty()
+            && self.no_new_privs.is_none()
+    }
+}
+
+impl fmt::Display for FullCapStateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        write_clauses(
+            f,
+            &[
+                ('e', self.effective),
+                ('i', self.inheritable),
+                ('p', self.permitted),
+                ('a', self.ambient),
+                ('b', self.bounding),
+            ],
+            &mut first,
+        )?;
+
+        if let Some(nnp) = self.no_new_privs {
+            write_separator(f, &mut first)?;
+            f.write_str(if nnp {
+                "no_new_privs+"
+            } else {
+                "no_new_privs-"
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents the differences between two [`FileCaps`].
+///
+/// # `Display` implementation
+///
+/// This uses the same format as [`CapStateDiff`'s
+/// implementation](./struct.CapStateDiff.html#display-implementation) for the permitted and
+/// inheritable sets. Changes to the "effective" bit are indicated with `effective+` or
+/// `effective-`, and changes to the root user ID are indicated with `rootid=<uid>` (or
+/// `rootid=none` if it was removed). For example: `cap_net_raw+p effective+ rootid=1000`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FileCapsDiff {
+    /// The new value of the "effective" bit, if it changed.
+    pub effective: Option<bool>,
+    pub permitted: CapSetDiff,
+    pub inheritable: CapSetDiff,
+    /// The new root user ID, if it changed.
+    pub rootid: Option<Option<libc::uid_t>>,
+}
+
+impl FileCapsDiff {
+    /// Compute the differences between `old` and `new`.
+    pub fn between(old: &FileCaps, new: &FileCaps) -> Self {
+        Self {
+            effective: if old.effective != new.effective {
+                Some(new.effective)
+            } else {
+                None
+            },
+            permitted: CapSetDiff::between(old.permitted, new.permitted),
+            inheritable: CapSetDiff::between(old.inheritable, new.inheritable),
+            rootid: if old.rootid != new.rootid {
+                Some(new.rootid)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Check whether the two sets of file capabilities were identical.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.effective.is_none()
+            && self.permitted.is_empty()
+            && self.inheritable.is_empty()
+            && self.rootid.is_none()
+    }
+}
+
+impl fmt::Display for FileCapsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        write_clauses(
+            f,
+            &[('i', self.inheritable), ('p', self.permitted)],
+            &mut first,
+        )?;
+
+        if let Some(effective) = self.effective {
+            write_separator(f, &mut first)?;
+            f.write_str(if effective {
+                "effective+"
+            } else {
+                "effective-"
+            })?;
+        }
+
+        match self.rootid {
+            Some(Some(rootid)) => {
+                write_separator(f, &mut first)?;
+                write!(f, "rootid={}", rootid)?;
+            }
+            Some(None) => {
+                write_separator(f, &mut first)?;
+                f.write_str("rootid=none")?;
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::capset;
+
+    #[test]
+    fn test_capset_diff() {
+        let diff = CapSetDiff::between(
+            capset!(Cap::CHOWN, Cap::KILL),
+            capset!(Cap::KILL, Cap::SETUID),
+        );
+        assert_eq!(diff.added, capset!(Cap::SETUID));
+        assert_eq!(diff.removed, capset!(Cap::CHOWN));
+        assert!(!diff.is_empty());
+
+        assert!(CapSetDiff::between(capset!(Cap::KILL), capset!(Cap::KILL)).is_empty());
+        assert_eq!(
+            CapSetDiff::between(capset!(), capset!()),
+            CapSetDiff::default()
+        );
+    }
+
+    #[test]
+    fn test_capstate_diff() {
+        let old = CapState {
+            effective: capset!(Cap::CHOWN),
+            permitted: capset!(Cap::CHOWN, Cap::KILL),
+            inheritable: capset!(Cap::KILL),
+        };
+
+        let diff = CapStateDiff::between(&old, &old);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "");
+
+        let new = CapState {
+            effective: capset!(Cap::SETUID, Cap::SETGID),
+            permitted: capset!(Cap::CHOWN, Cap::SETUID, Cap::SETGID),
+            inheritable: capset!(),
+        };
+
+        let diff = CapStateDiff::between(&old, &new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.effective.added, capset!(Cap::SETUID, Cap::SETGID));
+        assert_eq!(diff.effective.removed, capset!(Cap::CHOWN));
+        assert_eq!(diff.permitted.removed, capset!(Cap::KILL));
+        assert_eq!(
+            diff.to_string(),
+            "cap_chown-e cap_kill-ip cap_setgid,cap_setuid+ep"
+        );
+
+        let diff = CapStateDiff::between(&new, &old);
+        assert_eq!(
+            diff.to_string(),
+            "cap_chown+e cap_kill+ip cap_setgid,cap_setuid-ep"
+        );
+    }
+
+    #[test]
+    fn test_fullcapstate_diff() {
+        let mut old = FullCapState::empty();
+        old.bounding = capset!(Cap::CHOWN, Cap::NET_RAW, Cap::SYS_ADMIN);
+        old.permitted = capset!(Cap::NET_RAW);
+        old.inheritable = capset!(Cap::NET_RAW);
+
+        assert!(FullCapStateDiff::between(&old, &old).is_empty());
+
+        let mut new = old;
+        new.bounding.drop(Cap::SYS_ADMIN);
+        new.effective.add(Cap::NET_RAW);
+        new.ambient.add(Cap::NET_RAW);
+        new.no_new_privs = true;
+
+        let diff = FullCapStateDiff::between(&old, &new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.ambient.added, capset!(Cap::NET_RAW));
+        assert_eq!(diff.no_new_privs, Some(true));
+        assert_eq!(
+            diff.to_string(),
+            "cap_net_raw+ea cap_sys_admin-b no_new_privs+"
+        );
+
+        let diff = FullCapStateDiff::between(&new, &old);
+        assert_eq!(
+            diff.to_string(),
+            "cap_net_raw-ea cap_sys_admin+b no_new_privs-"
+        );
+
+        new = old;
+        new.no_new_privs = true;
+        assert_eq!(
+            FullCapStateDiff::between(&old, &new).to_string(),
+            "no_new_privs+"
+        );
+    }
+
+    #[test]
+    fn test_filecaps_diff() {
+        let old = FileCaps {
+            effective: false,
+            permitted: capset!(Cap::NET_RAW),
+            inheritable: capset!(),
+            rootid: None,
+        };
+
+        assert!(FileCapsDiff::between(&old, &old).is_empty());
+
+        let mut new = old;
+        new.effective = true;
+        new.permitted.add(Cap::NET_ADMIN);
+        new.inheritable.add(Cap::NET_ADMIN);
+        new.rootid = Some(1000);
+
+        let diff = FileCapsDiff::between(&old, &new);
+        assert_eq!(diff.effective, Some(true));
+        assert_eq!(diff.rootid, Some(Some(1000)));
+        assert_eq!(diff.to_string(), "cap_net_admin+ip effective+ rootid=1000");
+        assert_eq!(
+            FileCapsDiff::between(&new, &old).to_string(),
+            "cap_net_admin-ip effective- rootid=none"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_diff_serde() {
+        use serde_test::{assert_tokens, Token};
+
+        assert_tokens(
+            &CapSetDiff::between(capset!(Cap::CHOWN), capset!()),
+            &[
+                Token::Struct {
+                    name: "CapSetDiff",
+                    len: 2,
+                },
+                Token::Str("added"),
+                Token::Seq { len: Some(0) },
+                Token::SeqEnd,
+                Token::Str("removed"),
+                Token::Seq { len: Some(1) },
+                Token::UnitVariant {
+                    name: "Cap",
+                    variant: "CHOWN",
+                },
+                Token::SeqEnd,
+                Token::StructEnd,
+            ],
+        );
+    }
+}
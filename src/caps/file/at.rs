@@ -0,0 +1,314 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::sys;
+
+use super::{FileCaps, FileCapsRevision};
+
+bitflags::bitflags! {
+    /// Flags for the `*_at()` methods of [`FileCaps`].
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub struct AtFlags: libc::c_int {
+        /// If the last component of the path is a symbolic link, operate on the link itself
+        /// instead of the file it refers to (like `lgetxattr()`).
+        const SYMLINK_NOFOLLOW = libc::AT_SYMLINK_NOFOLLOW;
+        /// If the path is empty, operate on the file referred to by the directory file
+        /// descriptor (which may be any type of file, and may have been opened with `O_PATH`).
+        const EMPTY_PATH = libc::AT_EMPTY_PATH;
+    }
+}
+
+/// Represents the current working directory, for use as the directory file descriptor of the
+/// `*_at()` methods of [`FileCaps`] (like `AT_FDCWD`).
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Cwd;
+
+mod private {
+    pub trait Sealed {}
+
+    impl<T: std::os::unix::io::AsFd> Sealed for T {}
+    impl Sealed for super::Cwd {}
+}
+
+/// A directory file descriptor argument for the `*_at()` methods of [`FileCaps`].
+///
+/// This is implemented for all types that implement [`AsFd`] (for example, `&File` and
+/// [`BorrowedFd`]), and for [`Cwd`]. It is sealed; it cannot be implemented outside this crate.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub trait AsDirFd: private::Sealed {
+    #[doc(hidden)]
+    fn as_raw_dirfd(&self) -> RawFd;
+}
+
+impl<T: AsFd> AsDirFd for T {
+    #[inline]
+    fn as_raw_dirfd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+impl AsDirFd for Cwd {
+    #[inline]
+    fn as_raw_dirfd(&self) -> RawFd {
+        libc::AT_FDCWD
+    }
+}
+
+/// Resolve `path` relative to `dirfd` (honoring `flags`) to an `O_PATH` file descriptor, and call
+/// `f` with a `/proc/self/fd/<fd>` path that refers to it.
+///
+/// The `*xattr()` functions do not follow the "magic" link itself, so this operates on exactly the
+/// file that was opened (even if it is a symbolic link).
+fn with_resolved<T, F: FnOnce(&CStr) -> io::Result<T>>(
+    dirfd: RawFd,
+    path: &OsStr,
+    flags: AtFlags,
+    f: F,
+) -> io::Result<T> {
+    let proc_path = |fd: RawFd| CString::new(format!("/proc/self/fd/{}", fd)).unwrap();
+
+    if path.is_empty() {
+        if !flags.contains(AtFlags::EMPTY_PATH) {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        } else if dirfd != libc::AT_FDCWD {
+            return f(&proc_path(dirfd));
+        }
+    }
+
+    let path = CString::new(if path.is_empty() {
+        b".".as_ref()
+    } else {
+        path.as_bytes()
+    })?;
+
+    let mut oflags = libc::O_PATH | libc::O_CLOEXEC;
+    if flags.contains(AtFlags::SYMLINK_NOFOLLOW) {
+        oflags |= libc::O_NOFOLLOW;
+    }
+
+    let fd = unsafe { libc::openat(dirfd, path.as_ptr(), oflags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    f(&proc_path(fd.as_raw_fd()))
+}
+
+impl FileCaps {
+    /// Get the file capabilities attached to the file identified by `path`, relative to the
+    /// directory `dirfd`.
+    ///
+    /// This is like [`get_for_file()`](#method.get_for_file), except that relative paths are
+    /// resolved relative to `dirfd` (which may be [`Cwd`]) and `flags` can be used to avoid
+    /// following symbolic links or to operate on `dirfd` itself. See [`AtFlags`].
+    ///
+    /// Note: This requires `/proc` to be mounted.
+    #[inline]
+    pub fn get_for_file_at<D: AsDirFd, P: AsRef<OsStr>>(
+        dirfd: D,
+        path: P,
+        flags: AtFlags,
+    ) -> io::Result<Option<Self>> {
+        Ok(Self::get_for_file_at_with_revision(dirfd, path, flags)?.map(|(caps, _)| caps))
+    }
+
+    /// Get the file capabilities attached to the file identified by `path`, relative to the
+    /// directory `dirfd`, along with the revision of the format in which they were stored.
+    ///
+    /// See [`get_for_file_at()`](#method.get_for_file_at) for more information.
+    pub fn get_for_file_at_with_revision<D: AsDirFd, P: AsRef<OsStr>>(
+        dirfd: D,
+        path: P,
+        flags: AtFlags,
+    ) -> io::Result<Option<(Self, FileCapsRevision)>> {
+        with_resolved(dirfd.as_raw_dirfd(), path.as_ref(), flags, |path| {
+            let mut data = [0; sys::XATTR_CAPS_MAX_SIZE];
+
+            let ret = unsafe {
+                libc::getxattr(
+                    path.as_ptr(),
+                    sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
+                    data.as_mut_ptr() as *mut libc::c_void,
+                    data.len(),
+                )
+            };
+
+            Self::extract_attr_or_error(&data, ret)
+        })
+    }
+
+    /// Set the file capabilities attached to the file identified by `path`, relative to the
+    /// directory `dirfd`, to the state represented by this object.
+    ///
+    /// The capabilities are stored in the revision returned by
+    /// [`default_revision()`](#method.default_revision). See
+    /// [`get_for_file_at()`](#method.get_for_file_at) for more information.
+    #[inline]
+    pub fn set_for_file_at<D: AsDirFd, P: AsRef<OsStr>>(
+        &self,
+        dirfd: D,
+        path: P,
+        flags: AtFlags,
+    ) -> io::Result<()> {
+        self.set_for_file_at_as(dirfd, path, flags, self.default_revision())
+    }
+
+    /// Set the file capabilities attached to the file identified by `path`, relative to the
+    /// directory `dirfd`, to the state represented by this object, stored in the given format
+    /// revision.
+    ///
+    /// See [`set_for_file_as()`](#method.set_for_file_as) and
+    /// [`get_for_file_at()`](#method.get_for_file_at) for more information.
+    pub fn set_for_file_at_as<D: AsDirFd, P: AsRef<OsStr>>(
+        &self,
+        dirfd: D,
+        path: P,
+        flags: AtFlags,
+        revision: FileCapsRevision,
+    ) -> io::Result<()> {
+        with_resolved(dirfd.as_raw_dirfd(), path.as_ref(), flags, |path| {
+            let mut buf = [0u8; sys::XATTR_CAPS_MAX_SIZE];
+            let len = self.pack_into(&mut buf, revision)?;
+
+            debug_assert!(len <= buf.len());
+
+            if unsafe {
+                libc::setxattr(
+                    path.as_ptr(),
+                    sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
+                    buf.as_ptr() as *const libc::c_void,
+                    len,
+                    0,
+                )
+            } < 0
+            {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Remove the file capabilities attached to the file identified by `path`, relative to the
+    /// directory `dirfd`.
+    ///
+    /// See [`get_for_file_at()`](#method.get_for_file_at) for more information.
+    pub fn remove_for_file_at<D: AsDirFd, P: AsRef<OsStr>>(
+        dirfd: D,
+        path: P,
+        flags: AtFlags,
+    ) -> io::Result<()> {
+        with_resolved(dirfd.as_raw_dirfd(), path.as_ref(), flags, |path| {
+            if unsafe {
+                libc::removexattr(
+                    path.as_ptr(),
+                    sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
+                )
+            } < 0
+            {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::caps::file::testutil::TempDir;
+    use crate::caps::Cap;
+    use crate::capset;
+
+    #[test]
+    fn test_filecaps_at() {
+        let tmp = TempDir::new("at");
+        let root = tmp.path();
+        fs::write(root.join("file"), b"").unwrap();
+        std::os::unix::fs::symlink("file", root.join("link")).unwrap();
+
+        let dir = fs::File::open(root).unwrap();
+
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::NET_RAW);
+
+        if fcaps
+            .set_for_file_at(&dir, "link", AtFlags::empty())
+            .is_ok()
+        {
+            assert_eq!(
+                FileCaps::get_for_file(root.join("file")).unwrap(),
+                Some(fcaps)
+            );
+            assert_eq!(
+                FileCaps::get_for_file_at(&dir, "link", AtFlags::empty()).unwrap(),
+                Some(fcaps)
+            );
+            assert_eq!(
+                FileCaps::get_for_file_at(Cwd, root.join("link"), AtFlags::empty()).unwrap(),
+                Some(fcaps)
+            );
+
+            // The symlink itself has no capabilities
+            assert_eq!(
+                FileCaps::get_for_file_at(&dir, "link", AtFlags::SYMLINK_NOFOLLOW).unwrap(),
+                None
+            );
+
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(root.join("file"))
+                .unwrap();
+            assert_eq!(
+                FileCaps::get_for_file_at(&file, "", AtFlags::EMPTY_PATH).unwrap(),
+                Some(fcaps)
+            );
+
+            fcaps
+                .set_for_file_at_as(&dir, "file", AtFlags::empty(), FileCapsRevision::V2)
+                .unwrap();
+            assert_eq!(
+                FileCaps::get_for_file_at_with_revision(&file, "", AtFlags::EMPTY_PATH).unwrap(),
+                Some((fcaps, FileCapsRevision::V2))
+            );
+
+            // A root ID cannot be stored in version 2
+            let mut rootid_caps = fcaps;
+            rootid_caps.rootid = Some(0);
+            assert_eq!(
+                rootid_caps
+                    .set_for_file_at_as(&dir, "file", AtFlags::empty(), FileCapsRevision::V2)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EINVAL)
+            );
+
+            FileCaps::remove_for_file_at(&file, "", AtFlags::EMPTY_PATH).unwrap();
+            assert_eq!(
+                FileCaps::get_for_file_at(&dir, "file", AtFlags::SYMLINK_NOFOLLOW).unwrap(),
+                None
+            );
+        }
+
+        assert_eq!(
+            FileCaps::get_for_file_at(&dir, "", AtFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            FileCaps::get_for_file_at(&dir, "nonexistent", AtFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+}
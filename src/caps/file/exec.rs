@@ -0,0 +1,427 @@
+use std::ffi::{CString, OsStr};
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::*;
+
+use super::lint::write_caps;
+use super::FileCaps;
+use crate::caps::{simulate_execve, CapSet, FullCapState};
+use crate::prctl::Secbits;
+
+/// A reason why the file capabilities of an executable will not be (fully) granted when it is
+/// executed, as reported by [`ExecCheck`].
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum ExecBlocker {
+    /// The file is on a filesystem mounted with `nosuid`, so the kernel ignores its file
+    /// capabilities (and its set-user-ID bit).
+    NosuidMount,
+    /// The file has version 3 file capabilities whose root ID is not root in the current user
+    /// namespace, so the kernel ignores them.
+    RootIdNotHonored(libc::uid_t),
+    /// The file has version 3 file capabilities whose root ID is not mapped into the current user
+    /// namespace, so they cannot be read (the kernel fails with `EOVERFLOW`) and the kernel
+    /// ignores them.
+    RootIdNotMapped,
+    /// These capabilities from the file's permitted set are not in the bounding set. If the file's
+    /// effective bit is set, `execve()` will fail with `EPERM`.
+    NotInBounding(CapSet),
+    /// The "no new privileges" flag is set, so these capabilities will not be granted.
+    NoNewPrivs(CapSet),
+}
+
+impl fmt::Display for ExecBlocker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NosuidMount => f.write_str("File is on a nosuid mount"),
+            Self::RootIdNotHonored(rootid) => write!(
+                f,
+                "Root ID {} is not root in the current user namespace",
+                rootid
+            ),
+            Self::RootIdNotMapped => {
+                f.write_str("Root ID is not mapped into the current user namespace")
+            }
+            Self::NotInBounding(caps) => {
+                f.write_str("Capabilities not in bounding set: ")?;
+                write_caps(f, *caps)
+            }
+            Self::NoNewPrivs(caps) => {
+                f.write_str("Capabilities blocked by no_new_privs: ")?;
+                write_caps(f, *caps)
+            }
+        }
+    }
+}
+
+/// The result of checking what would happen if the current thread executed a given file; see
+/// [`FileCaps::check_exec_for_file()`].
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ExecCheck {
+    /// The file capabilities attached to the file (if any).
+    pub fcaps: Option<FileCaps>,
+    /// The capability state the thread would have after executing the file, or `None` if
+    /// `execve()` would fail with `EPERM`.
+    pub state: Option<FullCapState>,
+    /// The reasons why the file capabilities will not be (fully) granted.
+    pub blockers: Vec<ExecBlocker>,
+}
+
+impl ExecCheck {
+    /// Check whether the file has file capabilities and nothing prevents them from being granted.
+    #[inline]
+    pub fn is_honored(&self) -> bool {
+        self.fcaps.is_some() && self.blockers.is_empty()
+    }
+
+    fn evaluate(
+        fcaps: Option<FileCaps>,
+        st: &libc::stat,
+        nosuid: bool,
+        state: &FullCapState,
+        secbits: Secbits,
+        ruid: libc::uid_t,
+        euid: libc::uid_t,
+    ) -> Self {
+        let mut blockers = Vec::new();
+
+        let honored = match fcaps {
+            Some(_) if nosuid => {
+                blockers.push(ExecBlocker::NosuidMount);
+                None
+            }
+            // The kernel has already translated the root ID into our namespace, so it will only
+            // be honored if it's 0
+            Some(FileCaps {
+                rootid: Some(rootid),
+                ..
+            }) if rootid != 0 => {
+                blockers.push(ExecBlocker::RootIdNotHonored(rootid));
+                None
+            }
+            fcaps => fcaps,
+        };
+
+        if let Some(fcaps) = honored {
+            let missing = fcaps.permitted - state.bounding;
+            if !missing.is_empty() {
+                blockers.push(ExecBlocker::NotInBounding(missing));
+            }
+        }
+
+        let setuid_root = !nosuid && st.st_mode & libc::S_ISUID == libc::S_ISUID && st.st_uid == 0;
+
+        let new_state =
+            simulate_execve(state, honored.as_ref(), secbits, ruid, euid, setuid_root).ok();
+
+        if state.no_new_privs {
+            let unrestricted = FullCapState {
+                no_new_privs: false,
+                ..*state
+            };
+
+            if let (Some(new_state), Ok(unrestricted)) = (
+                new_state,
+                simulate_execve(
+                    &unrestricted,
+                    honored.as_ref(),
+                    secbits,
+                    ruid,
+                    euid,
+                    setuid_root,
+                ),
+            ) {
+                let lost = unrestricted.permitted - new_state.permitted;
+                if !lost.is_empty() {
+                    blockers.push(ExecBlocker::NoNewPrivs(lost));
+                }
+            }
+        }
+
+        Self {
+            fcaps,
+            state: new_state,
+            blockers,
+        }
+    }
+
+    fn for_current(
+        fcaps: io::Result<Option<FileCaps>>,
+        st: &libc::stat,
+        vfs: &libc::statvfs,
+    ) -> io::Result<Self> {
+        let mut root_id_not_mapped = false;
+        let fcaps = match fcaps {
+            // The filesystem doesn't support extended attributes
+            Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => None,
+            // Version 3 file capabilities whose root ID isn't mapped into our user namespace
+            Err(e) if e.raw_os_error() == Some(libc::EOVERFLOW) => {
+                root_id_not_mapped = true;
+                None
+            }
+            res => res?,
+        };
+
+        let state = FullCapState::get_current()?;
+        let secbits = crate::prctl::get_securebits()?;
+
+        let mut ruid = 0;
+        let mut euid = 0;
+        let mut suid = 0;
+        if unsafe { libc::getresuid(&mut ruid, &mut euid, &mut suid) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut check = Self::evaluate(
+            fcaps,
+            st,
+            vfs.f_flag & libc::ST_NOSUID == libc::ST_NOSUID,
+            &state,
+            secbits,
+            ruid,
+            euid,
+        );
+        if root_id_not_mapped {
+            check.blockers.push(ExecBlocker::RootIdNotMapped);
+        }
+
+        Ok(check)
+    }
+}
+
+impl FileCaps {
+    /// Check whether the file capabilities attached to the file identified by `path` would be
+    /// granted if the current thread executed it.
+    ///
+    /// This examines the file's mount flags (`nosuid`), the root ID of version 3 file
+    /// capabilities, the current thread's "no new privileges" flag and bounding set, and then uses
+    /// [`simulate_execve()`] to compute the resulting capability state.
+    ///
+    /// Note: This does not check whether the file is actually executable, and it does not account
+    /// for LSMs (such as SELinux or AppArmor) or for `ptrace()`.
+    pub fn check_exec_for_file<P: AsRef<OsStr>>(path: P) -> io::Result<ExecCheck> {
+        let cpath = CString::new(path.as_ref().as_bytes())?;
+
+        let mut st = unsafe { core::mem::zeroed() };
+        if unsafe { libc::stat(cpath.as_ptr(), &mut st) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut vfs = unsafe { core::mem::zeroed() };
+        if unsafe { libc::statvfs(cpath.as_ptr(), &mut vfs) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        ExecCheck::for_current(Self::get_for_file(path), &st, &vfs)
+    }
+
+    /// Check whether the file capabilities attached to the open file identified by the file
+    /// descriptor `fd` would be granted if the current thread executed it.
+    ///
+    /// See [`check_exec_for_file()`](#method.check_exec_for_file) for more information.
+    pub fn check_exec_for_fd<F: AsFd>(fd: F) -> io::Result<ExecCheck> {
+        let fd = fd.as_fd();
+
+        let mut st = unsafe { core::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut st) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut vfs = unsafe { core::mem::zeroed() };
+        if unsafe { libc::fstatvfs(fd.as_raw_fd(), &mut vfs) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        ExecCheck::for_current(Self::get_for_fd(fd), &st, &vfs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::caps::file::testutil::TempDir;
+    use crate::caps::{bounding, Cap};
+    use crate::capset;
+
+    #[test]
+    fn test_exec_check_evaluate() {
+        let st: libc::stat = unsafe { core::mem::zeroed() };
+
+        let mut state = FullCapState::empty();
+        state.bounding = capset!(Cap::NET_RAW, Cap::CHOWN);
+
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::NET_RAW);
+        fcaps.effective = true;
+
+        let check = ExecCheck::evaluate(
+            Some(fcaps),
+            &st,
+            false,
+            &state,
+            Secbits::empty(),
+            1000,
+            1000,
+        );
+        assert!(check.is_honored());
+        assert_eq!(check.state.unwrap().effective, capset!(Cap::NET_RAW));
+
+        // nosuid
+        let check =
+            ExecCheck::evaluate(Some(fcaps), &st, true, &state, Secbits::empty(), 1000, 1000);
+        assert!(!check.is_honored());
+        assert_eq!(check.blockers, vec![ExecBlocker::NosuidMount]);
+        assert_eq!(check.state.unwrap().permitted, capset!());
+
+        // Root ID
+        let mut v3 = fcaps;
+        v3.rootid = Some(100000);
+        let check = ExecCheck::evaluate(Some(v3), &st, false, &state, Secbits::empty(), 1000, 1000);
+        assert_eq!(check.blockers, vec![ExecBlocker::RootIdNotHonored(100000)]);
+        assert_eq!(
+            check.blockers[0].to_string(),
+            "Root ID 100000 is not root in the current user namespace"
+        );
+
+        // Bounding set
+        fcaps.permitted.add(Cap::SYSLOG);
+        let check = ExecCheck::evaluate(
+            Some(fcaps),
+            &st,
+            false,
+            &state,
+            Secbits::empty(),
+            1000,
+            1000,
+        );
+        assert_eq!(
+            check.blockers,
+            vec![ExecBlocker::NotInBounding(capset!(Cap::SYSLOG))]
+        );
+        assert_eq!(check.state, None);
+
+        fcaps.effective = false;
+        let check = ExecCheck::evaluate(
+            Some(fcaps),
+            &st,
+            false,
+            &state,
+            Secbits::empty(),
+            1000,
+            1000,
+        );
+        assert_eq!(check.state.unwrap().permitted, capset!(Cap::NET_RAW));
+
+        // no_new_privs
+        state.no_new_privs = true;
+        state.permitted = capset!(Cap::CHOWN);
+        fcaps.permitted = capset!(Cap::NET_RAW, Cap::CHOWN);
+        let check = ExecCheck::evaluate(
+            Some(fcaps),
+            &st,
+            false,
+            &state,
+            Secbits::empty(),
+            1000,
+            1000,
+        );
+        assert_eq!(
+            check.blockers,
+            vec![ExecBlocker::NoNewPrivs(capset!(Cap::NET_RAW))]
+        );
+        assert_eq!(check.state.unwrap().permitted, capset!(Cap::CHOWN));
+
+        // No file capabilities
+        let check = ExecCheck::evaluate(None, &st, true, &state, Secbits::empty(), 1000, 1000);
+        assert!(!check.is_honored());
+        assert_eq!(check.blockers, vec![]);
+    }
+
+    #[test]
+    fn test_exec_check_root_id_not_mapped() {
+        let st: libc::stat = unsafe { core::mem::zeroed() };
+        let vfs: libc::statvfs = unsafe { core::mem::zeroed() };
+
+        let check = ExecCheck::for_current(
+            Err(io::Error::from_raw_os_error(libc::EOVERFLOW)),
+            &st,
+            &vfs,
+        )
+        .unwrap();
+        assert_eq!(check.fcaps, None);
+        assert!(!check.is_honored());
+        assert_eq!(check.blockers, vec![ExecBlocker::RootIdNotMapped]);
+        assert_eq!(
+            check.blockers[0].to_string(),
+            "Root ID is not mapped into the current user namespace"
+        );
+
+        assert_eq!(
+            ExecCheck::for_current(Err(io::Error::from_raw_os_error(libc::EIO)), &st, &vfs)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EIO)
+        );
+    }
+
+    #[test]
+    fn test_check_exec() {
+        let tmp = TempDir::new("check-exec");
+        let path = tmp.path().join("file");
+        fs::write(&path, b"").unwrap();
+
+        let check = FileCaps::check_exec_for_file(&path).unwrap();
+        assert_eq!(check.fcaps, None);
+        assert!(!check.is_honored());
+
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::NET_RAW);
+        fcaps.effective = true;
+
+        if fcaps.set_for_file(&path).is_ok() && bounding::read(Cap::NET_RAW) == Some(true) {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let check = FileCaps::check_exec_for_file(&path).unwrap();
+                assert_eq!(check.fcaps, Some(fcaps));
+                if check.blockers != [ExecBlocker::NosuidMount] {
+                    assert!(check.is_honored());
+                    assert!(check.state.unwrap().effective.has(Cap::NET_RAW));
+
+                    if crate::caps::CapState::get_current()
+                        .unwrap()
+                        .permitted
+                        .has(Cap::SETPCAP)
+                    {
+                        crate::command::try_raise_effective(Cap::SETPCAP).unwrap();
+                        bounding::drop(Cap::NET_RAW).unwrap();
+
+                        let file = fs::File::open(&path).unwrap();
+                        let check = FileCaps::check_exec_for_fd(&file).unwrap();
+                        assert_eq!(
+                            check.blockers,
+                            vec![ExecBlocker::NotInBounding(capset!(Cap::NET_RAW))]
+                        );
+                        assert_eq!(check.state, None);
+                    }
+                }
+            })
+            .join()
+            .unwrap();
+        }
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            FileCaps::check_exec_for_file(&path)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+}
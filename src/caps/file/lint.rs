@@ -0,0 +1,218 @@
+use std::fmt;
+
+use super::{FileCaps, UidMap};
+use crate::caps::{bounding, Cap, CapSet};
+
+/// A potential problem with a set of file capabilities, as reported by [`FileCaps::lint()`].
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum FileCapsWarning {
+    /// The effective bit is set, but the permitted set is empty.
+    ///
+    /// The effective bit only has an effect on capabilities gained through the file's inheritable
+    /// set, which is rarely intended.
+    EffectiveWithoutPermitted,
+    /// The permitted set contains capabilities that are not in the bounding set, so they will not
+    /// be granted on `execve()`.
+    ///
+    /// If `exec_fails` is `true` (because the effective bit is set), the kernel treats the program
+    /// as "capability-dumb" and `execve()` fails with `EPERM` instead of running the program with
+    /// fewer capabilities.
+    NotInBounding {
+        /// The capabilities that are missing from the bounding set.
+        caps: CapSet,
+        /// Whether `execve()` will fail with `EPERM`.
+        exec_fails: bool,
+    },
+    /// The permitted or inheritable sets contain capabilities that the running kernel does not
+    /// support (see [`Cap::probe_supported()`]).
+    Unsupported(CapSet),
+    /// The root ID (of version 3 file capabilities) is not the host UID that is mapped to root in
+    /// the user namespace, so the file capabilities will not be honored there (see
+    /// [`FileCaps::is_honored_in()`]).
+    UnmappedRootId(libc::uid_t),
+}
+
+impl FileCapsWarning {
+    /// Check whether this warning indicates that `execve()` will fail (instead of merely granting
+    /// fewer capabilities than requested).
+    #[inline]
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Self::NotInBounding {
+                exec_fails: true,
+                ..
+            }
+        )
+    }
+}
+
+/// Write a comma-separated list of capabilities.
+pub(super) fn write_caps(f: &mut fmt::Formatter, caps: CapSet) -> fmt::Result {
+    for (i, cap) in caps.iter().enumerate() {
+        if i != 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{}", cap)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for FileCapsWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::EffectiveWithoutPermitted => {
+                f.write_str("Effective bit is set, but permitted set is empty")
+            }
+            Self::NotInBounding { caps, exec_fails } => {
+                f.write_str("Permitted capabilities not in bounding set: ")?;
+                write_caps(f, *caps)?;
+                if *exec_fails {
+                    f.write_str(" (execve() will fail with EPERM)")?;
+                }
+                Ok(())
+            }
+            Self::Unsupported(caps) => {
+                f.write_str("Capabilities not supported by the kernel: ")?;
+                write_caps(f, *caps)
+            }
+            Self::UnmappedRootId(rootid) => write!(f, "Root ID {} is not mapped to root", rootid),
+        }
+    }
+}
+
+impl FileCaps {
+    /// Check these file capabilities for common mistakes that would prevent them from working as
+    /// intended on this system.
+    ///
+    /// This checks them against the current thread's bounding set, the capabilities supported by
+    /// the running kernel, and the UID map of the current user namespace. See
+    /// [`lint_with()`](#method.lint_with) to use a different bounding set or UID map (for example,
+    /// that of the environment in which the program will actually be run).
+    ///
+    /// An empty list means no problems were found.
+    pub fn lint(&self) -> Vec<FileCapsWarning> {
+        let map = UidMap::get_for_pid(0).ok();
+        self.lint_with(bounding::probe(), map.as_ref())
+    }
+
+    /// Check these file capabilities for common mistakes, using the given bounding set and
+    /// (optionally) the UID map of the user namespace in which the program will be run.
+    ///
+    /// The capabilities supported by the running kernel are always checked.
+    pub fn lint_with(&self, bounding: CapSet, map: Option<&UidMap>) -> Vec<FileCapsWarning> {
+        let mut warnings = Vec::new();
+
+        if self.effective && self.permitted.is_empty() {
+            warnings.push(FileCapsWarning::EffectiveWithoutPermitted);
+        }
+
+        let supported = Cap::probe_supported();
+
+        let unsupported = (self.permitted | self.inheritable) - supported;
+        if !unsupported.is_empty() {
+            warnings.push(FileCapsWarning::Unsupported(unsupported));
+        }
+
+        // Unsupported capabilities are never in the bounding set; they've already been reported
+        let missing = (self.permitted & supported) - bounding;
+        if !missing.is_empty() {
+            warnings.push(FileCapsWarning::NotInBounding {
+                caps: missing,
+                exec_fails: self.effective,
+            });
+        }
+
+        if let (Some(rootid), Some(map)) = (self.rootid, map) {
+            if !self.is_honored_in(map) {
+                warnings.push(FileCapsWarning::UnmappedRootId(rootid));
+            }
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::capset;
+
+    #[test]
+    fn test_lint() {
+        let supported = Cap::probe_supported();
+
+        let mut fcaps = FileCaps::empty();
+        assert_eq!(fcaps.lint_with(CapSet::empty(), None), vec![]);
+
+        fcaps.effective = true;
+        assert_eq!(
+            fcaps.lint_with(supported, None),
+            vec![FileCapsWarning::EffectiveWithoutPermitted]
+        );
+
+        fcaps.permitted = capset!(Cap::NET_RAW, Cap::CHOWN);
+        assert_eq!(fcaps.lint_with(supported, None), vec![]);
+
+        let warnings = fcaps.lint_with(capset!(Cap::CHOWN), None);
+        assert_eq!(
+            warnings,
+            vec![FileCapsWarning::NotInBounding {
+                caps: capset!(Cap::NET_RAW),
+                exec_fails: true,
+            }]
+        );
+        assert!(warnings[0].is_fatal());
+        assert_eq!(
+            warnings[0].to_string(),
+            "Permitted capabilities not in bounding set: CAP_NET_RAW (execve() will fail with EPERM)"
+        );
+
+        fcaps.effective = false;
+        let warnings = fcaps.lint_with(capset!(), None);
+        assert_eq!(
+            warnings,
+            vec![FileCapsWarning::NotInBounding {
+                caps: capset!(Cap::NET_RAW, Cap::CHOWN),
+                exec_fails: false,
+            }]
+        );
+        assert!(!warnings[0].is_fatal());
+
+        // Capabilities the kernel doesn't support
+        if let Some(cap) = (!supported).iter().next() {
+            fcaps.inheritable = capset!(cap);
+            assert_eq!(
+                fcaps.lint_with(supported, None),
+                vec![FileCapsWarning::Unsupported(capset!(cap))]
+            );
+            fcaps.inheritable = capset!();
+        }
+
+        let map = UidMap::parse("0 100000 65536").unwrap();
+        fcaps.rootid = Some(100000);
+        assert_eq!(fcaps.lint_with(supported, Some(&map)), vec![]);
+        fcaps.rootid = Some(1000);
+        let warnings = fcaps.lint_with(supported, Some(&map));
+        assert_eq!(warnings, vec![FileCapsWarning::UnmappedRootId(1000)]);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Root ID 1000 is not mapped to root"
+        );
+        // Mapped into the namespace, but not to root
+        fcaps.rootid = Some(100001);
+        assert_eq!(
+            fcaps.lint_with(supported, Some(&map)),
+            vec![FileCapsWarning::UnmappedRootId(100001)]
+        );
+        fcaps.rootid = Some(0);
+        assert_eq!(fcaps.lint_with(supported, Some(&map)), vec![]);
+        assert_eq!(fcaps.lint_with(supported, None), vec![]);
+
+        // Just make sure it works
+        FileCaps::empty().lint();
+    }
+}
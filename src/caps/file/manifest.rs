@@ -0,0 +1,771 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::{CString, OsStr, OsString};
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use super::{FileCaps, FileCapsScanner, ParseFileCapsError, ScanError};
+
+/// A list of files and the file capabilities that should be attached to them.
+///
+/// # Text format
+///
+/// The `FromStr` and `Display` implementations use the format of `getcap` output: one file per
+/// line, consisting of the path, whitespace, and the capabilities in the format of
+/// `cap_to_text()` (see [`FileCaps`]). For example:
+///
+/// ```text
+/// /usr/bin/ping cap_net_raw=ep
+/// /usr/bin/dumpcap cap_dac_override,cap_net_admin,cap_net_raw=eip
+/// ```
+///
+/// Blank lines and lines starting with `#` are ignored, and the `path = caps` format used by older
+/// versions of `getcap` is also accepted. Whitespace, backslashes, control characters, and
+/// invalid UTF-8 in paths are written as octal escapes (for example, `\040` for a space), as in
+/// `/proc/mounts`.
+///
+/// Paths are always stored as absolute paths; relative paths are interpreted relative to `/`.
+/// When a manifest is applied to (or captured from) a directory tree, the paths are interpreted
+/// relative to the root of that tree.
+///
+/// Note: The text format cannot represent the root ID of version 3 file capabilities, so it is
+/// always cleared (and ignored when comparing capabilities).
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FileCapsManifest {
+    entries: BTreeMap<PathBuf, FileCaps>,
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    Path::new("/").join(path)
+}
+
+fn same_caps(a: &FileCaps, b: &FileCaps) -> bool {
+    a.effective == b.effective && a.permitted == b.permitted && a.inheritable == b.inheritable
+}
+
+/// Compare the `expected` capabilities of the file at `path` against the `actual` ones, adding
+/// any difference to `drift`.
+fn check_entry(
+    drift: &mut Vec<ManifestDrift>,
+    path: &Path,
+    expected: &FileCaps,
+    actual: Option<&FileCaps>,
+) {
+    match actual {
+        None => drift.push(ManifestDrift::Missing {
+            path: path.to_path_buf(),
+            expected: *expected,
+        }),
+        Some(actual) if !same_caps(expected, actual) => drift.push(ManifestDrift::Different {
+            path: path.to_path_buf(),
+            expected: *expected,
+            actual: *actual,
+        }),
+        Some(_) => (),
+    }
+}
+
+/// Get the full path of the file identified by `path` (interpreted relative to `root`).
+fn full_path(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Open the file at `name` relative to the directory `dirfd`, without following symbolic links.
+///
+/// Fails with `ELOOP` if it is a symbolic link, or with `ENOTDIR` if `dir` is `true` and it is not
+/// a directory.
+fn open_nofollow(dirfd: RawFd, name: &OsStr, dir: bool) -> io::Result<fs::File> {
+    let name = CString::new(name.as_bytes())?;
+
+    let flags = if dir {
+        // Only search permission is needed to traverse the directory
+        libc::O_PATH
+    } else {
+        libc::O_RDONLY | libc::O_NOCTTY | libc::O_NONBLOCK
+    };
+
+    let fd = unsafe {
+        libc::openat(
+            dirfd,
+            name.as_ptr(),
+            flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { fs::File::from_raw_fd(fd) };
+
+    if dir {
+        // With O_PATH, O_NOFOLLOW opens the symbolic link itself
+        let file_type = file.metadata()?.file_type();
+        if file_type.is_symlink() {
+            return Err(io::Error::from_raw_os_error(libc::ELOOP));
+        } else if !file_type.is_dir() {
+            return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+        }
+    }
+
+    Ok(file)
+}
+
+/// Open the file identified by `path` (interpreted relative to `root`).
+///
+/// The path is resolved one component at a time, without following symbolic links in any of
+/// them, so the file is always inside `root` (even if `root` contains absolute symbolic links).
+fn open_in(root: &Path, path: &Path) -> Result<fs::File, ScanError> {
+    let full = full_path(root, path);
+
+    let names = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(Ok(name)),
+            Component::RootDir | Component::CurDir => None,
+            Component::Prefix(_) | Component::ParentDir => Some(Err(())),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ScanError::new(full.clone(), io::Error::from_raw_os_error(libc::EINVAL)))?;
+
+    let (last, dirs) = match names.split_last() {
+        Some(split) => split,
+        None => {
+            return Err(ScanError::new(
+                full,
+                io::Error::from_raw_os_error(libc::EINVAL),
+            ))
+        }
+    };
+
+    let res = fs::OpenOptions::new()
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .read(true)
+        .open(root)
+        .and_then(|mut dir| {
+            for name in dirs {
+                dir = open_nofollow(dir.as_raw_fd(), name, true)?;
+            }
+            open_nofollow(dir.as_raw_fd(), last, false)
+        });
+
+    res.map_err(|e| ScanError::new(full, e))
+}
+
+impl FileCapsManifest {
+    /// Create an empty manifest.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry to the manifest, returning the previous capabilities for `path` (if any).
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, mut fcaps: FileCaps) -> Option<FileCaps> {
+        fcaps.rootid = None;
+        self.entries.insert(normalize(path.as_ref()), fcaps)
+    }
+
+    /// Remove the entry for `path`, returning its capabilities (if it was present).
+    #[inline]
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<FileCaps> {
+        self.entries.remove(&normalize(path.as_ref()))
+    }
+
+    /// Get the capabilities listed for `path`.
+    #[inline]
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&FileCaps> {
+        self.entries.get(&normalize(path.as_ref()))
+    }
+
+    /// Iterate over the entries in the manifest, sorted by path.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &FileCaps)> {
+        self.entries
+            .iter()
+            .map(|(path, fcaps)| (path.as_path(), fcaps))
+    }
+
+    /// Get the number of entries in the manifest.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the manifest is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Build a manifest of all the files with file capabilities in the directory tree at `root`.
+    ///
+    /// This uses [`FileCapsScanner`] with the default options (so symbolic links are not followed
+    /// and other mount points are not crossed). The first error encountered is returned.
+    pub fn capture<P: AsRef<Path>>(root: P) -> Result<Self, ScanError> {
+        let root = root.as_ref();
+
+        let scanner =
+            FileCapsScanner::new(root).map_err(|e| ScanError::new(root.to_path_buf(), e))?;
+
+        let mut manifest = Self::new();
+        for res in scanner {
+            let (path, fcaps) = res?;
+            manifest.insert(path.strip_prefix(root).unwrap_or(&path), fcaps);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Set the capabilities of every file listed in the manifest, interpreting the paths relative
+    /// to `root`.
+    ///
+    /// All of the files are opened (and their current capabilities are recorded) before any
+    /// changes are made, so a missing file does not cause a partial update. If setting the
+    /// capabilities of one of the files fails, the files that were already changed are restored
+    /// to their previous state (as far as possible) before the error is returned.
+    ///
+    /// Symbolic links are not followed in any component of each path (so files outside `root`
+    /// are never changed; this fails with `ELOOP` instead), and paths containing `..` are rejected
+    /// with `EINVAL`. Files that are not listed in the manifest are not touched.
+    pub fn apply<P: AsRef<Path>>(&self, root: P) -> Result<(), ScanError> {
+        let root = root.as_ref();
+
+        let mut files = Vec::with_capacity(self.entries.len());
+        for (path, fcaps) in self.entries.iter() {
+            let file = open_in(root, path)?;
+            let old = match FileCaps::get_for_fd(&file) {
+                Ok(old) => old,
+                // The filesystem doesn't support extended attributes; setting them will fail below
+                Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => None,
+                Err(e) => return Err(ScanError::new(full_path(root, path), e)),
+            };
+            files.push((path, file, fcaps, old));
+        }
+
+        for (i, (path, file, fcaps, _)) in files.iter().enumerate() {
+            if let Err(e) = fcaps.set_for_fd(file) {
+                for (_, file, _, old) in files[..i].iter().rev() {
+                    let _ = match old {
+                        Some(old) => old.set_for_fd(file),
+                        None => FileCaps::remove_for_fd(file),
+                    };
+                }
+
+                return Err(ScanError::new(full_path(root, path), e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compare the manifest against the file capabilities actually present in the directory tree
+    /// at `root`.
+    ///
+    /// Each file listed in the manifest is opened the same way as in [`apply()`](#method.apply)
+    /// and checked directly; files that do not exist are reported as
+    /// [`Missing`](ManifestDrift::Missing). The tree is then scanned as in
+    /// [`capture()`](#method.capture) to find files with file capabilities that are not listed.
+    ///
+    /// An empty list means that the tree matches the manifest exactly. The differences are
+    /// returned sorted by path.
+    pub fn verify<P: AsRef<Path>>(&self, root: P) -> Result<Vec<ManifestDrift>, ScanError> {
+        let root = root.as_ref();
+
+        let mut drift = Vec::new();
+
+        for (path, expected) in self.entries.iter() {
+            let actual = match open_in(root, path) {
+                Ok(file) => match FileCaps::get_for_fd(&file) {
+                    Ok(actual) => actual.map(|mut actual| {
+                        actual.rootid = None;
+                        actual
+                    }),
+                    Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => None,
+                    Err(e) => return Err(ScanError::new(full_path(root, path), e)),
+                },
+                Err(e)
+                    if matches!(e.error().raw_os_error(), Some(libc::ENOENT | libc::ENOTDIR)) =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+
+            check_entry(&mut drift, path, expected, actual.as_ref());
+        }
+
+        for (path, actual) in Self::capture(root)?.entries {
+            if !self.entries.contains_key(&path) {
+                drift.push(ManifestDrift::Extra { path, actual });
+            }
+        }
+
+        drift.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(drift)
+    }
+
+    /// Compare this manifest (the expected state) against `actual`.
+    ///
+    /// The differences are returned sorted by path.
+    pub fn diff(&self, actual: &Self) -> Vec<ManifestDrift> {
+        let mut drift = Vec::new();
+
+        for (path, expected) in self.entries.iter() {
+            check_entry(&mut drift, path, expected, actual.entries.get(path));
+        }
+
+        for (path, actual) in actual.entries.iter() {
+            if !self.entries.contains_key(path) {
+                drift.push(ManifestDrift::Extra {
+                    path: path.clone(),
+                    actual: *actual,
+                });
+            }
+        }
+
+        drift.sort_by(|a, b| a.path().cmp(b.path()));
+        drift
+    }
+}
+
+/// A difference between a [`FileCapsManifest`] and the actual state of a directory tree, as
+/// reported by [`FileCapsManifest::verify()`].
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum ManifestDrift {
+    /// The file is listed in the manifest, but it has no file capabilities (or does not exist).
+    Missing { path: PathBuf, expected: FileCaps },
+    /// The file has file capabilities, but it is not listed in the manifest.
+    Extra { path: PathBuf, actual: FileCaps },
+    /// The file has different file capabilities than those listed in the manifest.
+    Different {
+        path: PathBuf,
+        expected: FileCaps,
+        actual: FileCaps,
+    },
+}
+
+impl ManifestDrift {
+    /// Get the path of the file (as listed in the manifest).
+    #[inline]
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Missing { path, .. }
+            | Self::Extra { path, .. }
+            | Self::Different { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for ManifestDrift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing { path, expected } => {
+                write!(f, "{}: missing (expected {})", path.display(), expected)
+            }
+            Self::Extra { path, actual } => {
+                write!(f, "{}: unexpected {}", path.display(), actual)
+            }
+            Self::Different {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: expected {}, found {}",
+                path.display(),
+                expected,
+                actual
+            ),
+        }
+    }
+}
+
+fn write_escaped(path: &Path, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut bytes = path.as_os_str().as_bytes();
+
+    while !bytes.is_empty() {
+        let valid_len = match std::str::from_utf8(bytes) {
+            Ok(_) => bytes.len(),
+            Err(e) => e.valid_up_to(),
+        };
+
+        for ch in std::str::from_utf8(&bytes[..valid_len]).unwrap().chars() {
+            if ch.is_whitespace() || ch.is_control() || ch == '\\' {
+                let mut buf = [0; 4];
+                for &b in ch.encode_utf8(&mut buf).as_bytes() {
+                    write!(f, "\\{:03o}", b)?;
+                }
+            } else {
+                f.write_char(ch)?;
+            }
+        }
+
+        // Escape one invalid byte
+        if let Some(&b) = bytes.get(valid_len) {
+            write!(f, "\\{:03o}", b)?;
+            bytes = &bytes[valid_len + 1..];
+        } else {
+            bytes = &[];
+        }
+    }
+
+    Ok(())
+}
+
+fn unescape(s: &str) -> Option<PathBuf> {
+    let mut res = Vec::with_capacity(s.len());
+
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'\\' {
+            let mut val = 0u32;
+            for _ in 0..3 {
+                val = val * 8 + (bytes.next()? as char).to_digit(8)?;
+            }
+            res.push(u8::try_from(val).ok()?);
+        } else {
+            res.push(b);
+        }
+    }
+
+    Some(OsString::from_vec(res).into())
+}
+
+impl fmt::Display for FileCapsManifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, fcaps) in self.entries.iter() {
+            write_escaped(path, f)?;
+            writeln!(f, " {}", fcaps)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::str::FromStr for FileCapsManifest {
+    type Err = ParseManifestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut manifest = Self::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let err = |kind| ParseManifestError { line: i + 1, kind };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (path, caps) = match line.find(char::is_whitespace) {
+                Some(idx) => (&line[..idx], line[idx..].trim_start()),
+                None => return Err(err(ManifestErrorKind::MissingCaps)),
+            };
+            // Older versions of getcap print "path = caps"
+            let caps = match caps.strip_prefix('=') {
+                Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+                _ => caps,
+            };
+
+            let path = unescape(path).ok_or_else(|| err(ManifestErrorKind::BadPath))?;
+            let fcaps = caps.parse().map_err(|e| err(ManifestErrorKind::Caps(e)))?;
+
+            if manifest.insert(path, fcaps).is_some() {
+                return Err(err(ManifestErrorKind::Duplicate));
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum ManifestErrorKind {
+    MissingCaps,
+    BadPath,
+    Duplicate,
+    Caps(ParseFileCapsError),
+}
+
+/// Represents an error when parsing a [`FileCapsManifest`] from a string.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ParseManifestError {
+    line: usize,
+    kind: ManifestErrorKind,
+}
+
+impl ParseManifestError {
+    /// Get the (1-based) number of the line on which the error occurred.
+    #[inline]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+
+        match &self.kind {
+            ManifestErrorKind::MissingCaps => f.write_str("Missing capabilities"),
+            ManifestErrorKind::BadPath => f.write_str("Invalid escape sequence in path"),
+            ManifestErrorKind::Duplicate => f.write_str("Duplicate path"),
+            ManifestErrorKind::Caps(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl std::error::Error for ParseManifestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ManifestErrorKind::Caps(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::file::testutil::TempDir;
+
+    fn fcaps(s: &str) -> FileCaps {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_manifest_parse() {
+        let manifest: FileCapsManifest = "
+# Comment
+/usr/bin/ping cap_net_raw=ep
+usr/bin/dumpcap = cap_dac_override,cap_net_admin,cap_net_raw=eip
+/opt/my\\040app\\134x cap_chown+p cap_syslog+i
+"
+        .parse()
+        .unwrap();
+
+        assert_eq!(manifest.len(), 3);
+        assert_eq!(
+            manifest.get("/usr/bin/ping"),
+            Some(&fcaps("cap_net_raw=ep"))
+        );
+        assert_eq!(
+            manifest.get("usr/bin/dumpcap"),
+            Some(&fcaps("cap_dac_override,cap_net_admin,cap_net_raw=eip"))
+        );
+        assert_eq!(
+            manifest.get("/opt/my app\\x"),
+            Some(&fcaps("cap_chown+p cap_syslog+i"))
+        );
+
+        // Round-trip
+        let text = manifest.to_string();
+        assert!(text.contains("/opt/my\\040app\\134x "), "{:?}", text);
+        assert_eq!(text.parse::<FileCapsManifest>().unwrap(), manifest);
+
+        let mut manifest = FileCapsManifest::new();
+        manifest.insert(
+            Path::new(OsStr::from_bytes(b"/a\xff\tb")),
+            fcaps("cap_chown=p"),
+        );
+        assert_eq!(manifest.to_string(), "/a\\377\\011b cap_chown=p\n");
+        assert_eq!(
+            manifest.to_string().parse::<FileCapsManifest>().unwrap(),
+            manifest
+        );
+
+        for (text, line, msg) in [
+            ("/a cap_chown=p\n/b", 2, "Line 2: Missing capabilities"),
+            (
+                "/a\\09 cap_chown=p",
+                1,
+                "Line 1: Invalid escape sequence in path",
+            ),
+            (
+                "/a cap_chown=p\n\n/a cap_chown=p",
+                3,
+                "Line 3: Duplicate path",
+            ),
+            ("/a cap_noexist=p", 1, "Line 1: Unknown capability"),
+        ]
+        .iter()
+        {
+            let err = text.parse::<FileCapsManifest>().unwrap_err();
+            assert_eq!(err.line(), *line);
+            assert_eq!(err.to_string(), *msg);
+        }
+    }
+
+    #[test]
+    fn test_manifest_diff() {
+        let expected: FileCapsManifest = "/a cap_chown=p\n/b cap_net_raw=ep\n/c cap_syslog=i\n"
+            .parse()
+            .unwrap();
+        let actual: FileCapsManifest = "/b cap_net_raw=p\n/c cap_syslog=i\n/d cap_kill=p\n"
+            .parse()
+            .unwrap();
+
+        let drift = expected.diff(&actual);
+        assert_eq!(
+            drift,
+            vec![
+                ManifestDrift::Missing {
+                    path: "/a".into(),
+                    expected: fcaps("cap_chown=p"),
+                },
+                ManifestDrift::Different {
+                    path: "/b".into(),
+                    expected: fcaps("cap_net_raw=ep"),
+                    actual: fcaps("cap_net_raw=p"),
+                },
+                ManifestDrift::Extra {
+                    path: "/d".into(),
+                    actual: fcaps("cap_kill=p"),
+                },
+            ]
+        );
+        assert_eq!(
+            drift[1].to_string(),
+            "/b: expected cap_net_raw=ep, found cap_net_raw=p"
+        );
+
+        assert_eq!(expected.diff(&expected), vec![]);
+    }
+
+    #[test]
+    fn test_manifest_apply_verify() {
+        let tmp = TempDir::new("manifest-apply");
+        let root = tmp.path();
+
+        fs::create_dir(root.join("bin")).unwrap();
+        fs::write(root.join("bin/a"), b"").unwrap();
+        fs::write(root.join("bin/b"), b"").unwrap();
+
+        let mut manifest = FileCapsManifest::new();
+        manifest.insert("/bin/a", fcaps("cap_net_raw=ep"));
+        manifest.insert("bin/b", fcaps("cap_chown=p"));
+
+        // Test whether we can set file capabilities here
+        if fcaps("cap_kill=p")
+            .set_for_file(root.join("bin/b"))
+            .is_err()
+        {
+            return;
+        }
+
+        // A missing file means nothing is changed
+        let mut bad = manifest.clone();
+        bad.insert("/bin/nonexistent", fcaps("cap_chown=p"));
+        let err = bad.apply(root).unwrap_err();
+        assert_eq!(err.path(), root.join("bin/nonexistent"));
+        assert_eq!(err.error().raw_os_error(), Some(libc::ENOENT));
+        assert_eq!(
+            FileCaps::get_for_file(root.join("bin/b")).unwrap(),
+            Some(fcaps("cap_kill=p"))
+        );
+
+        let mut bad = manifest.clone();
+        bad.insert("/bin/../bin/a", fcaps("cap_chown=p"));
+        assert_eq!(
+            bad.apply(root).unwrap_err().error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        assert_eq!(
+            manifest.verify(root).unwrap(),
+            vec![
+                ManifestDrift::Missing {
+                    path: "/bin/a".into(),
+                    expected: fcaps("cap_net_raw=ep"),
+                },
+                ManifestDrift::Different {
+                    path: "/bin/b".into(),
+                    expected: fcaps("cap_chown=p"),
+                    actual: fcaps("cap_kill=p"),
+                },
+            ]
+        );
+
+        manifest.apply(root).unwrap();
+        assert_eq!(manifest.verify(root).unwrap(), vec![]);
+        assert_eq!(FileCapsManifest::capture(root).unwrap(), manifest);
+
+        fs::write(root.join("extra"), b"").unwrap();
+        fcaps("cap_kill=p")
+            .set_for_file(root.join("extra"))
+            .unwrap();
+        assert_eq!(
+            manifest.verify(root).unwrap(),
+            vec![ManifestDrift::Extra {
+                path: "/extra".into(),
+                actual: fcaps("cap_kill=p"),
+            }]
+        );
+
+        // The scanner only reports regular files, but listed entries are checked directly
+        fs::create_dir(root.join("dir")).unwrap();
+        manifest.insert("/extra", fcaps("cap_kill=p"));
+        manifest.insert("/dir", fcaps("cap_net_raw=p"));
+        assert_eq!(
+            manifest.verify(root).unwrap(),
+            vec![ManifestDrift::Missing {
+                path: "/dir".into(),
+                expected: fcaps("cap_net_raw=p"),
+            }]
+        );
+
+        manifest.apply(root).unwrap();
+        assert_eq!(manifest.verify(root).unwrap(), vec![]);
+        assert_eq!(FileCapsManifest::capture(root).unwrap().get("/dir"), None);
+
+        let mut bad = manifest.clone();
+        bad.insert("/extra/nonexistent", fcaps("cap_chown=p"));
+        assert_eq!(
+            bad.verify(root).unwrap(),
+            vec![ManifestDrift::Missing {
+                path: "/extra/nonexistent".into(),
+                expected: fcaps("cap_chown=p"),
+            }]
+        );
+
+        assert_eq!(
+            FileCapsManifest::capture(root.join("nonexistent"))
+                .unwrap_err()
+                .error()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    #[test]
+    fn test_manifest_apply_symlinks() {
+        let tmp = TempDir::new("manifest-symlinks");
+        let root = tmp.path();
+
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/sh"), b"").unwrap();
+        std::os::unix::fs::symlink("/usr/bin", root.join("bin")).unwrap();
+        std::os::unix::fs::symlink("sh", root.join("usr/bin/link")).unwrap();
+
+        // Nothing outside the root (like the host's /usr/bin/sh) is touched
+        for (path, eno) in [
+            ("/bin/sh", libc::ELOOP),
+            ("/usr/bin/link", libc::ELOOP),
+            ("/usr/bin/sh/x", libc::ENOTDIR),
+            ("/", libc::EINVAL),
+        ]
+        .iter()
+        {
+            let mut manifest = FileCapsManifest::new();
+            manifest.insert(path, fcaps("cap_chown=p"));
+
+            let err = manifest.apply(root).unwrap_err();
+            assert_eq!(err.path(), full_path(root, Path::new(path)));
+            assert_eq!(err.error().raw_os_error(), Some(*eno), "{}", path);
+        }
+    }
+}
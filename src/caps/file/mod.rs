@@ -9,6 +9,66 @@ use crate::sys;
 use super::cap_text::{caps_from_text, caps_to_text, ParseCapsError};
 use super::{CapSet, CapState};
 
+mod at;
+mod exec;
+mod lint;
+mod manifest;
+mod pax;
+mod scan;
+#[cfg(test)]
+mod testutil;
+mod userns;
+
+pub use at::{AsDirFd, AtFlags, Cwd};
+pub use exec::{ExecBlocker, ExecCheck};
+pub use lint::FileCapsWarning;
+pub use manifest::{FileCapsManifest, ManifestDrift, ParseManifestError};
+pub use pax::{PaxCapsWriter, PAX_CAPS_KEY};
+pub use scan::{FileCapsScanner, ScanError};
+pub use userns::{UidMap, UidMapEntry};
+
+/// The revision of the binary format used to store file capabilities in the
+/// `security.capability` extended attribute.
+///
+/// See [`FileCaps::pack_attrs_as()`] and [`FileCaps::unpack_attrs_with_revision()`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum FileCapsRevision {
+    /// Version 1 (Linux 2.6.24+). This can only store capabilities numbered below 32, and has no
+    /// root ID.
+    ///
+    /// Note: Linux 4.14+ can still read version 1 file capabilities, but rejects attempts to set
+    /// them with `EINVAL`. They can still be written into filesystem images or archives with
+    /// [`FileCaps::pack_attrs_as()`].
+    V1,
+    /// Version 2 (Linux 2.6.25+). This has no root ID.
+    V2,
+    /// Version 3 (Linux 4.14+). This includes the root ID of the user namespace in which the file
+    /// capabilities were added (see [`FileCaps::rootid`]).
+    V3,
+}
+
+impl FileCapsRevision {
+    fn magic(self) -> u32 {
+        match self {
+            Self::V1 => sys::VFS_CAP_REVISION_1,
+            Self::V2 => sys::VFS_CAP_REVISION_2,
+            Self::V3 => sys::VFS_CAP_REVISION_3,
+        }
+    }
+
+    /// Get the size of the extended attribute for this revision, in bytes.
+    #[inline]
+    pub fn attr_size(self) -> usize {
+        match self {
+            Self::V1 => sys::XATTR_CAPS_SZ_1,
+            Self::V2 => sys::XATTR_CAPS_SZ_2,
+            Self::V3 => sys::XATTR_CAPS_SZ_3,
+        }
+    }
+}
+
 /// Represents the capabilities attached to a file.
 ///
 /// # `FromStr` and `Display` implementations
@@ -36,7 +96,7 @@ pub struct FileCaps {
     pub inheritable: CapSet,
     /// The root user ID of the user namespace in which file capabilities were added to this file.
     /// See capabilities(7) for more details. This is only set to a non-`None` value for version 3
-    /// file capabilities.
+    /// file capabilities. See [`UidMap`] for translating it between user namespaces.
     pub rootid: Option<libc::uid_t>,
 }
 
@@ -58,7 +118,18 @@ impl FileCaps {
     /// this method returns `Err(<error>)`. Otherwise, if the given file has no file capabilities
     /// attached, this method returns `Ok(None)`. Otherwise, this method returns
     /// `Ok(Some(<capabilities>))`.
+    #[inline]
     pub fn get_for_file<P: AsRef<OsStr>>(path: P) -> io::Result<Option<Self>> {
+        Ok(Self::get_for_file_with_revision(path)?.map(|(caps, _)| caps))
+    }
+
+    /// Get the file capabilities attached to the file identified by `path`, along with the
+    /// revision of the format in which they were stored.
+    ///
+    /// See [`get_for_file()`](#method.get_for_file) for more information.
+    pub fn get_for_file_with_revision<P: AsRef<OsStr>>(
+        path: P,
+    ) -> io::Result<Option<(Self, FileCapsRevision)>> {
         let mut data = [0; sys::XATTR_CAPS_MAX_SIZE];
 
         let path = CString::new(path.as_ref().as_bytes())?;
@@ -78,12 +149,26 @@ impl FileCaps {
     /// Get the file capabilities attached to the open file identified by the file descriptor `fd`.
     ///
     /// See [`get_for_file()`](#method.get_for_file) for more information.
-    pub fn get_for_fd(fd: RawFd) -> io::Result<Option<Self>> {
+    ///
+    /// Before version 0.3.0, this took a `RawFd`; raw file descriptors can still be passed using
+    /// [`BorrowedFd::borrow_raw()`](std::os::unix::io::BorrowedFd::borrow_raw).
+    #[inline]
+    pub fn get_for_fd<F: AsFd>(fd: F) -> io::Result<Option<Self>> {
+        Ok(Self::get_for_fd_with_revision(fd)?.map(|(caps, _)| caps))
+    }
+
+    /// Get the file capabilities attached to the open file identified by the file descriptor `fd`,
+    /// along with the revision of the format in which they were stored.
+    ///
+    /// See [`get_for_file()`](#method.get_for_file) for more information.
+    pub fn get_for_fd_with_revision<F: AsFd>(
+        fd: F,
+    ) -> io::Result<Option<(Self, FileCapsRevision)>> {
         let mut data = [0; sys::XATTR_CAPS_MAX_SIZE];
 
         let ret = unsafe {
             libc::fgetxattr(
-                fd,
+                fd.as_fd().as_raw_fd(),
                 sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
                 data.as_mut_ptr() as *mut libc::c_void,
                 data.len(),
@@ -93,9 +178,14 @@ impl FileCaps {
         Self::extract_attr_or_error(&data, ret)
     }
 
-    fn extract_attr_or_error(data: &[u8], attr_res: isize) -> io::Result<Option<Self>> {
+    fn extract_attr_or_error(
+        data: &[u8],
+        attr_res: isize,
+    ) -> io::Result<Option<(Self, FileCapsRevision)>> {
         if attr_res >= 0 {
-            Ok(Some(Self::unpack_attrs(&data[..(attr_res as usize)])?))
+            Ok(Some(Self::unpack_attrs_with_revision(
+                &data[..(attr_res as usize)],
+            )?))
         } else {
             let err = io::Error::last_os_error();
 
@@ -115,7 +205,17 @@ impl FileCaps {
     ///
     /// [`get_for_file()`]: #method.get_for_file
     /// [`get_for_fd()`]: #method.get_for_fd
+    #[inline]
     pub fn unpack_attrs(attrs: &[u8]) -> io::Result<Self> {
+        Ok(Self::unpack_attrs_with_revision(attrs)?.0)
+    }
+
+    /// From the raw data from the `security.capability` extended attribute of a file, construct a
+    /// new `FileCaps` object representing the same data, and determine the revision of the format
+    /// in which it was stored.
+    ///
+    /// See [`unpack_attrs()`](#method.unpack_attrs) for more information.
+    pub fn unpack_attrs_with_revision(attrs: &[u8]) -> io::Result<(Self, FileCapsRevision)> {
         let len = attrs.len();
 
         if len < 4 {
@@ -128,43 +228,38 @@ impl FileCaps {
 
         let effective = (flags & sys::VFS_CAP_FLAGS_EFFECTIVE) != 0;
 
+        let read_u32 = |i: usize| u32::from_le_bytes(attrs[i..i + 4].try_into().unwrap());
+
         match (version, len) {
-            (sys::VFS_CAP_REVISION_2, sys::XATTR_CAPS_SZ_2) => Ok(FileCaps {
-                effective,
-                permitted: CapSet::from_bitmasks_u32(
-                    u32::from_le_bytes(attrs[4..8].try_into().unwrap()),
-                    u32::from_le_bytes(attrs[12..16].try_into().unwrap()),
-                ),
-                inheritable: CapSet::from_bitmasks_u32(
-                    u32::from_le_bytes(attrs[8..12].try_into().unwrap()),
-                    u32::from_le_bytes(attrs[16..20].try_into().unwrap()),
-                ),
-                rootid: None,
-            }),
-
-            (sys::VFS_CAP_REVISION_3, sys::XATTR_CAPS_SZ_3) => Ok(FileCaps {
-                effective,
-                permitted: CapSet::from_bitmasks_u32(
-                    u32::from_le_bytes(attrs[4..8].try_into().unwrap()),
-                    u32::from_le_bytes(attrs[12..16].try_into().unwrap()),
-                ),
-                inheritable: CapSet::from_bitmasks_u32(
-                    u32::from_le_bytes(attrs[8..12].try_into().unwrap()),
-                    u32::from_le_bytes(attrs[16..20].try_into().unwrap()),
-                ),
-                rootid: Some(u32::from_le_bytes(attrs[20..24].try_into().unwrap())),
-            }),
-
-            (sys::VFS_CAP_REVISION_1, sys::XATTR_CAPS_SZ_1) => Ok(FileCaps {
-                effective,
-                permitted: CapSet::from_bitmask_truncate(u32::from_le_bytes(
-                    attrs[4..8].try_into().unwrap(),
-                ) as u64),
-                inheritable: CapSet::from_bitmask_truncate(u32::from_le_bytes(
-                    attrs[8..12].try_into().unwrap(),
-                ) as u64),
-                rootid: None,
-            }),
+            (sys::VFS_CAP_REVISION_2, sys::XATTR_CAPS_SZ_2) => Ok((
+                FileCaps {
+                    effective,
+                    permitted: CapSet::from_bitmasks_u32(read_u32(4), read_u32(12)),
+                    inheritable: CapSet::from_bitmasks_u32(read_u32(8), read_u32(16)),
+                    rootid: None,
+                },
+                FileCapsRevision::V2,
+            )),
+
+            (sys::VFS_CAP_REVISION_3, sys::XATTR_CAPS_SZ_3) => Ok((
+                FileCaps {
+                    effective,
+                    permitted: CapSet::from_bitmasks_u32(read_u32(4), read_u32(12)),
+                    inheritable: CapSet::from_bitmasks_u32(read_u32(8), read_u32(16)),
+                    rootid: Some(read_u32(20)),
+                },
+                FileCapsRevision::V3,
+            )),
+
+            (sys::VFS_CAP_REVISION_1, sys::XATTR_CAPS_SZ_1) => Ok((
+                FileCaps {
+                    effective,
+                    permitted: CapSet::from_bitmask_truncate(read_u32(4) as u64),
+                    inheritable: CapSet::from_bitmask_truncate(read_u32(8) as u64),
+                    rootid: None,
+                },
+                FileCapsRevision::V1,
+            )),
 
             (_, _) => Err(io::Error::from_raw_os_error(libc::EINVAL)),
         }
@@ -172,12 +267,28 @@ impl FileCaps {
 
     /// Set the file capabilities attached to the file identified by `path` to the state
     /// represented by this object.
+    ///
+    /// The capabilities are stored in the revision returned by
+    /// [`default_revision()`](#method.default_revision).
     #[inline]
     pub fn set_for_file<P: AsRef<OsStr>>(&self, path: P) -> io::Result<()> {
+        self.set_for_file_as(path, self.default_revision())
+    }
+
+    /// Set the file capabilities attached to the file identified by `path` to the state
+    /// represented by this object, stored in the given format revision.
+    ///
+    /// This fails with `EINVAL` if the state cannot be represented in that revision (see
+    /// [`pack_attrs_as()`](#method.pack_attrs_as)).
+    pub fn set_for_file_as<P: AsRef<OsStr>>(
+        &self,
+        path: P,
+        revision: FileCapsRevision,
+    ) -> io::Result<()> {
         let path = CString::new(path.as_ref().as_bytes())?;
 
         let mut buf = [0u8; sys::XATTR_CAPS_MAX_SIZE];
-        let len = self.pack_into(&mut buf);
+        let len = self.pack_into(&mut buf, revision)?;
 
         debug_assert!(len <= buf.len());
 
@@ -199,16 +310,30 @@ impl FileCaps {
 
     /// Set the file capabilities attached to the open file identified by the file descriptor `fd`
     /// to the state represented by this object.
+    ///
+    /// The capabilities are stored in the revision returned by
+    /// [`default_revision()`](#method.default_revision).
+    ///
+    /// Before version 0.3.0, this took a `RawFd`; raw file descriptors can still be passed using
+    /// [`BorrowedFd::borrow_raw()`](std::os::unix::io::BorrowedFd::borrow_raw).
     #[inline]
-    pub fn set_for_fd(&self, fd: RawFd) -> io::Result<()> {
+    pub fn set_for_fd<F: AsFd>(&self, fd: F) -> io::Result<()> {
+        self.set_for_fd_as(fd, self.default_revision())
+    }
+
+    /// Set the file capabilities attached to the open file identified by the file descriptor `fd`
+    /// to the state represented by this object, stored in the given format revision.
+    ///
+    /// See [`set_for_file_as()`](#method.set_for_file_as) for more information.
+    pub fn set_for_fd_as<F: AsFd>(&self, fd: F, revision: FileCapsRevision) -> io::Result<()> {
         let mut buf = [0u8; sys::XATTR_CAPS_MAX_SIZE];
-        let len = self.pack_into(&mut buf);
+        let len = self.pack_into(&mut buf, revision)?;
 
         debug_assert!(len <= buf.len());
 
         if unsafe {
             libc::fsetxattr(
-                fd,
+                fd.as_fd().as_raw_fd(),
                 sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
                 buf.as_ptr() as *const libc::c_void,
                 len,
@@ -222,31 +347,50 @@ impl FileCaps {
         }
     }
 
-    fn pack_into(&self, buf: &mut [u8]) -> usize {
-        let mut magic = if self.rootid.is_some() {
-            sys::VFS_CAP_REVISION_3
+    /// Get the format revision in which these file capabilities are stored by default.
+    ///
+    /// This is [`FileCapsRevision::V3`] if [`rootid`](#structfield.rootid) is set, and
+    /// [`FileCapsRevision::V2`] otherwise.
+    #[inline]
+    pub fn default_revision(&self) -> FileCapsRevision {
+        if self.rootid.is_some() {
+            FileCapsRevision::V3
         } else {
-            sys::VFS_CAP_REVISION_2
+            FileCapsRevision::V2
+        }
+    }
+
+    fn pack_into(&self, buf: &mut [u8], revision: FileCapsRevision) -> io::Result<usize> {
+        let representable = match revision {
+            FileCapsRevision::V1 => {
+                self.rootid.is_none() && (self.permitted | self.inheritable).bits >> 32 == 0
+            }
+            FileCapsRevision::V2 => self.rootid.is_none(),
+            FileCapsRevision::V3 => true,
         };
+        if !representable {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
 
+        let mut magic = revision.magic();
         if self.effective {
             magic |= sys::VFS_CAP_FLAGS_EFFECTIVE;
         }
 
-        let mut len = 20;
-
         buf[..4].copy_from_slice(&magic.to_le_bytes());
         buf[4..8].copy_from_slice(&(self.permitted.bits as u32).to_le_bytes());
         buf[8..12].copy_from_slice(&(self.inheritable.bits as u32).to_le_bytes());
-        buf[12..16].copy_from_slice(&((self.permitted.bits >> 32) as u32).to_le_bytes());
-        buf[16..20].copy_from_slice(&((self.inheritable.bits >> 32) as u32).to_le_bytes());
 
-        if let Some(rootid) = self.rootid {
-            buf[len..len + 4].copy_from_slice(&rootid.to_le_bytes());
-            len += 4;
+        if revision >= FileCapsRevision::V2 {
+            buf[12..16].copy_from_slice(&((self.permitted.bits >> 32) as u32).to_le_bytes());
+            buf[16..20].copy_from_slice(&((self.inheritable.bits >> 32) as u32).to_le_bytes());
         }
 
-        len
+        if revision == FileCapsRevision::V3 {
+            buf[20..24].copy_from_slice(&self.rootid.unwrap_or(0).to_le_bytes());
+        }
+
+        Ok(revision.attr_size())
     }
 
     /// "Pack" the file capabilities represented by this object; i.e. convert it to the raw binary
@@ -267,19 +411,48 @@ impl FileCaps {
     ///
     /// (Note, however, that the reverse is not always true. For example, version 1 file
     /// capabilities can be "unpacked", but they will be "packed" as version 2 file capabilities,
-    /// and as a result the binary data will be different.)
+    /// and as a result the binary data will be different. Use [`pack_attrs_as()`] to control the
+    /// revision.)
     ///
     /// [`set_for_file()`]: #method.set_for_file
     /// [`set_for_fd()`]: #method.set_for_fd
     /// [`unpack_attrs()`]: #method.unpack_attrs
+    /// [`pack_attrs_as()`]: #method.pack_attrs_as
     #[inline]
     pub fn pack_attrs(&self) -> Vec<u8> {
+        self.pack_attrs_as(self.default_revision()).unwrap()
+    }
+
+    /// "Pack" the file capabilities represented by this object in the given format revision.
+    ///
+    /// This fails with `EINVAL` if the state cannot be represented in that revision:
+    ///
+    /// - [`FileCapsRevision::V1`] requires that [`rootid`](#structfield.rootid) is `None`, and
+    ///   that the permitted and inheritable sets only contain capabilities numbered below 32.
+    /// - [`FileCapsRevision::V2`] requires that `rootid` is `None`.
+    /// - [`FileCapsRevision::V3`] can represent any state (if `rootid` is `None`, a root ID of 0
+    ///   is stored).
+    ///
+    /// ```
+    /// # use capctl::caps::{Cap, FileCaps, FileCapsRevision};
+    /// let mut fcaps = FileCaps::empty();
+    /// fcaps.permitted.add(Cap::NET_RAW);
+    /// let attrs = fcaps.pack_attrs_as(FileCapsRevision::V1).unwrap();
+    /// assert_eq!(
+    ///     FileCaps::unpack_attrs_with_revision(&attrs).unwrap(),
+    ///     (fcaps, FileCapsRevision::V1),
+    /// );
+    ///
+    /// fcaps.permitted.add(Cap::SYSLOG);
+    /// assert!(fcaps.pack_attrs_as(FileCapsRevision::V1).is_err());
+    /// ```
+    pub fn pack_attrs_as(&self, revision: FileCapsRevision) -> io::Result<Vec<u8>> {
         let mut buf = vec![0u8; sys::XATTR_CAPS_MAX_SIZE];
 
-        let len = self.pack_into(&mut buf);
+        let len = self.pack_into(&mut buf, revision)?;
         buf.truncate(len);
 
-        buf
+        Ok(buf)
     }
 
     /// Remove the file capabilities attached to the file identified by `path`.
@@ -301,10 +474,17 @@ impl FileCaps {
     }
 
     /// Remove the file capabilities attached to the open file identified by `fd`.
+    ///
+    /// Before version 0.3.0, this took a `RawFd`; raw file descriptors can still be passed using
+    /// [`BorrowedFd::borrow_raw()`](std::os::unix::io::BorrowedFd::borrow_raw).
     #[inline]
-    pub fn remove_for_fd(fd: RawFd) -> io::Result<()> {
-        if unsafe { libc::fremovexattr(fd, sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char) }
-            < 0
+    pub fn remove_for_fd<F: AsFd>(fd: F) -> io::Result<()> {
+        if unsafe {
+            libc::fremovexattr(
+                fd.as_fd().as_raw_fd(),
+                sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
+            )
+        } < 0
         {
             Err(io::Error::last_os_error())
         } else {
@@ -386,6 +566,15 @@ mod tests {
         assert!(empty_caps.rootid.is_none());
     }
 
+    /// Open an `O_PATH` file descriptor, which the `f*xattr()` functions reject with `EBADF`.
+    fn open_path(path: &std::path::Path) -> std::fs::File {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(path)
+            .unwrap()
+    }
+
     #[test]
     fn test_filecaps_get() {
         let current_exe = std::env::current_exe().unwrap();
@@ -393,7 +582,7 @@ mod tests {
         FileCaps::get_for_file(&current_exe).unwrap();
 
         let f = std::fs::File::open(&current_exe).unwrap();
-        FileCaps::get_for_fd(f.as_raw_fd()).unwrap();
+        FileCaps::get_for_fd(&f).unwrap();
 
         assert_eq!(
             FileCaps::get_for_file(current_exe.join("sub"))
@@ -402,7 +591,9 @@ mod tests {
             Some(libc::ENOTDIR)
         );
         assert_eq!(
-            FileCaps::get_for_fd(-1).unwrap_err().raw_os_error(),
+            FileCaps::get_for_fd(open_path(&current_exe))
+                .unwrap_err()
+                .raw_os_error(),
             Some(libc::EBADF)
         );
     }
@@ -472,6 +663,81 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_filecaps_revision() {
+        let v1_data = b"\x01\x00\x00\x01\x01\x00\x00\x00\x00\x00\x00\x00";
+        let fcaps = FileCaps {
+            effective: true,
+            permitted: capset!(Cap::CHOWN),
+            inheritable: capset!(),
+            rootid: None,
+        };
+
+        assert_eq!(
+            FileCaps::unpack_attrs_with_revision(v1_data).unwrap(),
+            (fcaps, FileCapsRevision::V1)
+        );
+        assert_eq!(fcaps.default_revision(), FileCapsRevision::V2);
+        assert_eq!(&fcaps.pack_attrs_as(FileCapsRevision::V1).unwrap(), v1_data);
+
+        for revision in [
+            FileCapsRevision::V1,
+            FileCapsRevision::V2,
+            FileCapsRevision::V3,
+        ]
+        .iter()
+        {
+            let data = fcaps.pack_attrs_as(*revision).unwrap();
+            assert_eq!(data.len(), revision.attr_size());
+            let (unpacked, unpacked_rev) = FileCaps::unpack_attrs_with_revision(&data).unwrap();
+            assert_eq!(unpacked_rev, *revision);
+            assert_eq!(unpacked.permitted, fcaps.permitted);
+            assert_eq!(
+                unpacked.rootid,
+                if *revision == FileCapsRevision::V3 {
+                    Some(0)
+                } else {
+                    None
+                }
+            );
+        }
+
+        // Unrepresentable
+        let mut high = fcaps;
+        high.inheritable.add(Cap::SYSLOG);
+        assert_eq!(
+            high.pack_attrs_as(FileCapsRevision::V1)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        high.pack_attrs_as(FileCapsRevision::V2).unwrap();
+
+        let mut v3 = fcaps;
+        v3.rootid = Some(1000);
+        assert_eq!(v3.default_revision(), FileCapsRevision::V3);
+        for revision in [FileCapsRevision::V1, FileCapsRevision::V2].iter() {
+            assert_eq!(
+                v3.pack_attrs_as(*revision).unwrap_err().raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+
+        let current_exe = std::env::current_exe().unwrap();
+        assert_eq!(
+            high.set_for_file_as(&current_exe, FileCapsRevision::V1)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            FileCaps::get_for_file(&current_exe).unwrap(),
+            FileCaps::get_for_file_with_revision(&current_exe)
+                .unwrap()
+                .map(|(caps, _)| caps)
+        );
+    }
+
     #[test]
     fn test_filecaps_set_error() {
         let current_exe = std::env::current_exe().unwrap();
@@ -484,7 +750,10 @@ mod tests {
             Some(libc::ENOTDIR)
         );
         assert_eq!(
-            FileCaps::empty().set_for_fd(-1).unwrap_err().raw_os_error(),
+            FileCaps::empty()
+                .set_for_fd(open_path(&current_exe))
+                .unwrap_err()
+                .raw_os_error(),
             Some(libc::EBADF)
         );
     }
@@ -500,7 +769,9 @@ mod tests {
             Some(libc::ENOTDIR)
         );
         assert_eq!(
-            FileCaps::remove_for_fd(-1).unwrap_err().raw_os_error(),
+            FileCaps::remove_for_fd(open_path(&current_exe))
+                .unwrap_err()
+                .raw_os_error(),
             Some(libc::EBADF)
         );
     }
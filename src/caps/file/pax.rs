@@ -0,0 +1,341 @@
+use std::io::{self, Write};
+use std::os::unix::prelude::*;
+
+use crate::sys;
+
+use super::{FileCaps, FileCapsRevision};
+
+/// The key of the PAX extended header record that stores file capabilities in tar archives (as
+/// written by GNU tar and `bsdtar`).
+pub const PAX_CAPS_KEY: &str = "SCHILY.xattr.security.capability";
+
+/// Encode a single PAX extended header record (`"<length> <key>=<value>\n"`).
+fn encode_record(key: &str, value: &[u8]) -> Vec<u8> {
+    // The length includes the length field itself
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while rest + len.to_string().len() != len {
+        len += 1;
+    }
+
+    let mut record = Vec::with_capacity(len);
+    record.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    debug_assert_eq!(record.len(), len);
+    record
+}
+
+/// Find the value of the record with the given key in the data of a PAX extended header.
+///
+/// If the key appears more than once, the last value is returned (as specified by POSIX).
+fn find_record<'a>(mut data: &'a [u8], key: &str) -> io::Result<Option<&'a [u8]>> {
+    let einval = || io::Error::from_raw_os_error(libc::EINVAL);
+
+    let mut res = None;
+
+    // Some writers pad the data with NUL bytes
+    while !data.iter().all(|&b| b == 0) {
+        let space = data.iter().position(|&b| b == b' ').ok_or_else(einval)?;
+        let len: usize = std::str::from_utf8(&data[..space])
+            .ok()
+            .filter(|s| s.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(einval)?;
+
+        if len <= space + 1 || len > data.len() || data[len - 1] != b'\n' {
+            return Err(einval());
+        }
+
+        let record = &data[space + 1..len - 1];
+        let eq = record.iter().position(|&b| b == b'=').ok_or_else(einval)?;
+        if &record[..eq] == key.as_bytes() {
+            res = Some(&record[eq + 1..]);
+        }
+
+        data = &data[len..];
+    }
+
+    Ok(res)
+}
+
+fn unpack_pax_records(data: &[u8]) -> io::Result<Option<(FileCaps, FileCapsRevision)>> {
+    match find_record(data, PAX_CAPS_KEY)? {
+        Some(attrs) => Ok(Some(FileCaps::unpack_attrs_with_revision(attrs)?)),
+        None => Ok(None),
+    }
+}
+
+impl FileCaps {
+    /// Encode these file capabilities as a PAX extended header record (with the key
+    /// [`PAX_CAPS_KEY`]), for inclusion in a tar archive.
+    ///
+    /// The value of the record is the raw extended attribute data, as returned by
+    /// [`pack_attrs()`](#method.pack_attrs).
+    #[inline]
+    pub fn to_pax_record(&self) -> Vec<u8> {
+        encode_record(PAX_CAPS_KEY, &self.pack_attrs())
+    }
+
+    /// Read the file capabilities attached to the open file identified by the file descriptor `fd`
+    /// and encode them as a PAX extended header record, or return `None` if it has no file
+    /// capabilities.
+    ///
+    /// Unlike `FileCaps::get_for_fd(fd)?.map(|fcaps| fcaps.to_pax_record())`, this copies the
+    /// extended attribute data verbatim, so the format revision is preserved.
+    pub fn pax_record_for_fd<F: AsFd>(fd: F) -> io::Result<Option<Vec<u8>>> {
+        let mut data = [0; sys::XATTR_CAPS_MAX_SIZE];
+
+        let ret = unsafe {
+            libc::fgetxattr(
+                fd.as_fd().as_raw_fd(),
+                sys::XATTR_NAME_CAPS.as_ptr() as *const libc::c_char,
+                data.as_mut_ptr() as *mut libc::c_void,
+                data.len(),
+            )
+        };
+
+        if ret >= 0 {
+            let data = &data[..ret as usize];
+            // Make sure it's valid
+            Self::unpack_attrs(data)?;
+            Ok(Some(encode_record(PAX_CAPS_KEY, data)))
+        } else {
+            let err = io::Error::last_os_error();
+
+            if err.raw_os_error() == Some(libc::ENODATA) {
+                Ok(None)
+            } else {
+                Err(err)
+            }
+        }
+    }
+
+    /// Parse the data of a PAX extended header (which may contain any number of records) and
+    /// return the file capabilities stored in it, or `None` if there is no [`PAX_CAPS_KEY`]
+    /// record.
+    ///
+    /// This fails with `EINVAL` if the data is malformed.
+    #[inline]
+    pub fn from_pax_records(data: &[u8]) -> io::Result<Option<Self>> {
+        Ok(unpack_pax_records(data)?.map(|(fcaps, _)| fcaps))
+    }
+}
+
+/// A writer that restores file capabilities from a PAX extended header when extracting a file
+/// from a tar archive.
+///
+/// The kernel removes a file's capabilities whenever it is written to, so they can only be set
+/// after the contents have been written. This wraps the file being extracted, passes all writes
+/// through, and sets the file capabilities (in the same format revision as in the archive) when
+/// [`finish()`](#method.finish) is called.
+///
+/// Since Linux 4.14+ refuses to set version 1 file capabilities, they are restored as version 2
+/// capabilities (which store the same capabilities).
+///
+/// Example:
+///
+/// ```no_run
+/// # use std::io::Write;
+/// # use capctl::caps::PaxCapsWriter;
+/// # let pax_header: &[u8] = b"";
+/// # let contents: &[u8] = b"";
+/// let file = std::fs::File::create("/tmp/extracted").unwrap();
+/// let mut writer = PaxCapsWriter::new(file, pax_header).unwrap();
+/// writer.write_all(contents).unwrap();
+/// writer.finish().unwrap();
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug)]
+pub struct PaxCapsWriter<W> {
+    inner: W,
+    fcaps: Option<(FileCaps, FileCapsRevision)>,
+}
+
+impl<W: Write + AsFd> PaxCapsWriter<W> {
+    /// Wrap `inner`, using the file capabilities stored in the given PAX extended header data (if
+    /// any).
+    ///
+    /// This fails with `EINVAL` if the data is malformed.
+    pub fn new(inner: W, pax_header: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            fcaps: unpack_pax_records(pax_header)?,
+        })
+    }
+
+    /// Get the file capabilities that will be restored (if any).
+    #[inline]
+    pub fn file_caps(&self) -> Option<&FileCaps> {
+        self.fcaps.as_ref().map(|(fcaps, _)| fcaps)
+    }
+
+    /// Flush the writer, set the file capabilities (if any), and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+
+        if let Some((fcaps, revision)) = self.fcaps {
+            let revision = match revision {
+                FileCapsRevision::V1 => FileCapsRevision::V2,
+                revision => revision,
+            };
+            fcaps.set_for_fd_as(self.inner.as_fd(), revision)?;
+        }
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for PaxCapsWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::caps::file::testutil::TempDir;
+    use crate::caps::Cap;
+    use crate::capset;
+
+    #[test]
+    fn test_pax_encode() {
+        assert_eq!(encode_record("a", b""), b"5 a=\n");
+        assert_eq!(encode_record("a", b"bbbb"), b"9 a=bbbb\n");
+        assert_eq!(encode_record("a", b"bbbbb"), b"11 a=bbbbb\n");
+        for (n, prefix) in [(93, b"99 a=".as_ref()), (94, b"101 a=")].iter() {
+            let record = encode_record("a", &vec![b'b'; *n]);
+            assert!(record.starts_with(prefix));
+            assert_eq!(
+                find_record(&record, "a").unwrap(),
+                Some(&record[prefix.len()..record.len() - 1])
+            );
+        }
+
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::NET_RAW);
+        fcaps.effective = true;
+
+        let record = fcaps.to_pax_record();
+        assert!(record.starts_with(b"57 SCHILY.xattr.security.capability="));
+        assert_eq!(&record[36..56], fcaps.pack_attrs().as_slice());
+        assert_eq!(FileCaps::from_pax_records(&record).unwrap(), Some(fcaps));
+    }
+
+    #[test]
+    fn test_pax_decode() {
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::CHOWN);
+
+        let mut data = encode_record("path", b"/usr/bin/x");
+        data.extend_from_slice(&fcaps.to_pax_record());
+        data.extend_from_slice(&encode_record("mtime", b"1234.5"));
+        data.extend_from_slice(&[0; 20]);
+
+        assert_eq!(FileCaps::from_pax_records(&data).unwrap(), Some(fcaps));
+
+        let v1 = fcaps.pack_attrs_as(FileCapsRevision::V1).unwrap();
+        assert_eq!(
+            unpack_pax_records(&encode_record(PAX_CAPS_KEY, &v1)).unwrap(),
+            Some((fcaps, FileCapsRevision::V1))
+        );
+
+        assert_eq!(FileCaps::from_pax_records(b"").unwrap(), None);
+        assert_eq!(FileCaps::from_pax_records(b"9 a=bbbb\n").unwrap(), None);
+
+        for data in [
+            b"9 a=bbbb".as_ref(),
+            b"11 a=bbbb\n",
+            b"9 abbbbb\n",
+            b"x a=bbbb\n",
+            b"+9 a=bbbb\n",
+            b"1 \n",
+            b"abc",
+            b"37 SCHILY.xattr.security.capability=\n",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                FileCaps::from_pax_records(data).unwrap_err().raw_os_error(),
+                Some(libc::EINVAL),
+                "{:?}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn test_pax_writer() {
+        let tmp = TempDir::new("pax");
+        let path = tmp.path().join("file");
+
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::NET_RAW);
+        fcaps.effective = true;
+        let attrs = fcaps.pack_attrs_as(FileCapsRevision::V2).unwrap();
+
+        let file = fs::File::create(&path).unwrap();
+        let mut writer = PaxCapsWriter::new(file, &encode_record(PAX_CAPS_KEY, &attrs)).unwrap();
+        assert_eq!(writer.file_caps(), Some(&fcaps));
+        writer.write_all(b"contents").unwrap();
+
+        match writer.finish() {
+            Ok(file) => {
+                assert_eq!(
+                    FileCaps::get_for_file_with_revision(&path).unwrap(),
+                    Some((fcaps, FileCapsRevision::V2))
+                );
+                assert_eq!(
+                    FileCaps::pax_record_for_fd(&file).unwrap(),
+                    Some(fcaps.to_pax_record())
+                );
+            }
+            // No permission, or the filesystem doesn't support it
+            Err(e) => assert!(
+                matches!(e.raw_os_error(), Some(libc::EPERM) | Some(libc::EOPNOTSUPP)),
+                "{}",
+                e
+            ),
+        }
+
+        assert_eq!(fs::read(&path).unwrap(), b"contents");
+
+        // Version 1 capabilities are restored as version 2
+        fs::remove_file(&path).unwrap();
+        fcaps.permitted = capset!(Cap::CHOWN);
+        let attrs = fcaps.pack_attrs_as(FileCapsRevision::V1).unwrap();
+        let file = fs::File::create(&path).unwrap();
+        let writer = PaxCapsWriter::new(file, &encode_record(PAX_CAPS_KEY, &attrs)).unwrap();
+        assert_eq!(writer.file_caps(), Some(&fcaps));
+        match writer.finish() {
+            Ok(_) => assert_eq!(
+                FileCaps::get_for_file_with_revision(&path).unwrap(),
+                Some((fcaps, FileCapsRevision::V2))
+            ),
+            Err(e) => assert!(
+                matches!(e.raw_os_error(), Some(libc::EPERM) | Some(libc::EOPNOTSUPP)),
+                "{}",
+                e
+            ),
+        }
+
+        // No capabilities
+        fs::remove_file(&path).unwrap();
+        let file = fs::File::create(&path).unwrap();
+        assert_eq!(FileCaps::pax_record_for_fd(&file).unwrap(), None);
+        let writer = PaxCapsWriter::new(file, &encode_record("path", b"x")).unwrap();
+        assert_eq!(writer.file_caps(), None);
+        writer.finish().unwrap();
+        assert_eq!(FileCaps::get_for_file(&path).unwrap(), None);
+    }
+}
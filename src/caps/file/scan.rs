@@ -0,0 +1,545 @@
+use std::ffi::{CStr, OsStr};
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::FileCaps;
+
+type ScanItem = Result<(PathBuf, FileCaps), ScanError>;
+
+/// Represents an error encountered while processing a single entry with [`FileCapsScanner`] or
+/// [`FileCapsManifest`](super::FileCapsManifest).
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug)]
+pub struct ScanError {
+    path: PathBuf,
+    error: io::Error,
+}
+
+impl ScanError {
+    #[inline]
+    pub(super) fn new(path: PathBuf, error: io::Error) -> Self {
+        Self { path, error }
+    }
+
+    /// Get the path of the entry that could not be processed.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the error that occurred.
+    #[inline]
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// Consume this object and return the underlying error.
+    #[inline]
+    pub fn into_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl fmt::Display for ScanError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for ScanError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<ScanError> for io::Error {
+    #[inline]
+    fn from(e: ScanError) -> Self {
+        Self::new(e.error.kind(), e)
+    }
+}
+
+/// An open directory stream.
+struct Dir(*mut libc::DIR);
+
+// The stream is owned exclusively by this object
+unsafe impl Send for Dir {}
+
+impl Dir {
+    /// Take ownership of `fd` and create a directory stream from it.
+    fn from_fd(fd: RawFd) -> io::Result<Self> {
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+            }
+            return Err(err);
+        }
+
+        Ok(Self(dir))
+    }
+
+    #[inline]
+    fn fd(&self) -> RawFd {
+        unsafe { libc::dirfd(self.0) }
+    }
+
+    /// Get the name of the next entry (skipping `.` and `..`), or `None` at the end.
+    fn next_name(&mut self) -> Option<io::Result<&CStr>> {
+        loop {
+            unsafe {
+                *libc::__errno_location() = 0;
+            }
+
+            let entry = unsafe { libc::readdir(self.0) };
+            if entry.is_null() {
+                let eno = unsafe { *libc::__errno_location() };
+                return if eno == 0 {
+                    None
+                } else {
+                    Some(Err(io::Error::from_raw_os_error(eno)))
+                };
+            }
+
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            if name.to_bytes() != b"." && name.to_bytes() != b".." {
+                return Some(Ok(name));
+            }
+        }
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe {
+            libc::closedir(self.0);
+        }
+    }
+}
+
+struct Frame {
+    dir: Dir,
+    path: PathBuf,
+    depth: usize,
+    dev: libc::dev_t,
+    ino: libc::ino_t,
+}
+
+fn fstatat(dirfd: RawFd, name: &CStr, follow: bool) -> io::Result<libc::stat> {
+    let mut st = unsafe { core::mem::zeroed() };
+    let flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+
+    if unsafe { libc::fstatat(dirfd, name.as_ptr(), &mut st, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(st)
+}
+
+fn openat(dirfd: RawFd, name: &CStr, flags: libc::c_int) -> io::Result<RawFd> {
+    let fd = unsafe {
+        libc::openat(
+            dirfd,
+            name.as_ptr(),
+            flags | libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOCTTY | libc::O_NONBLOCK,
+        )
+    };
+
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(fd)
+    }
+}
+
+/// Get the file capabilities of an open file, treating filesystems that do not support extended
+/// attributes as having no file capabilities.
+fn get_caps(fd: RawFd) -> io::Result<Option<FileCaps>> {
+    match FileCaps::get_for_fd(unsafe { BorrowedFd::borrow_raw(fd) }) {
+        Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(None),
+        res => res,
+    }
+}
+
+fn is_dir(st: &libc::stat) -> bool {
+    st.st_mode & libc::S_IFMT == libc::S_IFDIR
+}
+
+fn is_reg(st: &libc::stat) -> bool {
+    st.st_mode & libc::S_IFMT == libc::S_IFREG
+}
+
+/// An iterator that recursively scans a directory tree for files with file capabilities (like
+/// `getcap -r`).
+///
+/// Each item is either the path to a regular file and its file capabilities, or a [`ScanError`]
+/// describing an entry that could not be scanned (scanning continues with the next entry).
+/// Files without file capabilities are not listed.
+///
+/// Directories are traversed with `openat()`, and the capabilities are read from each file with
+/// `fgetxattr()`, so renames elsewhere in the tree during the scan cannot redirect it. Only
+/// regular files are opened; FIFOs, devices, etc. are skipped.
+///
+/// By default, symbolic links are not followed, other mount points are not crossed, and the depth
+/// is unlimited.
+///
+/// Example:
+///
+/// ```no_run
+/// # use capctl::caps::FileCapsScanner;
+/// for res in FileCapsScanner::new("/usr").unwrap().skip_errors(true) {
+///     let (path, caps) = res.unwrap();
+///     println!("{} {}", path.display(), caps);
+/// }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub struct FileCapsScanner {
+    stack: Vec<Frame>,
+    root: Option<(PathBuf, RawFd, libc::stat)>,
+    root_dev: libc::dev_t,
+    cross_mounts: bool,
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+    skip_errors: bool,
+}
+
+impl FileCapsScanner {
+    /// Begin scanning the directory tree at `path`.
+    ///
+    /// `path` itself is always followed if it is a symbolic link. If it is a regular file, only
+    /// that file is checked.
+    pub fn new<P: AsRef<OsStr>>(path: P) -> io::Result<Self> {
+        let path = Path::new(path.as_ref());
+        let cpath = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+
+        let fd = openat(libc::AT_FDCWD, &cpath, 0)?;
+
+        let mut st = unsafe { core::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut st) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+            }
+            return Err(err);
+        }
+
+        Ok(Self {
+            stack: Vec::new(),
+            root: Some((path.into(), fd, st)),
+            root_dev: st.st_dev,
+            cross_mounts: false,
+            follow_symlinks: false,
+            max_depth: None,
+            skip_errors: false,
+        })
+    }
+
+    /// Set whether to descend into other filesystems mounted inside the tree (default `false`).
+    pub fn cross_mounts(mut self, cross_mounts: bool) -> Self {
+        self.cross_mounts = cross_mounts;
+        self
+    }
+
+    /// Set whether to follow symbolic links (default `false`).
+    ///
+    /// Symbolic links that would lead to a directory that is already being scanned (i.e. loops)
+    /// are skipped.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Set the maximum depth to scan (default unlimited).
+    ///
+    /// The entries directly inside the starting directory are at depth 1, so a depth of 1 only
+    /// scans the starting directory and does not descend into subdirectories.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Set whether to silently skip entries that cannot be scanned, instead of returning a
+    /// [`ScanError`] for each of them (default `false`).
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    /// Start scanning the root directory (or check the root file).
+    fn start(&mut self, path: PathBuf, fd: RawFd, st: libc::stat) -> Option<ScanItem> {
+        if is_dir(&st) {
+            match Dir::from_fd(fd) {
+                Ok(dir) => self.stack.push(Frame {
+                    dir,
+                    path,
+                    depth: 0,
+                    dev: st.st_dev,
+                    ino: st.st_ino,
+                }),
+                Err(error) => return Some(Err(ScanError { path, error })),
+            }
+            None
+        } else {
+            let res = if is_reg(&st) { get_caps(fd) } else { Ok(None) };
+            unsafe {
+                libc::close(fd);
+            }
+
+            match res {
+                Ok(Some(caps)) => Some(Ok((path, caps))),
+                Ok(None) => None,
+                Err(error) => Some(Err(ScanError { path, error })),
+            }
+        }
+    }
+
+    /// Scan the next entry in the directory at the top of the stack.
+    ///
+    /// Returns `None` if the directory is exhausted.
+    fn scan_next(&mut self) -> Option<Option<ScanItem>> {
+        let follow_symlinks = self.follow_symlinks;
+        let cross_mounts = self.cross_mounts;
+        let root_dev = self.root_dev;
+
+        let frame = self.stack.last_mut().unwrap();
+        let dirfd = frame.dir.fd();
+        let depth = frame.depth + 1;
+
+        let name = match frame.dir.next_name() {
+            None => return None,
+            Some(Ok(name)) => name.to_owned(),
+            Some(Err(error)) => {
+                // Give up on this directory
+                let path = frame.path.clone();
+                self.stack.pop();
+                return Some(Some(Err(ScanError { path, error })));
+            }
+        };
+        let path = frame.path.join(OsStr::from_bytes(name.to_bytes()));
+
+        macro_rules! tri {
+            ($e:expr) => {
+                match $e {
+                    Ok(val) => val,
+                    Err(error) => return Some(Some(Err(ScanError { path, error }))),
+                }
+            };
+        }
+
+        let st = match fstatat(dirfd, &name, follow_symlinks) {
+            Ok(st) => st,
+            // A dangling symlink, or the entry was removed
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => return Some(None),
+            Err(error) => return Some(Some(Err(ScanError { path, error }))),
+        };
+
+        if st.st_dev != root_dev && !cross_mounts {
+            return Some(None);
+        }
+
+        let nofollow = if follow_symlinks { 0 } else { libc::O_NOFOLLOW };
+
+        if is_reg(&st) {
+            let fd = tri!(openat(dirfd, &name, nofollow));
+            let res = get_caps(fd);
+            unsafe {
+                libc::close(fd);
+            }
+
+            return Some(tri!(res).map(|caps| Ok((path, caps))));
+        }
+
+        if !is_dir(&st) || matches!(self.max_depth, Some(max) if depth >= max) {
+            return Some(None);
+        }
+
+        // Avoid symlink loops
+        if self
+            .stack
+            .iter()
+            .any(|frame| frame.dev == st.st_dev && frame.ino == st.st_ino)
+        {
+            return Some(None);
+        }
+
+        let fd = tri!(openat(dirfd, &name, nofollow | libc::O_DIRECTORY));
+        let dir = tri!(Dir::from_fd(fd));
+
+        self.stack.push(Frame {
+            dir,
+            path,
+            depth,
+            dev: st.st_dev,
+            ino: st.st_ino,
+        });
+        Some(None)
+    }
+}
+
+impl Iterator for FileCapsScanner {
+    type Item = ScanItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, fd, st)) = self.root.take() {
+            if let Some(res) = self.start(path, fd, st) {
+                if res.is_ok() || !self.skip_errors {
+                    return Some(res);
+                }
+            }
+        }
+
+        while !self.stack.is_empty() {
+            match self.scan_next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Some(Err(_))) if self.skip_errors => (),
+                Some(Some(res)) => return Some(res),
+                Some(None) => (),
+            }
+        }
+
+        None
+    }
+}
+
+impl core::iter::FusedIterator for FileCapsScanner {}
+
+impl Drop for FileCapsScanner {
+    fn drop(&mut self) {
+        if let Some((_, fd, _)) = self.root.take() {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FileCapsScanner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileCapsScanner")
+            .field("cross_mounts", &self.cross_mounts)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("max_depth", &self.max_depth)
+            .field("skip_errors", &self.skip_errors)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::caps::file::testutil::TempDir;
+    use crate::caps::Cap;
+    use crate::capset;
+
+    fn scan(scanner: FileCapsScanner) -> Vec<(PathBuf, FileCaps)> {
+        let mut res: Vec<_> = scanner.map(|res| res.unwrap()).collect();
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res
+    }
+
+    #[test]
+    fn test_scan() {
+        let tmp = TempDir::new("scan-basic");
+        let root = tmp.path();
+
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("plain"), b"").unwrap();
+        fs::write(root.join("top"), b"").unwrap();
+        fs::write(root.join("a/b/deep"), b"").unwrap();
+        std::os::unix::fs::symlink(root.join("a"), root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("a/loop")).unwrap();
+        std::os::unix::fs::symlink("/nonexistent", root.join("dangling")).unwrap();
+
+        let mut fcaps = FileCaps::empty();
+        fcaps.permitted = capset!(Cap::NET_RAW);
+        fcaps.effective = true;
+        match fcaps.set_for_file(root.join("top")) {
+            Ok(()) => (),
+            // No permission, or the filesystem doesn't support it
+            Err(_) => return,
+        }
+        fcaps.set_for_file(root.join("a/b/deep")).unwrap();
+
+        assert_eq!(
+            scan(FileCapsScanner::new(root).unwrap()),
+            vec![(root.join("a/b/deep"), fcaps), (root.join("top"), fcaps)]
+        );
+
+        assert_eq!(
+            scan(FileCapsScanner::new(root).unwrap().max_depth(2)),
+            vec![(root.join("top"), fcaps)]
+        );
+
+        assert_eq!(
+            scan(FileCapsScanner::new(root).unwrap().follow_symlinks(true)),
+            vec![
+                (root.join("a/b/deep"), fcaps),
+                (root.join("link/b/deep"), fcaps),
+                (root.join("top"), fcaps)
+            ]
+        );
+
+        // A single file
+        assert_eq!(
+            scan(FileCapsScanner::new(root.join("top")).unwrap()),
+            vec![(root.join("top"), fcaps)]
+        );
+        assert_eq!(
+            scan(FileCapsScanner::new(root.join("plain")).unwrap()),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        assert_eq!(
+            FileCapsScanner::new("/nonexistent")
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+
+        let tmp = TempDir::new("scan-errors");
+        let root = tmp.path().to_path_buf();
+        fs::create_dir(root.join("locked")).unwrap();
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+
+        std::thread::spawn(move || {
+            // Make sure we can't bypass the permissions
+            let mut state = crate::caps::CapState::get_current().unwrap();
+            state.effective.clear();
+            state.set_current().unwrap();
+
+            let errs: Vec<_> = FileCapsScanner::new(&root).unwrap().collect();
+            assert_eq!(errs.len(), 1);
+            let err = errs.into_iter().next().unwrap().unwrap_err();
+            assert_eq!(err.path(), root.join("locked"));
+            assert_eq!(err.error().raw_os_error(), Some(libc::EACCES));
+            assert_eq!(
+                err.to_string(),
+                format!("{}: {}", root.join("locked").display(), err.error())
+            );
+
+            assert_eq!(
+                FileCapsScanner::new(&root)
+                    .unwrap()
+                    .skip_errors(true)
+                    .count(),
+                0
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
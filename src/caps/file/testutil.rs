@@ -0,0 +1,26 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A temporary directory that is removed (along with its contents) when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty temporary directory, with a name based on `name` and the process ID.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("capctl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
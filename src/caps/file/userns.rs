@@ -0,0 +1,296 @@
+use std::fs;
+use std::io;
+
+use super::FileCaps;
+
+/// A single line of a user namespace's UID map (see `user_namespaces(7)`).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct UidMapEntry {
+    /// The first UID of the range inside the namespace.
+    pub inside: libc::uid_t,
+    /// The first UID of the range outside the namespace.
+    pub outside: libc::uid_t,
+    /// The length of the range.
+    pub count: u32,
+}
+
+/// The UID map of a user namespace, as read from `/proc/<pid>/uid_map`.
+///
+/// This is used to translate the root ID of version 3 file capabilities (see
+/// [`FileCaps::rootid`]) between the "host" (the user namespace of the process that read the map)
+/// and the namespace.
+///
+/// Note: If the map is read by a process in the same user namespace as `pid`, the "outside" IDs
+/// refer to the parent namespace. Otherwise, they refer to the namespace of the reading process.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct UidMap {
+    entries: Vec<UidMapEntry>,
+}
+
+impl UidMap {
+    /// Construct a UID map from the given entries.
+    #[inline]
+    pub fn new(entries: Vec<UidMapEntry>) -> Self {
+        Self { entries }
+    }
+
+    /// Get the UID map of the user namespace of the process with the given PID.
+    ///
+    /// If `pid` is 0, this gets the UID map of the current process's user namespace.
+    pub fn get_for_pid(pid: libc::pid_t) -> io::Result<Self> {
+        let path = match pid.cmp(&0) {
+            core::cmp::Ordering::Less => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            core::cmp::Ordering::Equal => "/proc/self/uid_map".into(),
+            core::cmp::Ordering::Greater => format!("/proc/{}/uid_map", pid),
+        };
+
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+                Err(io::Error::from_raw_os_error(libc::ESRCH))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parse a UID map in the format of `/proc/<pid>/uid_map`.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let parse_id = |s: Option<&str>| {
+            s.and_then(|s| s.parse().ok())
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+        };
+
+        let mut entries = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.split_whitespace();
+            entries.push(UidMapEntry {
+                inside: parse_id(fields.next())?,
+                outside: parse_id(fields.next())?,
+                count: parse_id(fields.next())?,
+            });
+
+            if fields.next().is_some() {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Get the entries in this map.
+    #[inline]
+    pub fn entries(&self) -> &[UidMapEntry] {
+        &self.entries
+    }
+
+    /// Check whether this is an identity mapping of all UIDs (as in the initial user namespace).
+    pub fn is_identity(&self) -> bool {
+        self.entries
+            == [UidMapEntry {
+                inside: 0,
+                outside: 0,
+                count: u32::MAX,
+            }]
+    }
+
+    /// Translate a UID inside the namespace to the corresponding UID outside it.
+    ///
+    /// Returns `None` if the UID is not mapped.
+    pub fn to_host(&self, uid: libc::uid_t) -> Option<libc::uid_t> {
+        self.entries.iter().find_map(|entry| {
+            let offset = uid.checked_sub(entry.inside)?;
+            if offset < entry.count {
+                entry.outside.checked_add(offset)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Translate a UID outside the namespace to the corresponding UID inside it.
+    ///
+    /// Returns `None` if the UID is not mapped.
+    pub fn to_namespace(&self, uid: libc::uid_t) -> Option<libc::uid_t> {
+        self.entries.iter().find_map(|entry| {
+            let offset = uid.checked_sub(entry.outside)?;
+            if offset < entry.count {
+                entry.inside.checked_add(offset)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl FileCaps {
+    /// Check whether these file capabilities (as stored on the host) would be honored when a
+    /// program is executed by a process in the user namespace described by `map`.
+    ///
+    /// Version 2 file capabilities (and version 3 capabilities with a root ID of 0) are honored
+    /// in all namespaces. Version 3 file capabilities are honored if the root ID is the host UID
+    /// that is mapped to root in the namespace.
+    ///
+    /// Note: The kernel also honors file capabilities whose root ID is mapped to root in any
+    /// *intermediate* user namespace between the host and the target namespace. Since the UID
+    /// maps of those namespaces are not available, they are not considered here.
+    pub fn is_honored_in(&self, map: &UidMap) -> bool {
+        match self.rootid {
+            None | Some(0) => true,
+            Some(rootid) => map.to_host(0) == Some(rootid),
+        }
+    }
+
+    /// Convert these file capabilities, as seen by a process in the namespace described by `map`,
+    /// to the form in which they are stored on the host.
+    ///
+    /// This mirrors what the kernel does when a process inside the namespace sets file
+    /// capabilities. Version 2 capabilities are converted to version 3 capabilities whose root ID
+    /// is the host UID that is mapped to root in the namespace (or left as version 2 if that is 0),
+    /// and the root ID of version 3 capabilities is translated to the corresponding host UID.
+    ///
+    /// Use this to write file capabilities on the host that will be honored inside a container.
+    /// This fails with `EINVAL` if the relevant UID is not mapped.
+    pub fn from_namespace(&self, map: &UidMap) -> io::Result<Self> {
+        let rootid = map
+            .to_host(self.rootid.unwrap_or(0))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        Ok(Self {
+            rootid: if rootid == 0 { None } else { Some(rootid) },
+            ..*self
+        })
+    }
+
+    /// Convert these file capabilities, as stored on the host, to the form in which they would
+    /// be seen by a process in the namespace described by `map`.
+    ///
+    /// This mirrors what the kernel does when a process inside the namespace reads file
+    /// capabilities. Version 3 capabilities whose root ID is mapped to root in the namespace are
+    /// converted to version 2 capabilities, and other root IDs are translated into the
+    /// namespace. Version 2 capabilities are returned unchanged.
+    ///
+    /// Returns `None` if the root ID is not mapped into the namespace.
+    pub fn to_namespace(&self, map: &UidMap) -> Option<Self> {
+        let rootid = match self.rootid {
+            None => return Some(*self),
+            Some(rootid) => map.to_namespace(rootid)?,
+        };
+
+        Some(Self {
+            rootid: if rootid == 0 { None } else { Some(rootid) },
+            ..*self
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::Cap;
+    use crate::capset;
+
+    fn container_map() -> UidMap {
+        UidMap::parse("         0     100000      65536\n     65536       1000          1\n")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_uid_map_parse() {
+        assert_eq!(
+            container_map().entries(),
+            &[
+                UidMapEntry {
+                    inside: 0,
+                    outside: 100000,
+                    count: 65536,
+                },
+                UidMapEntry {
+                    inside: 65536,
+                    outside: 1000,
+                    count: 1,
+                },
+            ]
+        );
+
+        assert!(UidMap::parse("0 0 4294967295\n").unwrap().is_identity());
+        assert!(!container_map().is_identity());
+        assert_eq!(UidMap::parse("").unwrap(), UidMap::new(vec![]));
+
+        for text in ["0 0", "0 0 1 1", "a 0 1", "0 0 -1"].iter() {
+            assert_eq!(
+                UidMap::parse(text).unwrap_err().raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+
+        UidMap::get_for_pid(0).unwrap();
+        assert_eq!(
+            UidMap::get_for_pid(-1).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            UidMap::get_for_pid(libc::pid_t::MAX)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ESRCH)
+        );
+    }
+
+    #[test]
+    fn test_uid_map_translate() {
+        let map = container_map();
+
+        assert_eq!(map.to_host(0), Some(100000));
+        assert_eq!(map.to_host(65535), Some(165535));
+        assert_eq!(map.to_host(65536), Some(1000));
+        assert_eq!(map.to_host(65537), None);
+
+        assert_eq!(map.to_namespace(100000), Some(0));
+        assert_eq!(map.to_namespace(1000), Some(65536));
+        assert_eq!(map.to_namespace(0), None);
+        assert_eq!(map.to_namespace(99999), None);
+    }
+
+    #[test]
+    fn test_filecaps_userns() {
+        let map = container_map();
+        let identity = UidMap::parse("0 0 4294967295").unwrap();
+
+        let mut v2 = FileCaps::empty();
+        v2.permitted = capset!(Cap::NET_BIND_SERVICE);
+        v2.effective = true;
+
+        let v3 = v2.from_namespace(&map).unwrap();
+        assert_eq!(v3.rootid, Some(100000));
+        assert_eq!(v3.to_namespace(&map), Some(v2));
+        assert!(v3.is_honored_in(&map));
+        assert!(!v3.is_honored_in(&identity));
+        assert_eq!(v3.to_namespace(&identity), Some(v3));
+
+        assert!(v2.is_honored_in(&map));
+        assert_eq!(v2.from_namespace(&identity).unwrap(), v2);
+        assert_eq!(v2.to_namespace(&map), Some(v2));
+
+        // A root ID that is mapped, but not to root
+        let mut other = v2;
+        other.rootid = Some(65536);
+        assert_eq!(other.from_namespace(&map).unwrap().rootid, Some(1000));
+        other.rootid = Some(1000);
+        assert!(!other.is_honored_in(&map));
+        assert_eq!(other.to_namespace(&map).unwrap().rootid, Some(65536));
+
+        // Unmapped
+        other.rootid = Some(5);
+        assert_eq!(other.to_namespace(&map), None);
+        other.rootid = Some(70000);
+        assert_eq!(
+            other.from_namespace(&map).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+}
@@ -1,8 +1,7 @@
-use std::fs;
 use std::io;
-use std::io::prelude::*;
+use std::os::unix::io::BorrowedFd;
 
-use super::{ambient, bounding, CapSet, CapState};
+use super::{ambient, bounding, CapSet, CapState, Plan};
 
 /// Represents the "full" capability state of a thread (i.e. the contents of all 5 capability
 /// sets and some additional information).
@@ -55,60 +54,83 @@ impl FullCapState {
     ///
     /// If `pid` is 0, this method gets the capability state of the current thread.
     pub fn get_for_pid(pid: libc::pid_t) -> io::Result<Self> {
-        let file_res = match pid.cmp(&0) {
+        let path = match pid.cmp(&0) {
             core::cmp::Ordering::Less => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
-            core::cmp::Ordering::Equal => fs::File::open("/proc/thread-self/status"),
-            core::cmp::Ordering::Greater => fs::File::open(format!("/proc/{}/status", pid)),
+            core::cmp::Ordering::Equal => "/proc/thread-self/status".into(),
+            core::cmp::Ordering::Greater => format!("/proc/{}/status", pid),
         };
 
-        let f = match file_res {
-            Ok(f) => f,
-            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
-                return Err(io::Error::from_raw_os_error(libc::ESRCH));
-            }
-            Err(e) => return Err(e),
-        };
+        let mut res = Self::empty();
+        crate::procs::read_status(path.as_ref(), |key, value| {
+            res.parse_status_field(key, value)
+        })?;
+        Ok(res)
+    }
 
-        let mut reader = io::BufReader::new(f);
-        let mut line = String::new();
+    /// Get the full capability state of the process referred to by the given pidfd.
+    ///
+    /// Unlike [`get_for_pid()`](#method.get_for_pid), this is immune to PID reuse: the files in
+    /// `/proc` are opened relative to the `/proc/<pid>` directory of the process, after verifying
+    /// that the process referred to by `pidfd` is still alive. This fails with `ESRCH` if the
+    /// process has exited.
+    ///
+    /// This requires Linux 5.4 or newer.
+    pub fn get_for_pidfd(pidfd: BorrowedFd) -> io::Result<Self> {
+        let dir = crate::procs::open_pidfd_dir(pidfd)?;
 
         let mut res = Self::empty();
+        crate::procs::read_status_at(&dir, |key, value| res.parse_status_field(key, value))?;
+        Ok(res)
+    }
 
-        while reader.read_line(&mut line)? > 0 {
-            if line.ends_with('\n') {
-                line.pop();
-            }
-
-            if let Some(i) = line.find(":\t") {
-                let value = &line[i + 2..];
-
-                let set = match &line[..i] {
-                    "CapPrm" => Some(&mut res.permitted),
-                    "CapEff" => Some(&mut res.effective),
-                    "CapInh" => Some(&mut res.inheritable),
-                    "CapBnd" => Some(&mut res.bounding),
-                    "CapAmb" => Some(&mut res.ambient),
-                    "NoNewPrivs" => {
-                        res.no_new_privs = value == "1";
-                        None
-                    }
-                    _ => None,
-                };
-
-                if let Some(set) = set {
-                    match u64::from_str_radix(value, 16) {
-                        Ok(bitmask) => *set = CapSet::from_bitmask_truncate(bitmask),
-                        Err(e) => {
-                            return Err(io::Error::new(io::ErrorKind::Other, e.to_string()));
-                        }
-                    }
-                }
+    /// Update this state from a single `key: value` line of a `/proc/<pid>/status` file.
+    ///
+    /// Fields not related to capabilities are ignored.
+    pub(crate) fn parse_status_field(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let set = match key {
+            "CapPrm" => &mut self.permitted,
+            "CapEff" => &mut self.effective,
+            "CapInh" => &mut self.inheritable,
+            "CapBnd" => &mut self.bounding,
+            "CapAmb" => &mut self.ambient,
+            "NoNewPrivs" => {
+                self.no_new_privs = value == "1";
+                return Ok(());
             }
+            _ => return Ok(()),
+        };
 
-            line.clear();
+        match u64::from_str_radix(value, 16) {
+            Ok(bitmask) => *set = CapSet::from_bitmask_truncate(bitmask),
+            Err(e) => return Err(io::Error::other(e.to_string())),
         }
 
-        Ok(res)
+        Ok(())
+    }
+
+    /// Compute the steps that [`set_current()`](#method.set_current) would perform to change the
+    /// current thread's capabilities to match this state, without changing anything.
+    ///
+    /// The returned [`Plan`] also lists any reasons why the change is impossible (for example, if
+    /// a capability would have to be added to the permitted set).
+    #[inline]
+    pub fn plan_set_current(&self) -> io::Result<Plan> {
+        Plan::for_target(self)
+    }
+
+    /// Change the current thread's capabilities to match this state.
+    ///
+    /// This changes the bounding, permitted, effective, inheritable, and ambient sets, and the "no
+    /// new privileges" flag, in the order the kernel requires; see [`Plan`] for details. Any
+    /// capabilities that this library is not aware of are removed from all of the sets.
+    ///
+    /// If the change is impossible, this fails with `EPERM` without changing anything. If one of
+    /// the steps fails partway through, the error is returned and the thread's capabilities may
+    /// have been partially changed.
+    #[inline]
+    pub fn set_current(&self) -> io::Result<()> {
+        self.plan_set_current()?.apply()?;
+        Ok(())
     }
 }
 
@@ -116,6 +138,8 @@ impl FullCapState {
 mod tests {
     use super::*;
 
+    use std::os::unix::io::{AsFd, FromRawFd};
+
     #[test]
     fn test_get_current_proc() {
         assert_eq!(
@@ -130,6 +154,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_for_pidfd() {
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) } as i32;
+        if pidfd < 0 {
+            return;
+        }
+        let pidfd = unsafe { std::os::unix::io::OwnedFd::from_raw_fd(pidfd) };
+
+        assert_eq!(
+            FullCapState::get_for_pidfd(pidfd.as_fd()).unwrap(),
+            FullCapState::get_for_pid(unsafe { libc::getpid() }).unwrap(),
+        );
+
+        // Not a pidfd
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert_eq!(
+            FullCapState::get_for_pidfd(file.as_fd())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        // The process exited
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id(), 0) } as i32;
+        assert!(pidfd >= 0);
+        let pidfd = unsafe { std::os::unix::io::OwnedFd::from_raw_fd(pidfd) };
+        child.wait().unwrap();
+        assert_eq!(
+            FullCapState::get_for_pidfd(pidfd.as_fd())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ESRCH)
+        );
+    }
+
     #[test]
     fn test_get_invalid_pid() {
         assert_eq!(
@@ -0,0 +1,90 @@
+//! Predefined groups of related capabilities.
+//!
+//! These groups are curated by this library. Since opinions on (for example) which capabilities
+//! are "root-equivalent" can change as the kernel evolves, each change to the contents of these
+//! groups (or to [`Cap::risk_level()`]) is accompanied by an increment of [`VERSION`]. Policy
+//! checks that depend on the exact contents of these groups can compare against it to detect
+//! changes.
+
+use super::{Cap, CapSet};
+use crate::capset;
+
+/// The version of the groups defined in this module (and of [`Cap::risk_level()`]).
+pub const VERSION: u32 = 1;
+
+/// Capabilities that can be used to gain full root privileges.
+///
+/// These are exactly the capabilities whose [`risk_level()`](super::Cap::risk_level) is
+/// [`RiskLevel::Critical`](super::RiskLevel::Critical).
+pub const ROOT_EQUIVALENT: CapSet = capset!(
+    Cap::CHOWN,
+    Cap::DAC_OVERRIDE,
+    Cap::FOWNER,
+    Cap::SETGID,
+    Cap::SETUID,
+    Cap::SYS_MODULE,
+    Cap::SYS_RAWIO,
+    Cap::SYS_PTRACE,
+    Cap::SYS_ADMIN,
+    Cap::SYS_BOOT,
+    Cap::MKNOD,
+    Cap::SETFCAP,
+);
+
+/// Capabilities that grant privileges related to networking.
+pub const NETWORK: CapSet = capset!(
+    Cap::NET_BIND_SERVICE,
+    Cap::NET_BROADCAST,
+    Cap::NET_ADMIN,
+    Cap::NET_RAW,
+);
+
+/// Capabilities that bypass filesystem permission checks or grant privileged filesystem
+/// operations.
+pub const FILESYSTEM: CapSet = capset!(
+    Cap::CHOWN,
+    Cap::DAC_OVERRIDE,
+    Cap::DAC_READ_SEARCH,
+    Cap::FOWNER,
+    Cap::FSETID,
+    Cap::LINUX_IMMUTABLE,
+    Cap::MKNOD,
+    Cap::LEASE,
+    Cap::SETFCAP,
+);
+
+/// Capabilities that grant the ability to observe other processes or the kernel.
+pub const OBSERVABILITY: CapSet = capset!(
+    Cap::SYS_PTRACE,
+    Cap::SYSLOG,
+    Cap::AUDIT_READ,
+    Cap::PERFMON,
+    Cap::BPF,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::RiskLevel;
+
+    #[test]
+    fn test_root_equivalent() {
+        for cap in Cap::iter() {
+            assert_eq!(
+                ROOT_EQUIVALENT.has(cap),
+                cap.risk_level() == RiskLevel::Critical,
+                "{:?}",
+                cap
+            );
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(NETWORK.size(), 4);
+        assert!(FILESYSTEM.has(Cap::DAC_READ_SEARCH));
+        assert!(OBSERVABILITY.has(Cap::PERFMON));
+        assert!((NETWORK & FILESYSTEM).is_empty());
+    }
+}
@@ -8,6 +8,8 @@ use super::{Cap, CapState};
 /// WARNING: This function only operates on the current **thread**, not the process as a whole. This is
 /// because of the way Linux operates. If you call this function from a multithreaded program, you
 /// are responsible for synchronizing changes across threads as necessary to ensure proper security.
+/// (See [`threads::cap_set_ids()`](crate::threads::cap_set_ids) for a version that operates on
+/// every thread in the process.)
 ///
 /// This function performs the following actions in order. (Note: If `gid` is not `None` or
 /// `groups` is not `None`, CAP_SETGID will first be raised in the thread's effective set, and if
@@ -0,0 +1,471 @@
+use core::fmt;
+
+use super::Cap;
+
+/// A Linux kernel version (for example, `2.6.24`).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct KernelVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl KernelVersion {
+    /// Construct a new `KernelVersion`.
+    #[inline]
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Get the version of the running kernel (as reported by `uname()`).
+    ///
+    /// Returns `None` if the version could not be determined.
+    pub fn running() -> Option<Self> {
+        let mut uts = unsafe { core::mem::zeroed::<libc::utsname>() };
+        if unsafe { libc::uname(&mut uts) } < 0 {
+            return None;
+        }
+
+        let len = uts.release.iter().position(|&ch| ch == 0)?;
+        let release =
+            unsafe { core::slice::from_raw_parts(uts.release.as_ptr() as *const u8, len) };
+
+        Self::parse_release(core::str::from_utf8(release).ok()?)
+    }
+
+    /// Parse the version from a kernel release string like `5.15.0-91-generic`.
+    fn parse_release(release: &str) -> Option<Self> {
+        // Strip any suffix (like "-91-generic" or "+")
+        let end = release
+            .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+            .unwrap_or(release.len());
+        let mut parts = release[..end].split('.');
+
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = match parts.next() {
+            Some(patch) => patch.parse().ok()?,
+            None => 0,
+        };
+
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.patch != 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// A rough classification of how dangerous it is to grant a capability.
+///
+/// See [`Cap::risk_level()`]. These levels are ordered, so they can be compared (for example,
+/// `cap.risk_level() >= RiskLevel::High`).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RiskLevel {
+    /// Grants narrowly scoped privileges that are unlikely to be abused.
+    Low,
+    /// Grants privileges that can be abused to interfere with other processes or the system, but
+    /// not (on their own) to gain full privileges.
+    Medium,
+    /// Grants broad privileges, or privileges that can expose sensitive information or weaken
+    /// security mechanisms.
+    High,
+    /// Can be used to gain full root privileges (see
+    /// [`groups::ROOT_EQUIVALENT`](./groups/constant.ROOT_EQUIVALENT.html)).
+    Critical,
+}
+
+impl fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        })
+    }
+}
+
+impl Cap {
+    /// Get a one-line description of this capability.
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::CHOWN => "Make arbitrary changes to file UIDs and GIDs",
+            Self::DAC_OVERRIDE => "Bypass file read, write, and execute permission checks",
+            Self::DAC_READ_SEARCH => {
+                "Bypass file read and directory read and execute permission checks"
+            }
+            Self::FOWNER => {
+                "Bypass permission checks on operations that require the file's UID to match"
+            }
+            Self::FSETID => "Keep set-user-ID and set-group-ID bits when modifying files",
+            Self::KILL => "Bypass permission checks for sending signals",
+            Self::SETGID => "Make arbitrary manipulations of process GIDs and supplementary groups",
+            Self::SETUID => "Make arbitrary manipulations of process UIDs",
+            Self::SETPCAP => "Modify the bounding set, inheritable set, and securebits flags",
+            Self::LINUX_IMMUTABLE => "Set the immutable and append-only file attributes",
+            Self::NET_BIND_SERVICE => "Bind a socket to a privileged port (below 1024)",
+            Self::NET_BROADCAST => "Make socket broadcasts and listen to multicasts (unused)",
+            Self::NET_ADMIN => "Perform various network administration operations",
+            Self::NET_RAW => "Use RAW and PACKET sockets",
+            Self::IPC_LOCK => "Lock memory",
+            Self::IPC_OWNER => "Bypass permission checks for operations on System V IPC objects",
+            Self::SYS_MODULE => "Load and unload kernel modules",
+            Self::SYS_RAWIO => "Perform I/O port operations and access raw devices",
+            Self::SYS_CHROOT => "Use chroot() and change mount namespaces with setns()",
+            Self::SYS_PTRACE => "Trace and inspect arbitrary processes",
+            Self::SYS_PACCT => "Use acct() to configure process accounting",
+            Self::SYS_ADMIN => "Perform a wide range of system administration operations",
+            Self::SYS_BOOT => "Reboot the system and load new kernels",
+            Self::SYS_NICE => "Raise process priorities and change scheduling for any process",
+            Self::SYS_RESOURCE => "Override resource limits and quotas",
+            Self::SYS_TIME => "Set the system clock and the real-time (hardware) clock",
+            Self::SYS_TTY_CONFIG => "Use vhangup() and privileged ioctl()s on virtual terminals",
+            Self::MKNOD => "Create special files using mknod()",
+            Self::LEASE => "Establish leases on arbitrary files",
+            Self::AUDIT_WRITE => "Write records to the kernel auditing log",
+            Self::AUDIT_CONTROL => "Configure kernel auditing and change audit filter rules",
+            Self::SETFCAP => "Set capabilities on files and map UID 0 in user namespaces",
+            Self::MAC_OVERRIDE => "Override Mandatory Access Control (MAC) policies",
+            Self::MAC_ADMIN => "Configure Mandatory Access Control (MAC) policies",
+            Self::SYSLOG => "Perform privileged syslog() operations and view kernel addresses",
+            Self::WAKE_ALARM => "Trigger something that will wake up the system",
+            Self::BLOCK_SUSPEND => "Employ features that can block system suspend",
+            Self::AUDIT_READ => "Read the audit log via a multicast netlink socket",
+            Self::PERFMON => "Use performance monitoring and observability features",
+            Self::BPF => "Use privileged BPF operations",
+            Self::CHECKPOINT_RESTORE => "Use checkpoint/restore functionality",
+        }
+    }
+
+    /// Get the kernel version in which this capability was introduced.
+    ///
+    /// This can be compared with [`KernelVersion::running()`] to explain why a capability is not
+    /// [supported](#method.is_supported) (though note that distributions sometimes backport
+    /// capabilities to older kernels).
+    pub const fn introduced_in(self) -> KernelVersion {
+        match self {
+            Self::CHOWN
+            | Self::DAC_OVERRIDE
+            | Self::DAC_READ_SEARCH
+            | Self::FOWNER
+            | Self::FSETID
+            | Self::KILL
+            | Self::SETGID
+            | Self::SETUID
+            | Self::SETPCAP
+            | Self::LINUX_IMMUTABLE
+            | Self::NET_BIND_SERVICE
+            | Self::NET_BROADCAST
+            | Self::NET_ADMIN
+            | Self::NET_RAW
+            | Self::IPC_LOCK
+            | Self::IPC_OWNER
+            | Self::SYS_MODULE
+            | Self::SYS_RAWIO
+            | Self::SYS_CHROOT
+            | Self::SYS_PTRACE
+            | Self::SYS_PACCT
+            | Self::SYS_ADMIN
+            | Self::SYS_BOOT
+            | Self::SYS_NICE
+            | Self::SYS_RESOURCE
+            | Self::SYS_TIME
+            | Self::SYS_TTY_CONFIG => KernelVersion::new(2, 2, 0),
+            Self::MKNOD | Self::LEASE => KernelVersion::new(2, 4, 0),
+            Self::AUDIT_WRITE | Self::AUDIT_CONTROL => KernelVersion::new(2, 6, 11),
+            Self::SETFCAP => KernelVersion::new(2, 6, 24),
+            Self::MAC_OVERRIDE | Self::MAC_ADMIN => KernelVersion::new(2, 6, 25),
+            Self::SYSLOG => KernelVersion::new(2, 6, 37),
+            Self::WAKE_ALARM => KernelVersion::new(3, 0, 0),
+            Self::BLOCK_SUSPEND => KernelVersion::new(3, 5, 0),
+            Self::AUDIT_READ => KernelVersion::new(3, 16, 0),
+            Self::PERFMON | Self::BPF => KernelVersion::new(5, 8, 0),
+            Self::CHECKPOINT_RESTORE => KernelVersion::new(5, 9, 0),
+        }
+    }
+
+    /// Get the risk level of this capability.
+    ///
+    /// This classification is curated by this library and may be refined in future releases; see
+    /// [`groups::VERSION`](./groups/constant.VERSION.html).
+    pub const fn risk_level(self) -> RiskLevel {
+        match self {
+            Self::CHOWN
+            | Self::DAC_OVERRIDE
+            | Self::FOWNER
+            | Self::SETGID
+            | Self::SETUID
+            | Self::SYS_MODULE
+            | Self::SYS_RAWIO
+            | Self::SYS_PTRACE
+            | Self::SYS_ADMIN
+            | Self::SYS_BOOT
+            | Self::MKNOD
+            | Self::SETFCAP => RiskLevel::Critical,
+
+            Self::DAC_READ_SEARCH
+            | Self::SETPCAP
+            | Self::NET_ADMIN
+            | Self::AUDIT_CONTROL
+            | Self::MAC_OVERRIDE
+            | Self::MAC_ADMIN
+            | Self::PERFMON
+            | Self::BPF
+            | Self::CHECKPOINT_RESTORE => RiskLevel::High,
+
+            Self::FSETID
+            | Self::KILL
+            | Self::LINUX_IMMUTABLE
+            | Self::NET_RAW
+            | Self::IPC_OWNER
+            | Self::SYS_CHROOT
+            | Self::SYS_PACCT
+            | Self::SYS_NICE
+            | Self::SYS_RESOURCE
+            | Self::SYS_TIME
+            | Self::SYS_TTY_CONFIG
+            | Self::LEASE
+            | Self::SYSLOG => RiskLevel::Medium,
+
+            Self::NET_BIND_SERVICE
+            | Self::NET_BROADCAST
+            | Self::IPC_LOCK
+            | Self::AUDIT_WRITE
+            | Self::WAKE_ALARM
+            | Self::BLOCK_SUSPEND
+            | Self::AUDIT_READ => RiskLevel::Low,
+        }
+    }
+
+    /// Get a summary of the main privileged operations that this capability grants, as listed in
+    /// capabilities(7).
+    pub const fn operations(self) -> &'static [&'static str] {
+        match self {
+            Self::CHOWN => &["Change the owner and group of arbitrary files (chown())"],
+            Self::DAC_OVERRIDE => &["Bypass file read, write, and execute permission checks"],
+            Self::DAC_READ_SEARCH => &[
+                "Bypass file read permission checks",
+                "Bypass directory read and execute permission checks",
+                "Open files by handle (open_by_handle_at())",
+            ],
+            Self::FOWNER => &[
+                "Bypass permission checks on operations that require the file's UID to match",
+                "Set inode flags on arbitrary files",
+                "Set ACLs on arbitrary files",
+                "Ignore the sticky bit when deleting files",
+            ],
+            Self::FSETID => &[
+                "Keep the set-user-ID and set-group-ID bits when a file is modified",
+                "Set the set-group-ID bit on files whose GID does not match",
+            ],
+            Self::KILL => &["Bypass permission checks for sending signals"],
+            Self::SETGID => &[
+                "Make arbitrary manipulations of process GIDs and supplementary groups",
+                "Forge GIDs when passing socket credentials",
+                "Write a group ID mapping in a user namespace",
+            ],
+            Self::SETUID => &[
+                "Make arbitrary manipulations of process UIDs",
+                "Forge UIDs when passing socket credentials",
+                "Write a user ID mapping in a user namespace",
+            ],
+            Self::SETPCAP => &[
+                "Add capabilities from the bounding set to the inheritable set",
+                "Drop capabilities from the bounding set",
+                "Change the securebits flags",
+            ],
+            Self::LINUX_IMMUTABLE => &["Set the immutable and append-only inode flags"],
+            Self::NET_BIND_SERVICE => &["Bind a socket to a privileged port (below 1024)"],
+            Self::NET_BROADCAST => &["Make socket broadcasts and listen to multicasts (unused)"],
+            Self::NET_ADMIN => &[
+                "Configure network interfaces",
+                "Administer IP firewalls, masquerading, and accounting",
+                "Modify routing tables",
+                "Set privileged socket options",
+            ],
+            Self::NET_RAW => &[
+                "Use RAW and PACKET sockets",
+                "Bind to any address for transparent proxying",
+            ],
+            Self::IPC_LOCK => &[
+                "Lock memory (mlock(), mlockall(), mmap(), shmctl())",
+                "Allocate memory using huge pages",
+            ],
+            Self::IPC_OWNER => &["Bypass permission checks for operations on System V IPC objects"],
+            Self::SYS_MODULE => &[
+                "Load and unload kernel modules",
+                "Drop capabilities from the system-wide bounding set",
+            ],
+            Self::SYS_RAWIO => &[
+                "Perform I/O port operations (iopl(), ioperm())",
+                "Access /proc/kcore",
+                "Open devices for raw access",
+            ],
+            Self::SYS_CHROOT => &[
+                "Use chroot()",
+                "Change mount namespaces using setns()",
+            ],
+            Self::SYS_PTRACE => &[
+                "Trace arbitrary processes using ptrace()",
+                "Transfer data to or from the memory of arbitrary processes",
+                "Inspect processes using kcmp()",
+            ],
+            Self::SYS_PACCT => &["Use acct()"],
+            Self::SYS_ADMIN => &[
+                "Perform mount(), umount(), and other filesystem administration operations",
+                "Perform various privileged namespace operations",
+                "Set trusted and security extended attributes",
+                "Perform many other administrative operations (see capabilities(7))",
+            ],
+            Self::SYS_BOOT => &[
+                "Use reboot()",
+                "Load new kernels for later execution (kexec_load())",
+            ],
+            Self::SYS_NICE => &[
+                "Lower the nice value of processes and change the nice value of arbitrary processes",
+                "Set real-time scheduling policies and priorities",
+                "Set CPU affinity and I/O scheduling for arbitrary processes",
+            ],
+            Self::SYS_RESOURCE => &[
+                "Use reserved space on ext2 filesystems",
+                "Override disk quota and resource limits",
+                "Raise the message queue and pipe size limits",
+            ],
+            Self::SYS_TIME => &[
+                "Set the system clock (settimeofday(), stime(), adjtimex())",
+                "Set the real-time (hardware) clock",
+            ],
+            Self::SYS_TTY_CONFIG => &[
+                "Use vhangup()",
+                "Employ various privileged ioctl() operations on virtual terminals",
+            ],
+            Self::MKNOD => &["Create special files using mknod()"],
+            Self::LEASE => &["Establish leases on arbitrary files"],
+            Self::AUDIT_WRITE => &["Write records to the kernel auditing log"],
+            Self::AUDIT_CONTROL => &[
+                "Enable and disable kernel auditing",
+                "Change auditing filter rules",
+                "Retrieve auditing status and filtering rules",
+            ],
+            Self::SETFCAP => &[
+                "Set arbitrary capabilities on a file",
+                "Map UID 0 when writing a user namespace UID mapping",
+            ],
+            Self::MAC_OVERRIDE => &["Override Mandatory Access Control (MAC) policies"],
+            Self::MAC_ADMIN => &["Change Mandatory Access Control (MAC) configuration"],
+            Self::SYSLOG => &[
+                "Perform privileged syslog() operations",
+                "View kernel addresses exposed in /proc and other interfaces",
+            ],
+            Self::WAKE_ALARM => &[
+                "Set CLOCK_REALTIME_ALARM and CLOCK_BOOTTIME_ALARM timers",
+            ],
+            Self::BLOCK_SUSPEND => &[
+                "Use EPOLLWAKEUP",
+                "Use /proc/sys/wake_lock",
+            ],
+            Self::AUDIT_READ => &["Read the audit log via a multicast netlink socket"],
+            Self::PERFMON => &[
+                "Use perf_event_open() and other performance monitoring operations",
+                "Use some BPF operations that have performance implications",
+            ],
+            Self::BPF => &[
+                "Use privileged BPF operations (for example, creating all map types)",
+                "Use advanced BPF program verifier features",
+            ],
+            Self::CHECKPOINT_RESTORE => &[
+                "Update /proc/sys/kernel/ns_last_pid",
+                "Use set_tid when calling clone3()",
+                "Read the contents of /proc/[pid]/map_files for other processes",
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_version() {
+        assert!(KernelVersion::new(2, 6, 24) < KernelVersion::new(2, 6, 25));
+        assert!(KernelVersion::new(2, 6, 39) < KernelVersion::new(3, 0, 0));
+        assert!(KernelVersion::new(5, 9, 0) > KernelVersion::new(5, 8, 10));
+
+        assert_eq!(
+            KernelVersion::parse_release("5.15.0-91-generic"),
+            Some(KernelVersion::new(5, 15, 0))
+        );
+        assert_eq!(
+            KernelVersion::parse_release("6.1"),
+            Some(KernelVersion::new(6, 1, 0))
+        );
+        assert_eq!(
+            KernelVersion::parse_release("4.19.128+"),
+            Some(KernelVersion::new(4, 19, 128))
+        );
+        assert_eq!(KernelVersion::parse_release("6"), None);
+        assert_eq!(KernelVersion::parse_release(""), None);
+
+        // Rust requires at least 2.6.32
+        assert!(KernelVersion::running().unwrap() >= KernelVersion::new(2, 6, 32));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_kernel_version_display() {
+        assert_eq!(KernelVersion::new(2, 6, 24).to_string(), "2.6.24");
+        assert_eq!(KernelVersion::new(5, 8, 0).to_string(), "5.8");
+    }
+
+    #[test]
+    fn test_cap_info() {
+        let running = KernelVersion::running().unwrap();
+
+        for cap in Cap::iter() {
+            assert!(!cap.description().is_empty());
+            assert!(!cap.operations().is_empty());
+
+            // If the kernel is new enough, the capability must be supported
+            if cap.introduced_in() <= running {
+                assert!(cap.is_supported(), "{:?}", cap);
+            }
+        }
+
+        assert_eq!(Cap::SETFCAP.introduced_in(), KernelVersion::new(2, 6, 24));
+        assert_eq!(Cap::CHOWN.introduced_in(), KernelVersion::new(2, 2, 0));
+        assert_eq!(Cap::MKNOD.introduced_in(), KernelVersion::new(2, 4, 0));
+        assert_eq!(Cap::BPF.introduced_in(), KernelVersion::new(5, 8, 0));
+
+        const DESC: &str = Cap::NET_RAW.description();
+        assert_eq!(DESC, "Use RAW and PACKET sockets");
+    }
+
+    #[test]
+    fn test_risk_level() {
+        assert!(RiskLevel::Low < RiskLevel::Medium);
+        assert!(RiskLevel::Medium < RiskLevel::High);
+        assert!(RiskLevel::High < RiskLevel::Critical);
+
+        assert_eq!(Cap::SYS_ADMIN.risk_level(), RiskLevel::Critical);
+        assert_eq!(Cap::BPF.risk_level(), RiskLevel::High);
+        assert_eq!(Cap::NET_RAW.risk_level(), RiskLevel::Medium);
+        assert_eq!(Cap::NET_BIND_SERVICE.risk_level(), RiskLevel::Low);
+
+        #[cfg(feature = "std")]
+        assert_eq!(RiskLevel::Critical.to_string(), "critical");
+    }
+}
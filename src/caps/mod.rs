@@ -6,25 +6,54 @@ mod cap_text;
 mod capset;
 mod capstate;
 mod helpers;
+mod info;
+mod scoped;
 
 #[cfg(feature = "serde")]
 mod serde_impl;
 
+#[cfg(feature = "std")]
+mod diff;
+#[cfg(feature = "std")]
+pub use diff::{CapSetDiff, CapStateDiff, FileCapsDiff, FullCapStateDiff};
+
 #[cfg(feature = "std")]
 mod file;
 #[cfg(feature = "std")]
-pub use file::{FileCaps, ParseFileCapsError};
+pub use file::{
+    AsDirFd, AtFlags, Cwd, ExecBlocker, ExecCheck, FileCaps, FileCapsManifest, FileCapsRevision,
+    FileCapsScanner, FileCapsWarning, ManifestDrift, ParseFileCapsError, ParseManifestError,
+    PaxCapsWriter, ScanError, UidMap, UidMapEntry, PAX_CAPS_KEY,
+};
 
 #[cfg(feature = "std")]
 mod fullcapstate;
 #[cfg(feature = "std")]
 pub use fullcapstate::FullCapState;
 
+#[cfg(feature = "std")]
+mod plan;
+#[cfg(feature = "std")]
+pub use plan::{Infeasibility, Plan, PlanStep};
+
+#[cfg(feature = "std")]
+mod privdrop;
+#[cfg(feature = "std")]
+pub use privdrop::PrivDrop;
+
+#[cfg(feature = "std")]
+mod transition;
+#[cfg(feature = "std")]
+pub use transition::{simulate_execve, simulate_setuid};
+
 pub mod ambient;
 pub mod bounding;
+pub mod groups;
 pub use capset::{CapSet, CapSetIterator};
-pub use capstate::{CapState, ParseCapStateError};
+pub use capstate::{CapState, ParseCapStateError, RawCapState};
 pub use helpers::cap_set_ids;
+pub use info::{KernelVersion, RiskLevel};
+pub use scoped::{with_effective, EffectiveGuard};
 
 /// Given a series of "paths" (i.e. `a::b`), yield the last one.
 macro_rules! last_path {
@@ -116,7 +145,7 @@ define_cap! {
     BPF = 39,
     CHECKPOINT_RESTORE = 40,
     // Adding a new capability here is sufficient to make the library aware of it (though the
-    // capability numbers MUST be consecutive)
+    // capability numbers MUST be consecutive, and its metadata must be added in info.rs)
 }
 
 impl Cap {
@@ -146,7 +175,11 @@ impl Cap {
     /// This uses a binary search combined with [`Cap::is_supported()`] to determine the supported
     /// capabilities. It is more efficient than a simple `Cap::iter()`/`Cap::is_supported()` loop.
     ///
+    /// If a capability is missing from the returned set, [`Cap::introduced_in()`] and
+    /// [`KernelVersion::running()`] can help explain why.
+    ///
     /// [`Cap::is_supported()`]: #method.is_supported
+    /// [`Cap::introduced_in()`]: #method.introduced_in
     pub fn probe_supported() -> CapSet {
         // Do a binary search
 
@@ -180,13 +213,21 @@ impl Cap {
     }
 }
 
+/// Strip the (case-insensitive) "CAP_" prefix from `s`, if it is present and followed by anything.
+fn strip_cap_prefix(s: &str) -> Option<&str> {
+    // Not s[..4], which panics if byte 4 isn't a char boundary
+    if s.len() > 4 && matches!(s.get(..4), Some(p) if p.eq_ignore_ascii_case("CAP_")) {
+        Some(&s[4..])
+    } else {
+        None
+    }
+}
+
 impl core::str::FromStr for Cap {
     type Err = ParseCapError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() > 4 && s[..4].eq_ignore_ascii_case("CAP_") {
-            let s = &s[4..];
-
+        if let Some(s) = strip_cap_prefix(s) {
             for (i, cap_name) in CAP_NAMES.iter().enumerate() {
                 if cap_name.eq_ignore_ascii_case(s) {
                     return Ok(Cap::from_u8(i as u8).unwrap());
@@ -205,6 +246,100 @@ impl fmt::Display for Cap {
     }
 }
 
+/// A capability identified by its number, which may or may not be one that this library is aware
+/// of.
+///
+/// Unlike [`Cap`], this can represent any of the 64 capabilities that the kernel's interfaces have
+/// room for, including ones added in kernels newer than this library (see [Handling of
+/// newly-added capabilities](../index.html#handling-of-newly-added-capabilities)). It is mainly
+/// useful in combination with [`RawCapState`].
+///
+/// # `FromStr` and `Display` implementations
+///
+/// Like `libcap`, `RawCap` can be parsed from either a capability name (for example,
+/// `CAP_CHOWN`) or a capability number (for example, `cap_41` or just `41`). Parsing is
+/// case-insensitive.
+///
+/// Capabilities that this library is aware of are displayed by name (like [`Cap`]), and ones that
+/// it is not aware of are displayed by number (for example, `CAP_41`).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RawCap(u8);
+
+impl RawCap {
+    /// Construct a `RawCap` from a capability number.
+    ///
+    /// Returns `None` if `num` is not in the range 0-63.
+    #[inline]
+    pub const fn new(num: u8) -> Option<Self> {
+        if num < 64 {
+            Some(Self(num))
+        } else {
+            None
+        }
+    }
+
+    /// Get the number of this capability.
+    #[inline]
+    pub const fn number(self) -> u8 {
+        self.0
+    }
+
+    /// Get the [`Cap`] that corresponds to this capability, or `None` if this library is not aware
+    /// of it.
+    #[inline]
+    pub fn to_cap(self) -> Option<Cap> {
+        Cap::from_u8(self.0)
+    }
+
+    /// Check whether this library is aware of this capability.
+    #[inline]
+    pub fn is_known(self) -> bool {
+        self.0 <= CAP_MAX
+    }
+
+    /// Get a bitmask with only the bit corresponding to this capability set.
+    ///
+    /// This is the representation used by [`RawCapState`].
+    #[inline]
+    pub const fn bitmask(self) -> u64 {
+        1u64 << self.0
+    }
+}
+
+impl From<Cap> for RawCap {
+    #[inline]
+    fn from(cap: Cap) -> Self {
+        Self(cap as u8)
+    }
+}
+
+impl core::str::FromStr for RawCap {
+    type Err = ParseCapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let num = strip_cap_prefix(s).unwrap_or(s);
+
+        if !num.is_empty() && num.bytes().all(|ch| ch.is_ascii_digit()) {
+            num.parse()
+                .ok()
+                .and_then(Self::new)
+                .ok_or(ParseCapError(()))
+        } else {
+            s.parse::<Cap>().map(Self::from)
+        }
+    }
+}
+
+impl fmt::Display for RawCap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_cap() {
+            Some(cap) => fmt::Display::fmt(&cap, f),
+            None => write!(f, "CAP_{}", self.0),
+        }
+    }
+}
+
 /// Represents an error when parsing a `Cap` from a string.
 #[derive(Clone, Eq, PartialEq)]
 pub struct ParseCapError(());
@@ -333,6 +468,8 @@ mod tests {
         assert!(Cap::from_str("CAP_").is_err());
         assert!(Cap::from_str("CHOWN").is_err());
         assert!(Cap::from_str("CAP_NOEXIST").is_err());
+        assert!(Cap::from_str("abcé").is_err());
+        assert!(Cap::from_str("éabc").is_err());
 
         #[cfg(feature = "std")]
         assert_eq!(Cap::CHOWN.to_string(), "CAP_CHOWN");
@@ -377,6 +514,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rawcap() {
+        for cap in Cap::iter() {
+            let raw = RawCap::from(cap);
+            assert_eq!(raw.number(), cap as u8);
+            assert_eq!(raw.to_cap(), Some(cap));
+            assert!(raw.is_known());
+            assert_eq!(raw.bitmask(), cap.to_single_bitfield());
+            assert_eq!(RawCap::new(cap as u8), Some(raw));
+        }
+
+        for i in NUM_CAPS..64 {
+            let raw = RawCap::new(i).unwrap();
+            assert_eq!(raw.number(), i);
+            assert_eq!(raw.to_cap(), None);
+            assert!(!raw.is_known());
+            assert_eq!(raw.bitmask(), 1 << i);
+        }
+
+        for i in 64..=u8::MAX {
+            assert_eq!(RawCap::new(i), None);
+        }
+    }
+
+    #[test]
+    fn test_rawcap_string() {
+        assert_eq!(RawCap::from_str("CAP_CHOWN"), Ok(RawCap::from(Cap::CHOWN)));
+        assert_eq!(
+            RawCap::from_str("cap_sys_chroot"),
+            Ok(RawCap::from(Cap::SYS_CHROOT))
+        );
+        assert_eq!(RawCap::from_str("cap_0"), Ok(RawCap::from(Cap::CHOWN)));
+        assert_eq!(RawCap::from_str("0"), Ok(RawCap::from(Cap::CHOWN)));
+        assert_eq!(
+            RawCap::from_str("CAP_63"),
+            RawCap::new(63).ok_or(ParseCapError(()))
+        );
+        assert_eq!(
+            RawCap::from_str("63"),
+            RawCap::new(63).ok_or(ParseCapError(()))
+        );
+
+        assert!(RawCap::from_str("").is_err());
+        assert!(RawCap::from_str("CAP_").is_err());
+        assert!(RawCap::from_str("CHOWN").is_err());
+        assert!(RawCap::from_str("CAP_NOEXIST").is_err());
+        assert!(RawCap::from_str("cap_64").is_err());
+        assert!(RawCap::from_str("cap_256").is_err());
+        assert!(RawCap::from_str("cap_+1").is_err());
+        assert!(RawCap::from_str("-1").is_err());
+        assert!(RawCap::from_str("abcé").is_err());
+        assert!(RawCap::from_str("éabc").is_err());
+
+        #[cfg(feature = "std")]
+        {
+            assert_eq!(RawCap::from(Cap::CHOWN).to_string(), "CAP_CHOWN");
+            assert_eq!(RawCap::new(63).unwrap().to_string(), "CAP_63");
+
+            for i in 0..64 {
+                let raw = RawCap::new(i).unwrap();
+                assert_eq!(RawCap::from_str(&raw.to_string()), Ok(raw));
+                assert_eq!(RawCap::from_str(&raw.to_string().to_lowercase()), Ok(raw));
+            }
+        }
+    }
+
     #[test]
     fn test_cap_iter_last() {
         assert_eq!(Cap::iter().last(), Some(LAST_CAP));
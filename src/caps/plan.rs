@@ -0,0 +1,498 @@
+use core::fmt;
+use std::io;
+
+use super::{ambient, bounding, Cap, CapState, FullCapState, RawCapState};
+use crate::prctl::Secbits;
+
+/// A single step in a [`Plan`].
+///
+/// Each step corresponds to one (or, for [`ClearUnknownBounding`](#variant.ClearUnknownBounding),
+/// a few) system calls that modify the current thread's capabilities.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum PlanStep {
+    /// Raise `CAP_SETPCAP` in the effective set so that capabilities can be dropped from the
+    /// bounding set.
+    RaiseSetpcap,
+    /// Drop the given capability from the bounding set.
+    DropBounding(Cap),
+    /// Drop capabilities that this library is not aware of from the bounding set (see
+    /// [`bounding::clear_unknown()`]).
+    ClearUnknownBounding,
+    /// Set the permitted, effective, and inheritable sets.
+    SetCapState(CapState),
+    /// Clear the ambient set.
+    ClearAmbient,
+    /// Raise the given capability in the ambient set.
+    RaiseAmbient(Cap),
+    /// Set the "no new privileges" flag.
+    SetNoNewPrivs,
+}
+
+impl PlanStep {
+    /// Perform this step on the current thread.
+    pub fn apply(&self) -> crate::Result<()> {
+        match *self {
+            Self::RaiseSetpcap => {
+                let mut raw = RawCapState::get_current()?;
+                raw.effective |= Cap::SETPCAP.to_single_bitfield();
+                raw.set_current()
+            }
+            Self::DropBounding(cap) => bounding::drop(cap),
+            Self::ClearUnknownBounding => bounding::clear_unknown(),
+            Self::SetCapState(state) => state.set_current(),
+            Self::ClearAmbient => ambient::clear(),
+            Self::RaiseAmbient(cap) => ambient::raise(cap),
+            Self::SetNoNewPrivs => crate::prctl::set_no_new_privs(),
+        }
+    }
+}
+
+/// A reason why a [`Plan`] cannot be carried out.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Infeasibility {
+    /// The given capability would have to be added to the bounding set, which is impossible.
+    RaiseBounding(Cap),
+    /// Capabilities have to be dropped from the bounding set, but `CAP_SETPCAP` is not in the
+    /// permitted set.
+    MissingSetpcap,
+    /// The given capability would have to be added to the permitted set, which is impossible.
+    RaisePermitted(Cap),
+    /// The given capability is in the new effective set, but not in the new permitted set.
+    EffectiveNotPermitted(Cap),
+    /// The given capability cannot be added to the inheritable set (it must be in the current
+    /// inheritable set, or in both the permitted set and the new bounding set).
+    RaiseInheritable(Cap),
+    /// Ambient capabilities are not supported by the running kernel.
+    AmbientUnsupported,
+    /// The given capability cannot be raised in the ambient set because it is not in both the new
+    /// permitted and inheritable sets.
+    AmbientNotPermitted(Cap),
+    /// Capabilities cannot be raised in the ambient set because the
+    /// [`Secbits::NO_CAP_AMBIENT_RAISE`] flag is set.
+    AmbientRaiseLocked,
+    /// The "no new privileges" flag is set and cannot be unset.
+    ClearNoNewPrivs,
+}
+
+impl fmt::Display for Infeasibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RaiseBounding(cap) => write!(f, "Cannot add {} to the bounding set", cap),
+            Self::MissingSetpcap => f.write_str(
+                "Cannot drop capabilities from the bounding set without CAP_SETPCAP permitted",
+            ),
+            Self::RaisePermitted(cap) => write!(f, "Cannot add {} to the permitted set", cap),
+            Self::EffectiveNotPermitted(cap) => {
+                write!(f, "{} is effective but not permitted", cap)
+            }
+            Self::RaiseInheritable(cap) => write!(f, "Cannot add {} to the inheritable set", cap),
+            Self::AmbientUnsupported => f.write_str("Ambient capabilities are not supported"),
+            Self::AmbientNotPermitted(cap) => {
+                write!(f, "{} is ambient but not permitted and inheritable", cap)
+            }
+            Self::AmbientRaiseLocked => {
+                f.write_str("Raising ambient capabilities is disabled by securebits")
+            }
+            Self::ClearNoNewPrivs => f.write_str("Cannot unset the no_new_privs flag"),
+        }
+    }
+}
+
+/// The current state of the thread, as needed to compute a plan.
+#[derive(Clone, Debug)]
+struct Environment {
+    current: FullCapState,
+    unknown_caps: bool,
+    unknown_bounding: bool,
+    unknown_ambient: bool,
+    ambient_supported: bool,
+    secbits: Secbits,
+}
+
+impl Environment {
+    fn get_current() -> io::Result<Self> {
+        Ok(Self {
+            current: FullCapState::get_current()?,
+            unknown_caps: RawCapState::get_current()?.unknown() != RawCapState::empty(),
+            unknown_bounding: bounding::has_unknown(),
+            unknown_ambient: ambient::has_unknown(),
+            ambient_supported: ambient::is_supported(),
+            secbits: crate::prctl::get_securebits()?,
+        })
+    }
+}
+
+/// An ordered list of the steps needed to change the current thread's capabilities to match a
+/// given [`FullCapState`].
+///
+/// This is returned by [`FullCapState::plan_set_current()`]. Computing a plan does not change
+/// anything, so it can be used as a "dry run" of [`FullCapState::set_current()`].
+///
+/// The steps are ordered as the kernel requires:
+///
+/// 1. `CAP_SETPCAP` is raised in the effective set (if necessary), and capabilities are dropped
+///    from the bounding set (including any that this library is not aware of).
+/// 2. The permitted, effective, and inheritable sets are set. (This must happen after the bounding
+///    set is changed, since dropping bounding capabilities may require `CAP_SETPCAP`, and before
+///    the ambient set is changed, since ambient capabilities must be permitted and inheritable.)
+/// 3. The ambient set is cleared (if necessary) and capabilities are raised in it.
+/// 4. The "no new privileges" flag is set.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Plan {
+    steps: Vec<PlanStep>,
+    infeasible: Vec<Infeasibility>,
+}
+
+impl Plan {
+    pub(crate) fn for_target(target: &FullCapState) -> io::Result<Self> {
+        Ok(Self::compute(&Environment::get_current()?, target))
+    }
+
+    fn compute(env: &Environment, target: &FullCapState) -> Self {
+        let cur = &env.current;
+
+        let mut steps = Vec::new();
+        let mut infeasible = Vec::new();
+
+        // Bounding set
+        infeasible.extend(
+            (target.bounding - cur.bounding)
+                .iter()
+                .map(Infeasibility::RaiseBounding),
+        );
+
+        let mut effective = cur.effective;
+        let bounding_drops = cur.bounding - target.bounding;
+        if !bounding_drops.is_empty() || env.unknown_bounding {
+            if !effective.has(Cap::SETPCAP) {
+                if cur.permitted.has(Cap::SETPCAP) {
+                    steps.push(PlanStep::RaiseSetpcap);
+                    effective.add(Cap::SETPCAP);
+                } else {
+                    infeasible.push(Infeasibility::MissingSetpcap);
+                }
+            }
+
+            steps.extend(bounding_drops.iter().map(PlanStep::DropBounding));
+            if env.unknown_bounding {
+                steps.push(PlanStep::ClearUnknownBounding);
+            }
+        }
+        let new_bounding = cur.bounding & target.bounding;
+
+        // Permitted, effective, and inheritable sets
+        infeasible.extend(
+            (target.permitted - cur.permitted)
+                .iter()
+                .map(Infeasibility::RaisePermitted),
+        );
+        infeasible.extend(
+            (target.effective - target.permitted)
+                .iter()
+                .map(Infeasibility::EffectiveNotPermitted),
+        );
+
+        // Without CAP_SETPCAP, only permitted capabilities can be added to the inheritable set; in
+        // either case, new inheritable capabilities must be in the bounding set.
+        let mut allowed_inheritable = cur.inheritable | new_bounding;
+        if !effective.has(Cap::SETPCAP) {
+            allowed_inheritable &= cur.inheritable | cur.permitted;
+        }
+        infeasible.extend(
+            (target.inheritable - allowed_inheritable)
+                .iter()
+                .map(Infeasibility::RaiseInheritable),
+        );
+
+        let new_capstate = CapState {
+            effective: target.effective,
+            permitted: target.permitted,
+            inheritable: target.inheritable,
+        };
+        let old_capstate = CapState {
+            effective,
+            permitted: cur.permitted,
+            inheritable: cur.inheritable,
+        };
+        if new_capstate != old_capstate || env.unknown_caps {
+            steps.push(PlanStep::SetCapState(new_capstate));
+        }
+
+        // Ambient set
+        if !target.ambient.is_empty() && !env.ambient_supported {
+            infeasible.push(Infeasibility::AmbientUnsupported);
+        }
+
+        // The kernel automatically lowers ambient capabilities that are no longer both permitted
+        // and inheritable
+        let mut ambient = cur.ambient & target.permitted & target.inheritable;
+        if !(ambient - target.ambient).is_empty() || env.unknown_ambient {
+            steps.push(PlanStep::ClearAmbient);
+            ambient.clear();
+        }
+
+        let ambient_raises = target.ambient - ambient;
+        if !ambient_raises.is_empty() && env.secbits.contains(Secbits::NO_CAP_AMBIENT_RAISE) {
+            infeasible.push(Infeasibility::AmbientRaiseLocked);
+        }
+        for cap in ambient_raises {
+            if target.permitted.has(cap) && target.inheritable.has(cap) {
+                steps.push(PlanStep::RaiseAmbient(cap));
+            } else {
+                infeasible.push(Infeasibility::AmbientNotPermitted(cap));
+            }
+        }
+
+        // No new privileges flag
+        match (cur.no_new_privs, target.no_new_privs) {
+            (false, true) => steps.push(PlanStep::SetNoNewPrivs),
+            (true, false) => infeasible.push(Infeasibility::ClearNoNewPrivs),
+            _ => (),
+        }
+
+        Self { steps, infeasible }
+    }
+
+    /// Get the steps in this plan, in the order they will be performed.
+    #[inline]
+    pub fn steps(&self) -> &[PlanStep] {
+        &self.steps
+    }
+
+    /// Get the reasons (if any) that this plan cannot be carried out.
+    #[inline]
+    pub fn infeasible(&self) -> &[Infeasibility] {
+        &self.infeasible
+    }
+
+    /// Check whether this plan can be carried out (i.e. whether [`infeasible()`](#method.infeasible)
+    /// is empty).
+    #[inline]
+    pub fn is_feasible(&self) -> bool {
+        self.infeasible.is_empty()
+    }
+
+    /// Perform all of the steps in this plan on the current thread.
+    ///
+    /// This fails with `EPERM` (without changing anything) if the plan is not feasible. If one of
+    /// the steps fails, the remaining steps are skipped and the error is returned; note that the
+    /// thread's capabilities may then have been partially changed.
+    pub fn apply(&self) -> crate::Result<()> {
+        if !self.is_feasible() {
+            return Err(crate::Error::from_code(libc::EPERM));
+        }
+
+        for step in self.steps.iter() {
+            step.apply()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::capset;
+
+    fn env(current: FullCapState) -> Environment {
+        Environment {
+            current,
+            unknown_caps: false,
+            unknown_bounding: false,
+            unknown_ambient: false,
+            ambient_supported: true,
+            secbits: Secbits::empty(),
+        }
+    }
+
+    fn full() -> FullCapState {
+        let mut state = FullCapState::empty();
+        state.permitted = !capset!();
+        state.effective = !capset!();
+        state.bounding = !capset!();
+        state
+    }
+
+    #[test]
+    fn test_plan_noop() {
+        for state in [FullCapState::empty(), full()].iter() {
+            let plan = Plan::compute(&env(*state), state);
+            assert_eq!(plan.steps(), &[]);
+            assert!(plan.is_feasible());
+        }
+
+        let mut e = env(full());
+        e.unknown_caps = true;
+        e.unknown_bounding = true;
+        e.unknown_ambient = true;
+        let plan = Plan::compute(&e, &full());
+        assert_eq!(
+            plan.steps(),
+            &[
+                PlanStep::ClearUnknownBounding,
+                PlanStep::SetCapState(CapState {
+                    effective: !capset!(),
+                    permitted: !capset!(),
+                    inheritable: capset!(),
+                }),
+                PlanStep::ClearAmbient,
+            ]
+        );
+        assert!(plan.is_feasible());
+    }
+
+    #[test]
+    fn test_plan_order() {
+        let mut current = full();
+        current.effective = capset!();
+
+        let mut target = FullCapState::empty();
+        target.bounding = capset!(Cap::NET_RAW);
+        target.permitted = capset!(Cap::NET_RAW);
+        target.inheritable = capset!(Cap::NET_RAW);
+        target.ambient = capset!(Cap::NET_RAW);
+        target.no_new_privs = true;
+
+        let plan = Plan::compute(&env(current), &target);
+        assert!(plan.is_feasible(), "{:?}", plan);
+
+        let mut expected = vec![PlanStep::RaiseSetpcap];
+        expected.extend((!capset!(Cap::NET_RAW)).iter().map(PlanStep::DropBounding));
+        expected.extend_from_slice(&[
+            PlanStep::SetCapState(CapState {
+                effective: capset!(),
+                permitted: capset!(Cap::NET_RAW),
+                inheritable: capset!(Cap::NET_RAW),
+            }),
+            PlanStep::RaiseAmbient(Cap::NET_RAW),
+            PlanStep::SetNoNewPrivs,
+        ]);
+        assert_eq!(plan.steps(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_plan_ambient() {
+        let mut current = full();
+        current.inheritable = capset!(Cap::CHOWN, Cap::NET_RAW);
+        current.ambient = capset!(Cap::CHOWN, Cap::NET_RAW);
+
+        // Lowering CAP_CHOWN in the permitted set drops it from the ambient set automatically
+        let mut target = current;
+        target.permitted.drop(Cap::CHOWN);
+        target.effective.drop(Cap::CHOWN);
+        target.ambient = capset!(Cap::NET_RAW);
+        let plan = Plan::compute(&env(current), &target);
+        assert_eq!(
+            plan.steps(),
+            &[PlanStep::SetCapState(CapState {
+                effective: target.effective,
+                permitted: target.permitted,
+                inheritable: target.inheritable,
+            })]
+        );
+
+        // But lowering only the ambient capability requires clearing the set
+        let mut target = current;
+        target.ambient = capset!(Cap::NET_RAW);
+        let plan = Plan::compute(&env(current), &target);
+        assert_eq!(
+            plan.steps(),
+            &[PlanStep::ClearAmbient, PlanStep::RaiseAmbient(Cap::NET_RAW)]
+        );
+    }
+
+    #[test]
+    fn test_plan_infeasible() {
+        let mut current = FullCapState::empty();
+        current.bounding = capset!(Cap::CHOWN, Cap::NET_RAW);
+        current.permitted = capset!(Cap::CHOWN);
+        current.no_new_privs = true;
+
+        let mut target = FullCapState::empty();
+        target.bounding = capset!(Cap::CHOWN, Cap::SYS_ADMIN);
+        target.permitted = capset!(Cap::CHOWN, Cap::SETUID);
+        target.effective = capset!(Cap::CHOWN, Cap::KILL);
+        target.inheritable = capset!(Cap::CHOWN, Cap::SYSLOG);
+        target.ambient = capset!(Cap::CHOWN, Cap::KILL);
+
+        let mut e = env(current);
+        e.secbits = Secbits::NO_CAP_AMBIENT_RAISE;
+        let plan = Plan::compute(&e, &target);
+
+        assert_eq!(
+            plan.infeasible(),
+            &[
+                Infeasibility::RaiseBounding(Cap::SYS_ADMIN),
+                Infeasibility::MissingSetpcap,
+                Infeasibility::RaisePermitted(Cap::SETUID),
+                Infeasibility::EffectiveNotPermitted(Cap::KILL),
+                Infeasibility::RaiseInheritable(Cap::SYSLOG),
+                Infeasibility::AmbientRaiseLocked,
+                Infeasibility::AmbientNotPermitted(Cap::KILL),
+                Infeasibility::ClearNoNewPrivs,
+            ]
+        );
+        assert!(!plan.is_feasible());
+        assert_eq!(plan.apply().unwrap_err().code(), libc::EPERM);
+
+        assert_eq!(
+            Infeasibility::RaiseBounding(Cap::SYS_ADMIN).to_string(),
+            "Cannot add CAP_SYS_ADMIN to the bounding set"
+        );
+
+        let mut e = env(FullCapState::empty());
+        e.ambient_supported = false;
+        target = FullCapState::empty();
+        target.ambient = capset!(Cap::CHOWN);
+        assert_eq!(
+            Plan::compute(&e, &target).infeasible(),
+            &[
+                Infeasibility::AmbientUnsupported,
+                Infeasibility::AmbientNotPermitted(Cap::CHOWN)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_current() {
+        std::thread::spawn(|| {
+            let current = FullCapState::get_current().unwrap();
+            assert!(current.plan_set_current().unwrap().is_feasible());
+            current.set_current().unwrap();
+            assert_eq!(FullCapState::get_current().unwrap(), current);
+
+            if !current.permitted.has(Cap::SETPCAP)
+                || !current.permitted.has(Cap::NET_RAW)
+                || !current.bounding.has(Cap::NET_RAW)
+                || !ambient::is_supported()
+            {
+                return;
+            }
+
+            let mut target = current;
+            target.bounding.drop(Cap::SYS_MODULE);
+            target.inheritable.add(Cap::NET_RAW);
+            target.ambient.add(Cap::NET_RAW);
+            target.effective.clear();
+
+            let plan = target.plan_set_current().unwrap();
+            assert!(plan.is_feasible(), "{:?}", plan);
+            // Nothing has changed yet
+            assert_eq!(FullCapState::get_current().unwrap(), current);
+
+            target.set_current().unwrap();
+            assert_eq!(FullCapState::get_current().unwrap(), target);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,435 @@
+use std::io;
+
+use super::{ambient, cap_set_ids, Cap, CapSet, FullCapState};
+use crate::prctl::Secbits;
+
+/// A builder for dropping privileges: switching to another user while keeping a chosen set of
+/// capabilities.
+///
+/// [`apply()`](#method.apply) performs all of the necessary steps in the correct order:
+///
+/// 1. Check that the requested changes are possible, and fail with `EPERM` (without changing
+///    anything) if not.
+/// 2. Reduce the bounding set to the capabilities being kept (unless disabled with
+///    [`clear_bounding()`](#method.clear_bounding)).
+/// 3. Switch the UID/GID/supplementary groups with [`cap_set_ids()`], preserving the permitted
+///    set.
+/// 4. Reduce the permitted, effective, and inheritable sets, set the ambient set, and set the "no
+///    new privileges" flag (if requested); see [`FullCapState::set_current()`]. If securebits
+///    flags were requested, `CAP_SETPCAP` is kept in the permitted and effective sets for now.
+/// 5. Set (and lock) the requested securebits flags, and then drop `CAP_SETPCAP` (unless it is
+///    being kept).
+/// 6. Verify that the thread ended up in the expected state, failing with `EINVAL` if not.
+///
+/// For example, to switch to UID/GID 1000 with no supplementary groups, keeping only
+/// `CAP_NET_BIND_SERVICE` (and passing it on to child processes as an ambient capability):
+///
+/// ```no_run
+/// # use capctl::*;
+/// PrivDrop::new()
+///     .uid(1000)
+///     .gid(1000)
+///     .groups(&[])
+///     .keep(capset!(Cap::NET_BIND_SERVICE))
+///     .ambient(true)
+///     .securebits(Secbits::NOROOT | Secbits::NOROOT_LOCKED)
+///     .no_new_privs(true)
+///     .apply()
+///     .unwrap();
+/// ```
+///
+/// WARNING: Like [`cap_set_ids()`], this only operates on the current **thread**. See
+/// [`threads`](crate::threads) if other threads need to be changed as well. If `apply()` fails
+/// partway through, the thread is in an unknown and possibly inconsistent state; abort as soon as
+/// possible if you are unable to revert the changes.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug)]
+pub struct PrivDrop<'a> {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    groups: Option<&'a [libc::gid_t]>,
+    keep: CapSet,
+    keep_effective: bool,
+    ambient: bool,
+    clear_bounding: bool,
+    securebits: Secbits,
+    no_new_privs: bool,
+}
+
+impl<'a> PrivDrop<'a> {
+    /// Create a new builder.
+    ///
+    /// By default, the UID, GID, and supplementary groups are left unchanged, no capabilities are
+    /// kept, the bounding set is cleared, no securebits flags are set, and the "no new privileges"
+    /// flag is not set.
+    pub fn new() -> Self {
+        Self {
+            uid: None,
+            gid: None,
+            groups: None,
+            keep: CapSet::empty(),
+            keep_effective: false,
+            ambient: false,
+            clear_bounding: true,
+            securebits: Secbits::empty(),
+            no_new_privs: false,
+        }
+    }
+
+    /// Set the real, effective, and saved UIDs to `uid`.
+    #[inline]
+    pub fn uid(&mut self, uid: libc::uid_t) -> &mut Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Set the real, effective, and saved GIDs to `gid`.
+    #[inline]
+    pub fn gid(&mut self, gid: libc::gid_t) -> &mut Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Set the supplementary group list to `groups`.
+    #[inline]
+    pub fn groups(&mut self, groups: &'a [libc::gid_t]) -> &mut Self {
+        self.groups = Some(groups);
+        self
+    }
+
+    /// Set the capabilities to keep in the permitted set. All other capabilities are dropped.
+    #[inline]
+    pub fn keep(&mut self, caps: CapSet) -> &mut Self {
+        self.keep = caps;
+        self
+    }
+
+    /// Set whether the kept capabilities should also be raised in the effective set (default
+    /// `false`).
+    #[inline]
+    pub fn keep_effective(&mut self, keep_effective: bool) -> &mut Self {
+        self.keep_effective = keep_effective;
+        self
+    }
+
+    /// Set whether the kept capabilities should be added to the inheritable and ambient sets, so
+    /// that they are passed on to child processes (default `false`).
+    #[inline]
+    pub fn ambient(&mut self, ambient: bool) -> &mut Self {
+        self.ambient = ambient;
+        self
+    }
+
+    /// Set whether the bounding set should be reduced to the kept capabilities (default `true`).
+    #[inline]
+    pub fn clear_bounding(&mut self, clear_bounding: bool) -> &mut Self {
+        self.clear_bounding = clear_bounding;
+        self
+    }
+
+    /// Set the securebits flags to set (and/or lock).
+    ///
+    /// These are set *after* switching the UID/GID, so locking (for example)
+    /// [`Secbits::KEEP_CAPS_LOCKED`] will not interfere with the switch.
+    ///
+    /// The flags are added to the thread's current securebits. `apply()` fails with `EPERM` if
+    /// this would change a flag that is already locked, or if the result would include
+    /// [`Secbits::NO_CAP_AMBIENT_RAISE`] while [`ambient()`](#method.ambient) is enabled.
+    #[inline]
+    pub fn securebits(&mut self, flags: Secbits) -> &mut Self {
+        self.securebits = flags;
+        self
+    }
+
+    /// Set whether the "no new privileges" flag should be set (default `false`).
+    #[inline]
+    pub fn no_new_privs(&mut self, no_new_privs: bool) -> &mut Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Perform the privilege drop on the current thread.
+    ///
+    /// On success, returns the thread's new (verified) capability state.
+    pub fn apply(&self) -> io::Result<FullCapState> {
+        let orig = FullCapState::get_current()?;
+        let orig_secbits = crate::prctl::get_securebits()?;
+
+        if !self.is_feasible(&orig, orig_secbits) {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+
+        let mut expected = orig;
+        expected.permitted = self.keep;
+        expected.effective = if self.keep_effective {
+            self.keep
+        } else {
+            CapSet::empty()
+        };
+        expected.inheritable = if self.ambient {
+            self.keep
+        } else {
+            CapSet::empty()
+        };
+        expected.ambient = expected.inheritable;
+        expected.no_new_privs |= self.no_new_privs;
+        if self.clear_bounding {
+            expected.bounding &= self.keep;
+        }
+
+        // Reduce the bounding set (this raises CAP_SETPCAP as necessary)
+        if expected.bounding != orig.bounding {
+            let mut state = orig;
+            state.bounding = expected.bounding;
+            state.set_current()?;
+        }
+
+        cap_set_ids(self.uid, self.gid, self.groups)?;
+
+        if self.securebits.is_empty() {
+            expected.set_current()?;
+        } else {
+            // Raise the ambient capabilities first, since NO_CAP_AMBIENT_RAISE would prevent it,
+            // but keep CAP_SETPCAP until the securebits have been set
+            let mut state = expected;
+            state.permitted.add(Cap::SETPCAP);
+            state.effective.add(Cap::SETPCAP);
+            state.set_current()?;
+
+            crate::prctl::set_securebits(orig_secbits | self.securebits)?;
+
+            expected.set_current()?;
+        }
+
+        self.verify(&expected)?;
+        Ok(expected)
+    }
+
+    /// Check that everything requested is possible, given the current state and securebits.
+    ///
+    /// Everything that could fail after the UID/GID switch (which can't be undone) is checked
+    /// here.
+    fn is_feasible(&self, orig: &FullCapState, orig_secbits: Secbits) -> bool {
+        let mut needed = self.keep;
+
+        if self.uid.is_some() {
+            needed.add(Cap::SETUID);
+        }
+        if self.gid.is_some() || self.groups.is_some() {
+            needed.add(Cap::SETGID);
+        }
+        if !self.securebits.is_empty()
+            || (self.clear_bounding && !(orig.bounding - self.keep).is_empty())
+        {
+            needed.add(Cap::SETPCAP);
+        }
+
+        if !(needed - orig.permitted).is_empty() {
+            return false;
+        }
+
+        let secbits = orig_secbits | self.securebits;
+
+        if self.ambient
+            && !self.keep.is_empty()
+            && (!ambient::is_supported()
+                || !(self.keep - orig.bounding).is_empty()
+                || secbits.contains(Secbits::NO_CAP_AMBIENT_RAISE))
+        {
+            return false;
+        }
+
+        // Flags whose lock bit is already set can't be changed (each lock bit is the bit after the
+        // flag it locks)
+        let locked = Secbits::from_bits_truncate(orig_secbits.bits() >> 1)
+            & (Secbits::NOROOT
+                | Secbits::NO_SETUID_FIXUP
+                | Secbits::KEEP_CAPS
+                | Secbits::NO_CAP_AMBIENT_RAISE);
+        if !(locked & (orig_secbits ^ secbits)).is_empty() {
+            return false;
+        }
+
+        true
+    }
+
+    fn verify(&self, expected: &FullCapState) -> io::Result<()> {
+        let mut ok = FullCapState::get_current()? == *expected;
+
+        if !self.securebits.is_empty() {
+            ok &= crate::prctl::get_securebits()?.contains(self.securebits);
+        }
+
+        if let Some(uid) = self.uid {
+            let (mut ruid, mut euid, mut suid) = (0, 0, 0);
+            if unsafe { libc::getresuid(&mut ruid, &mut euid, &mut suid) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            ok &= ruid == uid && euid == uid && suid == uid;
+        }
+
+        if let Some(gid) = self.gid {
+            let (mut rgid, mut egid, mut sgid) = (0, 0, 0);
+            if unsafe { libc::getresgid(&mut rgid, &mut egid, &mut sgid) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            ok &= rgid == gid && egid == gid && sgid == gid;
+        }
+
+        if let Some(groups) = self.groups {
+            let mut actual = vec![0; groups.len() + 1];
+            let n = unsafe { libc::getgroups(actual.len() as libc::c_int, actual.as_mut_ptr()) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            actual.truncate(n as usize);
+
+            ok &= actual.len() == groups.len() && groups.iter().all(|gid| actual.contains(gid));
+        }
+
+        if ok {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(libc::EINVAL))
+        }
+    }
+}
+
+impl Default for PrivDrop<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::CapState;
+    use crate::capset;
+
+    #[test]
+    fn test_privdrop_infeasible() {
+        std::thread::spawn(|| {
+            let mut state = CapState::get_current().unwrap();
+            state.effective.drop(Cap::NET_RAW);
+            state.permitted.drop(Cap::NET_RAW);
+            state.set_current().unwrap();
+
+            let orig = FullCapState::get_current().unwrap();
+
+            assert_eq!(
+                PrivDrop::new()
+                    .keep(capset!(Cap::NET_RAW))
+                    .clear_bounding(false)
+                    .apply()
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EPERM)
+            );
+
+            // Nothing changed
+            assert_eq!(FullCapState::get_current().unwrap(), orig);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_privdrop_infeasible_secbits() {
+        std::thread::spawn(|| {
+            let orig = FullCapState::get_current().unwrap();
+            if !(orig.permitted.has(Cap::SETUID)
+                && orig.permitted.has(Cap::SETPCAP)
+                && orig.bounding.has(Cap::NET_BIND_SERVICE)
+                && ambient::is_supported())
+            {
+                return;
+            }
+
+            let uid = unsafe { libc::geteuid() };
+            let try_drop = |securebits| {
+                let err = PrivDrop::new()
+                    .uid(uid)
+                    .keep(capset!(Cap::NET_BIND_SERVICE))
+                    .ambient(true)
+                    .securebits(securebits)
+                    .apply()
+                    .unwrap_err();
+                assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+            };
+
+            // Ambient capabilities can't be raised with NO_CAP_AMBIENT_RAISE
+            try_drop(Secbits::NO_CAP_AMBIENT_RAISE);
+            assert_eq!(FullCapState::get_current().unwrap(), orig);
+
+            // A flag can't be changed once it's locked
+            let mut state = CapState::get_current().unwrap();
+            state.effective.add(Cap::SETPCAP);
+            state.set_current().unwrap();
+            crate::prctl::set_securebits(Secbits::NO_SETUID_FIXUP_LOCKED).unwrap();
+            let orig = FullCapState::get_current().unwrap();
+            try_drop(Secbits::NO_SETUID_FIXUP);
+            assert_eq!(FullCapState::get_current().unwrap(), orig);
+
+            // The current securebits count too
+            crate::prctl::set_securebits(
+                Secbits::NO_SETUID_FIXUP_LOCKED | Secbits::NO_CAP_AMBIENT_RAISE,
+            )
+            .unwrap();
+            try_drop(Secbits::empty());
+            assert_eq!(FullCapState::get_current().unwrap(), orig);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_privdrop() {
+        std::thread::spawn(|| {
+            let orig = FullCapState::get_current().unwrap();
+            if !(orig.permitted.has(Cap::SETUID)
+                && orig.permitted.has(Cap::SETGID)
+                && orig.permitted.has(Cap::SETPCAP)
+                && orig.bounding.has(Cap::NET_BIND_SERVICE)
+                && ambient::is_supported())
+            {
+                return;
+            }
+
+            // Switch to the current UID/GID (which works even inside a user namespace)
+            let uid = unsafe { libc::geteuid() };
+            let gid = unsafe { libc::getegid() };
+
+            let state = PrivDrop::new()
+                .uid(uid)
+                .gid(gid)
+                .keep(capset!(Cap::NET_BIND_SERVICE))
+                .ambient(true)
+                .securebits(Secbits::NO_SETUID_FIXUP)
+                .no_new_privs(true)
+                .apply()
+                .unwrap();
+
+            assert_eq!(state, FullCapState::get_current().unwrap());
+            assert_eq!(state.permitted, capset!(Cap::NET_BIND_SERVICE));
+            assert_eq!(state.effective, capset!());
+            assert_eq!(state.inheritable, capset!(Cap::NET_BIND_SERVICE));
+            assert_eq!(state.ambient, capset!(Cap::NET_BIND_SERVICE));
+            assert_eq!(state.bounding, capset!(Cap::NET_BIND_SERVICE));
+            assert!(state.no_new_privs);
+            assert!(crate::prctl::get_securebits()
+                .unwrap()
+                .contains(Secbits::NO_SETUID_FIXUP));
+
+            // Now we can't do it again
+            assert_eq!(
+                PrivDrop::new().uid(uid).apply().unwrap_err().raw_os_error(),
+                Some(libc::EPERM)
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+use core::marker::PhantomData;
+
+use super::{CapSet, RawCapState};
+
+/// A guard that raises capabilities in the current thread's effective set, and lowers them again
+/// when it is dropped (including during a panic).
+///
+/// Capabilities that were already in the effective set when the guard was created (and were not
+/// raised by another guard) are left alone when it is dropped. Capabilities raised by more than one
+/// guard in the same thread are reference-counted, and are only lowered when the last of those
+/// guards is dropped, so overlapping guards work correctly regardless of the order in which they
+/// are dropped.
+///
+/// Since capabilities are per-thread, this type is neither `Send` nor `Sync`.
+///
+/// Without the `std` feature, there is no per-thread reference count; each guard only lowers the
+/// capabilities that it raised itself. In that case, overlapping guards must be dropped in the
+/// reverse order of their creation (which happens automatically with normal scoping).
+///
+/// The capabilities are raised and lowered by reading and writing the capability state with
+/// [`RawCapState`], so capabilities that this library is not aware of are preserved.
+///
+/// If the capabilities cannot be lowered when the guard is dropped, the process is aborted, since
+/// continuing with unexpectedly raised capabilities would be a security risk. Use
+/// [`lower()`](#method.lower) to handle this error instead.
+///
+/// Example:
+///
+/// ```no_run
+/// # use capctl::*;
+/// let guard = EffectiveGuard::raise(capset!(Cap::NET_BIND_SERVICE)).unwrap();
+/// // Bind to a privileged port here
+/// drop(guard);
+/// ```
+///
+/// See also [`with_effective()`].
+#[must_use]
+#[derive(Debug)]
+pub struct EffectiveGuard {
+    raised: CapSet,
+    // The capabilities whose reference counts this guard holds
+    held: CapSet,
+    // Capabilities are per-thread, so this must not be moved to (or shared with) another thread
+    _marker: PhantomData<*const ()>,
+}
+
+impl EffectiveGuard {
+    /// Raise the given capabilities in the current thread's effective set.
+    ///
+    /// This fails with `EPERM` if any of the capabilities are not in the permitted set.
+    pub fn raise(caps: CapSet) -> crate::Result<Self> {
+        let mut state = RawCapState::get_current()?;
+
+        let mut raised = caps;
+        raised.bits &= !state.effective;
+
+        if !raised.is_empty() {
+            state.effective |= raised.bits;
+            state.set_current()?;
+        }
+
+        // Also take a reference to any capabilities that other guards have already raised
+        let held = raised | (caps & refcount::held());
+        refcount::acquire(held);
+
+        Ok(Self {
+            raised,
+            held,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Get the capabilities that this guard actually raised (i.e. the ones that were not already in
+    /// the effective set when it was created).
+    #[inline]
+    pub fn raised(&self) -> CapSet {
+        self.raised
+    }
+
+    /// Lower the capabilities raised by this guard, returning any error that occurs.
+    ///
+    /// Capabilities that are still held by other guards are left raised.
+    pub fn lower(mut self) -> crate::Result<()> {
+        let res = Self::lower_raw(refcount::release(self.held));
+        self.held.clear();
+        res
+    }
+
+    fn lower_raw(caps: CapSet) -> crate::Result<()> {
+        if caps.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = RawCapState::get_current()?;
+        state.effective &= !caps.bits;
+        state.set_current()
+    }
+}
+
+impl Drop for EffectiveGuard {
+    fn drop(&mut self) {
+        if Self::lower_raw(refcount::release(self.held)).is_err() {
+            unsafe {
+                libc::abort();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+mod refcount {
+    use core::cell::Cell;
+
+    use super::CapSet;
+
+    std::thread_local! {
+        // The number of live guards in this thread holding each capability
+        static COUNTS: Cell<[u32; 64]> = const { Cell::new([0; 64]) };
+    }
+
+    /// Get the capabilities currently held by at least one guard in this thread.
+    pub fn held() -> CapSet {
+        COUNTS
+            .try_with(|counts| {
+                let mut caps = CapSet::empty();
+                for (i, &count) in counts.get().iter().enumerate() {
+                    if count != 0 {
+                        caps.bits |= 1 << i;
+                    }
+                }
+                caps
+            })
+            .unwrap_or_else(|_| CapSet::empty())
+    }
+
+    pub fn acquire(caps: CapSet) {
+        let _ = COUNTS.try_with(|counts| {
+            let mut c = counts.get();
+            for cap in caps.iter() {
+                c[cap as usize] += 1;
+            }
+            counts.set(c);
+        });
+    }
+
+    /// Release the given capabilities, returning the ones that are no longer held by any guard
+    /// (and should be lowered).
+    pub fn release(caps: CapSet) -> CapSet {
+        COUNTS
+            .try_with(|counts| {
+                let mut c = counts.get();
+                let mut unheld = CapSet::empty();
+                for cap in caps.iter() {
+                    let count = &mut c[cap as usize];
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        unheld.add(cap);
+                    }
+                }
+                counts.set(c);
+                unheld
+            })
+            // The thread is exiting; lower everything
+            .unwrap_or(caps)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod refcount {
+    use super::CapSet;
+
+    #[inline]
+    pub fn held() -> CapSet {
+        CapSet::empty()
+    }
+
+    #[inline]
+    pub fn acquire(_caps: CapSet) {}
+
+    #[inline]
+    pub fn release(caps: CapSet) -> CapSet {
+        caps
+    }
+}
+
+/// Raise the given capabilities in the current thread's effective set, run `f`, and then lower
+/// them again.
+///
+/// This is a wrapper around [`EffectiveGuard`]; see its documentation for more details. The
+/// capabilities are lowered even if `f` panics.
+///
+/// Example:
+///
+/// ```no_run
+/// # use capctl::*;
+/// let sock = with_effective(capset!(Cap::NET_BIND_SERVICE), || {
+///     std::net::TcpListener::bind("0.0.0.0:80")
+/// })
+/// .unwrap();
+/// ```
+pub fn with_effective<T, F: FnOnce() -> T>(caps: CapSet, f: F) -> crate::Result<T> {
+    let guard = EffectiveGuard::raise(caps)?;
+    let res = f();
+    guard.lower()?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::{Cap, CapState};
+    use crate::capset;
+
+    #[test]
+    fn test_effective_guard() {
+        let mut state = CapState::get_current().unwrap();
+
+        if !state.permitted.has(Cap::NET_BIND_SERVICE) || !state.permitted.has(Cap::KILL) {
+            assert_eq!(
+                EffectiveGuard::raise(!state.permitted).unwrap_err().code(),
+                libc::EPERM
+            );
+            return;
+        }
+
+        state.effective.clear();
+        state.set_current().unwrap();
+
+        let effective = || CapState::get_current().unwrap().effective;
+
+        let outer = EffectiveGuard::raise(capset!(Cap::NET_BIND_SERVICE)).unwrap();
+        assert_eq!(outer.raised(), capset!(Cap::NET_BIND_SERVICE));
+        assert_eq!(effective(), capset!(Cap::NET_BIND_SERVICE));
+
+        {
+            let inner = EffectiveGuard::raise(capset!(Cap::NET_BIND_SERVICE, Cap::KILL)).unwrap();
+            assert_eq!(inner.raised(), capset!(Cap::KILL));
+            assert_eq!(effective(), capset!(Cap::NET_BIND_SERVICE, Cap::KILL));
+        }
+
+        // The inner guard only lowered what it raised
+        assert_eq!(effective(), capset!(Cap::NET_BIND_SERVICE));
+
+        outer.lower().unwrap();
+        assert_eq!(effective(), capset!());
+
+        // Overlapping guards dropped out of order
+        #[cfg(feature = "std")]
+        {
+            let first = EffectiveGuard::raise(capset!(Cap::NET_BIND_SERVICE)).unwrap();
+            let second = EffectiveGuard::raise(capset!(Cap::NET_BIND_SERVICE, Cap::KILL)).unwrap();
+            assert_eq!(second.raised(), capset!(Cap::KILL));
+
+            drop(first);
+            assert_eq!(effective(), capset!(Cap::NET_BIND_SERVICE, Cap::KILL));
+            drop(second);
+            assert_eq!(effective(), capset!());
+        }
+
+        // Capabilities raised outside of any guard are left alone
+        state.effective = capset!(Cap::KILL);
+        state.set_current().unwrap();
+        with_effective(capset!(Cap::KILL, Cap::NET_BIND_SERVICE), || ()).unwrap();
+        assert_eq!(effective(), capset!(Cap::KILL));
+        state.effective.clear();
+        state.set_current().unwrap();
+
+        assert_eq!(
+            with_effective(capset!(Cap::KILL), effective).unwrap(),
+            capset!(Cap::KILL)
+        );
+        assert_eq!(effective(), capset!());
+
+        #[cfg(feature = "std")]
+        {
+            let res = std::panic::catch_unwind(|| {
+                with_effective(capset!(Cap::KILL), || panic!("oops")).unwrap();
+            });
+            assert!(res.is_err());
+            assert_eq!(effective(), capset!());
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use crate::prctl::Secbits;
+
+use super::{Cap, CapSet, FileCaps, FullCapState};
+
+/// The capabilities that are lowered from (or raised in) the effective set when the filesystem UID
+/// is changed from (or to) 0.
+const FS_CAPS: CapSet = crate::capset!(
+    Cap::CHOWN,
+    Cap::DAC_OVERRIDE,
+    Cap::DAC_READ_SEARCH,
+    Cap::FOWNER,
+    Cap::FSETID,
+    Cap::LINUX_IMMUTABLE,
+    Cap::MAC_OVERRIDE,
+    Cap::MKNOD,
+);
+
+/// Compute the capability state that a thread will have after a successful `execve()`.
+///
+/// This is a pure function; it does not examine or modify the state of the current thread. It
+/// implements the transformation rules described in capabilities(7):
+///
+/// ```text
+/// P'(ambient)     = (file is privileged) ? 0 : P(ambient)
+/// P'(permitted)   = (P(inheritable) & F(inheritable)) | (F(permitted) & P(bounding)) | P'(ambient)
+/// P'(effective)   = F(effective) ? P'(permitted) : P'(ambient)
+/// P'(inheritable) = P(inheritable)
+/// P'(bounding)    = P(bounding)
+/// ```
+///
+/// The arguments are as follows:
+///
+/// - `state`: The capability state of the thread calling `execve()`. `state.no_new_privs` is
+///   honored (see below).
+/// - `fcaps`: The file capabilities attached to the executable (if any). These are assumed to be
+///   honored by the kernel; i.e. the file is not on a `nosuid` mount, and (for version 3 file
+///   capabilities) the `rootid` is valid in the caller's user namespace. (See
+///   [`FileCaps::check_exec_for_file()`], which checks these conditions for a real file.)
+/// - `secbits`: The caller's securebits. Only [`Secbits::NOROOT`] affects the result.
+/// - `ruid` and `euid`: The caller's real and effective UIDs before the `execve()`.
+/// - `setuid_root`: Whether the executable is a set-user-ID-root program.
+///
+/// Unless [`Secbits::NOROOT`] is set, the special handling of UID 0 applies: if the real or
+/// effective UID is 0 after the `execve()`, the file's permitted and inheritable sets are treated
+/// as full, and if the effective UID is 0 the file's effective bit is treated as set. (As in the
+/// kernel, this is skipped if the file has capabilities and is being executed by a thread whose
+/// effective UID is 0 but whose real UID is not.)
+///
+/// If `state.no_new_privs` is set, the set-user-ID bit is ignored, and the new permitted set is
+/// limited to the old permitted set (plus the ambient set).
+///
+/// The ambient set is cleared if the file has capabilities, or if the new effective UID differs
+/// from the old real UID (whether because of a set-user-ID binary or because the real and
+/// effective UIDs already differed).
+///
+/// If the file's effective bit is set and one or more of the capabilities in its permitted set
+/// would not be granted (for example, because they are not present in the bounding set), the
+/// kernel fails the `execve()` with `EPERM`. In that case, this function also fails with `EPERM`.
+///
+/// Note: This does not model set-group-ID executables (which clear the ambient set in the same
+/// way as set-user-ID executables), or the restrictions applied when the caller is being
+/// `ptrace()`d.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn simulate_execve(
+    state: &FullCapState,
+    fcaps: Option<&FileCaps>,
+    secbits: Secbits,
+    ruid: libc::uid_t,
+    euid: libc::uid_t,
+    setuid_root: bool,
+) -> crate::Result<FullCapState> {
+    // The kernel ignores the set-user-ID bit if no_new_privs is set
+    let new_euid = if setuid_root && !state.no_new_privs {
+        0
+    } else {
+        euid
+    };
+    // Like the kernel's __is_setuid(), this compares against the old *real* UID
+    let is_setid = new_euid != ruid;
+
+    let mut permitted = CapSet::empty();
+    let mut effective = false;
+
+    if let Some(fcaps) = fcaps {
+        permitted = (fcaps.permitted & state.bounding) | (fcaps.inheritable & state.inheritable);
+        effective = fcaps.effective;
+
+        // "All or nothing" rule for capability-dumb binaries
+        if effective && !fcaps.permitted.issubset(permitted) {
+            return Err(crate::Error::from_code(libc::EPERM));
+        }
+    }
+
+    // If a set-user-ID-root binary with file capabilities is run by a non-root user, the kernel
+    // only grants the file capabilities
+    let suid_with_fcaps = fcaps.is_some() && ruid != 0 && new_euid == 0;
+
+    if !secbits.contains(Secbits::NOROOT) && !suid_with_fcaps {
+        if ruid == 0 || new_euid == 0 {
+            permitted = state.bounding | state.inheritable;
+        }
+
+        if new_euid == 0 {
+            effective = true;
+        }
+    }
+
+    if state.no_new_privs {
+        permitted &= state.permitted;
+    }
+
+    let ambient = if fcaps.is_some() || is_setid {
+        CapSet::empty()
+    } else {
+        state.ambient
+    };
+
+    permitted |= ambient;
+
+    Ok(FullCapState {
+        permitted,
+        effective: if effective { permitted } else { ambient },
+        inheritable: state.inheritable,
+        ambient,
+        bounding: state.bounding,
+        no_new_privs: state.no_new_privs,
+    })
+}
+
+/// Compute the capability state that a thread will have after changing its UIDs.
+///
+/// This is a pure function; it does not examine or modify the state of the current thread. `old`
+/// and `new` are `(ruid, euid, suid, fsuid)` tuples giving the thread's real, effective, saved, and
+/// filesystem UIDs before and after the change.
+///
+/// The change is modeled as a `setresuid()` call (if any of the real, effective, or saved UIDs
+/// changed) followed by a `setfsuid()` call (if the filesystem UID does not end up matching the
+/// new effective UID). As described in capabilities(7), unless [`Secbits::NO_SETUID_FIXUP`] is
+/// set:
+///
+/// - If one or more of the real, effective, or saved UIDs was 0, and as a result of the change
+///   all of them are nonzero, the ambient set is cleared. The permitted and effective sets are
+///   also cleared, unless [`Secbits::KEEP_CAPS`] is set (see [`crate::prctl::set_keepcaps()`]).
+/// - If the effective UID is changed from 0 to nonzero, the effective set is cleared.
+/// - If the effective UID is changed from nonzero to 0, the permitted set is copied to the
+///   effective set.
+/// - If the filesystem UID is changed from 0 to nonzero, filesystem-related capabilities
+///   (`CAP_CHOWN`, `CAP_DAC_OVERRIDE`, `CAP_DAC_READ_SEARCH`, `CAP_FOWNER`, `CAP_FSETID`,
+///   `CAP_LINUX_IMMUTABLE`, `CAP_MAC_OVERRIDE`, and `CAP_MKNOD`) are lowered in the effective
+///   set. If it is changed from nonzero to 0, any of those capabilities that are present in the
+///   permitted set are raised in the effective set.
+///
+/// This function does not check whether the thread would actually be *allowed* to make the
+/// specified change (which usually requires `CAP_SETUID`).
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn simulate_setuid(
+    state: &FullCapState,
+    old: (libc::uid_t, libc::uid_t, libc::uid_t, libc::uid_t),
+    new: (libc::uid_t, libc::uid_t, libc::uid_t, libc::uid_t),
+    secbits: Secbits,
+) -> FullCapState {
+    let mut res = *state;
+
+    if secbits.contains(Secbits::NO_SETUID_FIXUP) {
+        return res;
+    }
+
+    let (old_ruid, old_euid, old_suid, old_fsuid) = old;
+    let (new_ruid, new_euid, new_suid, new_fsuid) = new;
+
+    let mut fsuid = old_fsuid;
+
+    if (old_ruid, old_euid, old_suid) != (new_ruid, new_euid, new_suid) {
+        if (old_ruid == 0 || old_euid == 0 || old_suid == 0)
+            && (new_ruid != 0 && new_euid != 0 && new_suid != 0)
+        {
+            if !secbits.contains(Secbits::KEEP_CAPS) {
+                res.permitted.clear();
+                res.effective.clear();
+            }
+
+            res.ambient.clear();
+        }
+
+        if old_euid == 0 && new_euid != 0 {
+            res.effective.clear();
+        } else if old_euid != 0 && new_euid == 0 {
+            res.effective = res.permitted;
+        }
+
+        // setresuid() also changes the filesystem UID to match the new effective UID
+        fsuid = new_euid;
+    }
+
+    if fsuid == 0 && new_fsuid != 0 {
+        res.effective -= FS_CAPS;
+    } else if fsuid != 0 && new_fsuid == 0 {
+        res.effective |= res.permitted & FS_CAPS;
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::Cap;
+    use crate::capset;
+
+    fn base_state() -> FullCapState {
+        FullCapState {
+            bounding: !capset!(),
+            ..FullCapState::empty()
+        }
+    }
+
+    fn fcaps(effective: bool, permitted: CapSet, inheritable: CapSet) -> FileCaps {
+        FileCaps {
+            effective,
+            permitted,
+            inheritable,
+            rootid: None,
+        }
+    }
+
+    #[test]
+    fn test_execve_unprivileged() {
+        // Nothing is gained
+        assert_eq!(
+            simulate_execve(&base_state(), None, Secbits::empty(), 1000, 1000, false).unwrap(),
+            base_state()
+        );
+
+        // The permitted and effective sets are lost; the inheritable set is preserved
+        let state = FullCapState {
+            permitted: capset!(Cap::CHOWN, Cap::KILL),
+            effective: capset!(Cap::CHOWN),
+            inheritable: capset!(Cap::KILL),
+            ..base_state()
+        };
+        assert_eq!(
+            simulate_execve(&state, None, Secbits::empty(), 1000, 1000, false).unwrap(),
+            FullCapState {
+                inheritable: capset!(Cap::KILL),
+                ..base_state()
+            }
+        );
+    }
+
+    #[test]
+    fn test_execve_ambient() {
+        let state = FullCapState {
+            permitted: capset!(Cap::NET_BIND_SERVICE, Cap::KILL),
+            inheritable: capset!(Cap::NET_BIND_SERVICE),
+            ambient: capset!(Cap::NET_BIND_SERVICE),
+            ..base_state()
+        };
+
+        // Ambient capabilities are preserved and raised in the permitted/effective sets
+        assert_eq!(
+            simulate_execve(&state, None, Secbits::empty(), 1000, 1000, false).unwrap(),
+            FullCapState {
+                permitted: capset!(Cap::NET_BIND_SERVICE),
+                effective: capset!(Cap::NET_BIND_SERVICE),
+                inheritable: capset!(Cap::NET_BIND_SERVICE),
+                ambient: capset!(Cap::NET_BIND_SERVICE),
+                ..base_state()
+            }
+        );
+
+        // File capabilities clear the ambient set
+        assert_eq!(
+            simulate_execve(
+                &state,
+                Some(&fcaps(false, capset!(Cap::NET_RAW), capset!())),
+                Secbits::empty(),
+                1000,
+                1000,
+                false
+            )
+            .unwrap(),
+            FullCapState {
+                permitted: capset!(Cap::NET_RAW),
+                inheritable: capset!(Cap::NET_BIND_SERVICE),
+                ..base_state()
+            }
+        );
+
+        // So does a set-user-ID-root binary
+        assert_eq!(
+            simulate_execve(&state, None, Secbits::NOROOT, 1000, 1000, true).unwrap(),
+            FullCapState {
+                inheritable: capset!(Cap::NET_BIND_SERVICE),
+                ..base_state()
+            }
+        );
+
+        // And running with differing real and effective UIDs (even without a set-user-ID binary)
+        for &(ruid, euid) in [(1000, 2000), (1000, 0)].iter() {
+            assert_eq!(
+                simulate_execve(&state, None, Secbits::NOROOT, ruid, euid, false).unwrap(),
+                FullCapState {
+                    inheritable: capset!(Cap::NET_BIND_SERVICE),
+                    ..base_state()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_execve_file_caps() {
+        let state = FullCapState {
+            inheritable: capset!(Cap::SYSLOG, Cap::KILL),
+            bounding: !capset!(Cap::SYS_ADMIN),
+            ..FullCapState::empty()
+        };
+
+        let new_state = simulate_execve(
+            &state,
+            Some(&fcaps(
+                true,
+                capset!(Cap::NET_RAW),
+                capset!(Cap::KILL, Cap::CHOWN),
+            )),
+            Secbits::empty(),
+            1000,
+            1000,
+            false,
+        )
+        .unwrap();
+        assert_eq!(new_state.permitted, capset!(Cap::NET_RAW, Cap::KILL));
+        assert_eq!(new_state.effective, capset!(Cap::NET_RAW, Cap::KILL));
+        assert_eq!(new_state.inheritable, state.inheritable);
+        assert_eq!(new_state.bounding, state.bounding);
+
+        // Without the effective bit
+        let new_state = simulate_execve(
+            &state,
+            Some(&fcaps(
+                false,
+                capset!(Cap::NET_RAW, Cap::SYS_ADMIN),
+                capset!(),
+            )),
+            Secbits::empty(),
+            1000,
+            1000,
+            false,
+        )
+        .unwrap();
+        assert_eq!(new_state.permitted, capset!(Cap::NET_RAW));
+        assert_eq!(new_state.effective, capset!());
+
+        // With the effective bit, a capability missing from the bounding set causes EPERM
+        assert_eq!(
+            simulate_execve(
+                &state,
+                Some(&fcaps(
+                    true,
+                    capset!(Cap::NET_RAW, Cap::SYS_ADMIN),
+                    capset!()
+                )),
+                Secbits::empty(),
+                1000,
+                1000,
+                false,
+            )
+            .unwrap_err()
+            .code(),
+            libc::EPERM
+        );
+    }
+
+    #[test]
+    fn test_execve_root() {
+        let state = FullCapState {
+            inheritable: capset!(Cap::SYSLOG),
+            bounding: !capset!(Cap::SYS_ADMIN, Cap::SYSLOG),
+            ..FullCapState::empty()
+        };
+        let full = state.bounding | state.inheritable;
+
+        // Real and effective UID 0
+        let new_state = simulate_execve(&state, None, Secbits::empty(), 0, 0, false).unwrap();
+        assert_eq!(new_state.permitted, full);
+        assert_eq!(new_state.effective, full);
+
+        // Only real UID 0 -> effective set is not raised
+        let new_state = simulate_execve(&state, None, Secbits::empty(), 0, 1000, false).unwrap();
+        assert_eq!(new_state.permitted, full);
+        assert_eq!(new_state.effective, capset!());
+
+        // Set-user-ID-root binary
+        let new_state = simulate_execve(&state, None, Secbits::empty(), 1000, 1000, true).unwrap();
+        assert_eq!(new_state.permitted, full);
+        assert_eq!(new_state.effective, full);
+
+        // Set-user-ID-root binary with file capabilities -> the file capabilities win
+        let new_state = simulate_execve(
+            &state,
+            Some(&fcaps(false, capset!(Cap::CHOWN), capset!())),
+            Secbits::empty(),
+            1000,
+            1000,
+            true,
+        )
+        .unwrap();
+        assert_eq!(new_state.permitted, capset!(Cap::CHOWN));
+        assert_eq!(new_state.effective, capset!());
+
+        // But not if the real UID is 0
+        let new_state = simulate_execve(
+            &state,
+            Some(&fcaps(false, capset!(Cap::CHOWN), capset!())),
+            Secbits::empty(),
+            0,
+            1000,
+            true,
+        )
+        .unwrap();
+        assert_eq!(new_state.permitted, full);
+        assert_eq!(new_state.effective, full);
+
+        // NOROOT disables all of this
+        for &(ruid, euid, setuid_root) in
+            [(0, 0, false), (0, 1000, false), (1000, 1000, true)].iter()
+        {
+            assert_eq!(
+                simulate_execve(&state, None, Secbits::NOROOT, ruid, euid, setuid_root).unwrap(),
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn test_execve_no_new_privs() {
+        let state = FullCapState {
+            permitted: capset!(Cap::CHOWN),
+            bounding: !capset!(),
+            no_new_privs: true,
+            ..FullCapState::empty()
+        };
+
+        // The set-user-ID bit is ignored
+        assert_eq!(
+            simulate_execve(&state, None, Secbits::empty(), 1000, 1000, true).unwrap(),
+            FullCapState {
+                permitted: capset!(),
+                ..state
+            }
+        );
+
+        // File capabilities can't grant anything that wasn't already permitted
+        let new_state = simulate_execve(
+            &state,
+            Some(&fcaps(false, capset!(Cap::CHOWN, Cap::NET_RAW), capset!())),
+            Secbits::empty(),
+            1000,
+            1000,
+            false,
+        )
+        .unwrap();
+        assert_eq!(new_state.permitted, capset!(Cap::CHOWN));
+        assert!(new_state.no_new_privs);
+
+        // Same for root
+        let new_state = simulate_execve(&state, None, Secbits::empty(), 0, 0, false).unwrap();
+        assert_eq!(new_state.permitted, capset!(Cap::CHOWN));
+        assert_eq!(new_state.effective, capset!(Cap::CHOWN));
+    }
+
+    #[test]
+    fn test_setuid_drop_root() {
+        let state = FullCapState {
+            permitted: capset!(Cap::CHOWN, Cap::NET_BIND_SERVICE),
+            effective: capset!(Cap::CHOWN, Cap::NET_BIND_SERVICE),
+            inheritable: capset!(Cap::NET_BIND_SERVICE),
+            ambient: capset!(Cap::NET_BIND_SERVICE),
+            ..base_state()
+        };
+
+        // Everything but the inheritable set is lost
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (0, 0, 0, 0),
+                (1000, 1000, 1000, 1000),
+                Secbits::empty()
+            ),
+            FullCapState {
+                inheritable: capset!(Cap::NET_BIND_SERVICE),
+                ..base_state()
+            }
+        );
+
+        // With KEEP_CAPS, the permitted set is preserved (but the ambient set is still cleared)
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (0, 0, 0, 0),
+                (1000, 1000, 1000, 1000),
+                Secbits::KEEP_CAPS
+            ),
+            FullCapState {
+                permitted: capset!(Cap::CHOWN, Cap::NET_BIND_SERVICE),
+                inheritable: capset!(Cap::NET_BIND_SERVICE),
+                ..base_state()
+            }
+        );
+
+        // With NO_SETUID_FIXUP, nothing changes
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (0, 0, 0, 0),
+                (1000, 1000, 1000, 1000),
+                Secbits::NO_SETUID_FIXUP
+            ),
+            state
+        );
+
+        // If the saved UID is still 0, only the effective set is cleared
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (0, 0, 0, 0),
+                (1000, 1000, 0, 1000),
+                Secbits::empty()
+            ),
+            FullCapState {
+                effective: capset!(),
+                ..state
+            }
+        );
+
+        // Nonzero UIDs -> nonzero UIDs changes nothing
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (1000, 1000, 1000, 1000),
+                (1001, 1001, 1001, 1001),
+                Secbits::empty()
+            ),
+            state
+        );
+    }
+
+    #[test]
+    fn test_setuid_regain_root() {
+        let state = FullCapState {
+            permitted: capset!(Cap::CHOWN, Cap::KILL),
+            ..base_state()
+        };
+
+        // Switching the effective UID back to 0 raises the permitted set in the effective set
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (1000, 1000, 0, 1000),
+                (1000, 0, 0, 0),
+                Secbits::empty()
+            ),
+            FullCapState {
+                effective: capset!(Cap::CHOWN, Cap::KILL),
+                ..state
+            }
+        );
+    }
+
+    #[test]
+    fn test_setuid_fsuid() {
+        let state = FullCapState {
+            permitted: capset!(Cap::CHOWN, Cap::KILL, Cap::MKNOD),
+            effective: capset!(Cap::CHOWN, Cap::KILL),
+            ..base_state()
+        };
+
+        // Changing the filesystem UID from 0 lowers the filesystem capabilities
+        let new_state = simulate_setuid(&state, (0, 0, 0, 0), (0, 0, 0, 1000), Secbits::empty());
+        assert_eq!(new_state.effective, capset!(Cap::KILL));
+        assert_eq!(new_state.permitted, state.permitted);
+
+        // Changing it back raises them again (if they're permitted)
+        assert_eq!(
+            simulate_setuid(&new_state, (0, 0, 0, 1000), (0, 0, 0, 0), Secbits::empty()).effective,
+            capset!(Cap::CHOWN, Cap::KILL, Cap::MKNOD)
+        );
+
+        // NO_SETUID_FIXUP disables this
+        assert_eq!(
+            simulate_setuid(
+                &state,
+                (0, 0, 0, 0),
+                (0, 0, 0, 1000),
+                Secbits::NO_SETUID_FIXUP
+            ),
+            state
+        );
+
+        // If the effective UID is changed to nonzero and the filesystem UID is set back to 0,
+        // only the filesystem capabilities are raised again
+        assert_eq!(
+            simulate_setuid(&state, (0, 0, 0, 0), (0, 1000, 0, 0), Secbits::empty()).effective,
+            capset!(Cap::CHOWN, Cap::MKNOD)
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Set up the capabilities (and related attributes) of child processes spawned with
+//! [`std::process::Command`].
+//!
+//! The requested changes are collected in a [`ChildCaps`], and
+//! [`CommandExt::child_caps()`] installs a single `pre_exec` hook (see
+//! [`std::os::unix::process::CommandExt::pre_exec()`]) that performs them in the child process,
+//! after `fork()` and before `exec()`. The hook only makes system calls; it does not allocate
+//! memory or take locks, so it is safe to run in the child of a multithreaded process.
+//!
+//! Some changes affect others, so regardless of the order in which the [`ChildCaps`] methods are
+//! called, the changes are always made in the following order (skipping any that were not
+//! requested):
+//!
+//! 1. [`bounding_caps()`](ChildCaps::bounding_caps)
+//! 2. [`securebits()`](ChildCaps::securebits)
+//! 3. [`keep_caps_as_user()`](ChildCaps::keep_caps_as_user) (this clears the ambient set and the
+//!    "parent death" signal)
+//! 4. [`inheritable_caps()`](ChildCaps::inheritable_caps)
+//! 5. [`ambient_caps()`](ChildCaps::ambient_caps)
+//! 6. [`no_new_privs()`](ChildCaps::no_new_privs)
+//! 7. [`pdeathsig()`](ChildCaps::pdeathsig)
+//!
+//! Example (run a helper as UID/GID 1000 with `CAP_NET_BIND_SERVICE` as its only capability):
+//!
+//! ```no_run
+//! # use capctl::*;
+//! use capctl::command::{ChildCaps, CommandExt};
+//!
+//! std::process::Command::new("/usr/libexec/helper")
+//!     .child_caps(
+//!         ChildCaps::new()
+//!             .bounding_caps(capset!(Cap::NET_BIND_SERVICE))
+//!             .keep_caps_as_user(1000, 1000)
+//!             .ambient_caps(capset!(Cap::NET_BIND_SERVICE))
+//!             .no_new_privs(),
+//!     )
+//!     .spawn()
+//!     .unwrap();
+//! ```
+
+use std::os::unix::process::CommandExt as _;
+use std::process::Command;
+
+use crate::caps::{ambient, bounding, cap_set_ids, Cap, CapSet, CapState};
+use crate::prctl::Secbits;
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for std::process::Command {}
+}
+
+/// Raise `cap` in the effective set, if it is in the permitted set.
+///
+/// If it is not permitted, the operation that needs it will fail with `EPERM`.
+pub(crate) fn try_raise_effective(cap: Cap) -> crate::Result<()> {
+    let mut state = CapState::get_current()?;
+    if state.permitted.has(cap) && !state.effective.has(cap) {
+        state.effective.add(cap);
+        state.set_current()?;
+    }
+    Ok(())
+}
+
+/// Set the ambient set to exactly `caps`, adding them to the inheritable set first.
+pub(crate) fn set_ambient(caps: CapSet) -> crate::Result<()> {
+    let mut state = CapState::get_current()?;
+    if !state.inheritable.issuperset(caps) {
+        state.inheritable |= caps;
+        state.set_current()?;
+    }
+
+    ambient::clear()?;
+    for cap in caps {
+        ambient::raise(cap)?;
+    }
+    Ok(())
+}
+
+/// Reduce the bounding set to `caps`, raising `CAP_SETPCAP` first.
+pub(crate) fn set_bounding(caps: CapSet) -> crate::Result<()> {
+    try_raise_effective(Cap::SETPCAP)?;
+
+    for cap in !caps {
+        if bounding::read(cap) == Some(true) {
+            bounding::drop(cap)?;
+        }
+    }
+    bounding::clear_unknown()
+}
+
+/// The capabilities (and related attributes) to set up in a child process; see
+/// [`CommandExt::child_caps()`].
+///
+/// See the [module-level documentation](self) for the order in which the changes are made.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone, Debug, Default)]
+pub struct ChildCaps {
+    bounding: Option<CapSet>,
+    securebits: Option<Secbits>,
+    user: Option<(libc::uid_t, libc::gid_t)>,
+    inheritable: Option<CapSet>,
+    ambient: Option<CapSet>,
+    no_new_privs: bool,
+    pdeathsig: Option<libc::c_int>,
+}
+
+impl ChildCaps {
+    /// Create a new set of settings that changes nothing.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the child's ambient capability set to exactly `caps`.
+    ///
+    /// The capabilities are first added to the inheritable set (which the kernel requires). They
+    /// must be present in the permitted set.
+    #[inline]
+    pub fn ambient_caps(&mut self, caps: CapSet) -> &mut Self {
+        self.ambient = Some(caps);
+        self
+    }
+
+    /// Set the child's inheritable capability set to exactly `caps`.
+    ///
+    /// Any capabilities that this library is not aware of are removed from the permitted,
+    /// effective, and inheritable sets (see [`CapState::set_current()`]).
+    #[inline]
+    pub fn inheritable_caps(&mut self, caps: CapSet) -> &mut Self {
+        self.inheritable = Some(caps);
+        self
+    }
+
+    /// Reduce the child's bounding capability set to `caps`.
+    ///
+    /// All other capabilities (including ones that this library is not aware of) are dropped.
+    /// `CAP_SETPCAP` is raised in the effective set if necessary.
+    #[inline]
+    pub fn bounding_caps(&mut self, caps: CapSet) -> &mut Self {
+        self.bounding = Some(caps);
+        self
+    }
+
+    /// Set the child's securebits flags to `flags` (see [`crate::prctl::set_securebits()`]).
+    ///
+    /// `CAP_SETPCAP` is raised in the effective set if necessary.
+    #[inline]
+    pub fn securebits(&mut self, flags: Secbits) -> &mut Self {
+        self.securebits = Some(flags);
+        self
+    }
+
+    /// Set the child's "no new privileges" flag.
+    #[inline]
+    pub fn no_new_privs(&mut self) -> &mut Self {
+        self.no_new_privs = true;
+        self
+    }
+
+    /// Switch the child to the given UID and GID (with no supplementary groups), preserving its
+    /// permitted capability set.
+    ///
+    /// This uses [`cap_set_ids()`], so the effective set is emptied afterward. The kernel also
+    /// clears the ambient set and the "parent death" signal when the UIDs change; they are set
+    /// afterward (if requested), so this does not interfere with
+    /// [`ambient_caps()`](#method.ambient_caps) or [`pdeathsig()`](#method.pdeathsig).
+    ///
+    /// Unlike [`std::os::unix::process::CommandExt::uid()`], this does not drop the child's
+    /// capabilities.
+    #[inline]
+    pub fn keep_caps_as_user(&mut self, uid: libc::uid_t, gid: libc::gid_t) -> &mut Self {
+        self.user = Some((uid, gid));
+        self
+    }
+
+    /// Set the signal that the child will receive when its parent dies (see
+    /// [`crate::prctl::set_pdeathsig()`]).
+    ///
+    /// If the parent (the process calling [`CommandExt::child_caps()`]) has already died by the
+    /// time this is set, the child sends the signal to itself immediately, so the race between the
+    /// two is handled.
+    #[inline]
+    pub fn pdeathsig(&mut self, sig: libc::c_int) -> &mut Self {
+        self.pdeathsig = Some(sig);
+        self
+    }
+
+    /// Make the requested changes, in the documented order. Runs in the child.
+    fn apply(&self, parent: libc::pid_t) -> crate::Result<()> {
+        if let Some(caps) = self.bounding {
+            set_bounding(caps)?;
+        }
+
+        if let Some(flags) = self.securebits {
+            try_raise_effective(Cap::SETPCAP)?;
+            crate::prctl::set_securebits(flags)?;
+        }
+
+        if let Some((uid, gid)) = self.user {
+            cap_set_ids(Some(uid), Some(gid), Some(&[]))?;
+        }
+
+        if let Some(caps) = self.inheritable {
+            let mut state = CapState::get_current()?;
+            state.inheritable = caps;
+            state.set_current()?;
+        }
+
+        if let Some(caps) = self.ambient {
+            set_ambient(caps)?;
+        }
+
+        if self.no_new_privs {
+            crate::prctl::set_no_new_privs()?;
+        }
+
+        if let Some(sig) = self.pdeathsig {
+            crate::prctl::set_pdeathsig(Some(sig))?;
+            if unsafe { libc::getppid() } != parent {
+                unsafe {
+                    libc::raise(sig);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Extension methods for [`std::process::Command`] that change the capabilities (and related
+/// attributes) of the child process before it executes the new program.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// This trait is sealed; it cannot be implemented outside this crate.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub trait CommandExt: private::Sealed {
+    /// Make the changes described by `caps` in the child process, before it executes the new
+    /// program.
+    ///
+    /// This installs a single `pre_exec` hook, which makes all of the changes in the order given
+    /// in the [module-level documentation](self).
+    fn child_caps(&mut self, caps: &ChildCaps) -> &mut Self;
+}
+
+impl CommandExt for Command {
+    fn child_caps(&mut self, caps: &ChildCaps) -> &mut Self {
+        let caps = caps.clone();
+        let parent = unsafe { libc::getpid() };
+
+        unsafe {
+            self.pre_exec(move || {
+                caps.apply(parent)?;
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::capset;
+
+    fn child_status(cmd: &mut Command) -> String {
+        let output = cmd.arg("/proc/self/status").output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn status_field<'a>(status: &'a str, key: &str) -> &'a str {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(":\t"))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_no_new_privs_pdeathsig() {
+        let status = child_status(
+            Command::new("cat")
+                .child_caps(ChildCaps::new().no_new_privs().pdeathsig(libc::SIGTERM)),
+        );
+        assert_eq!(status_field(&status, "NoNewPrivs"), "1");
+    }
+
+    #[test]
+    fn test_caps() {
+        let state = CapState::get_current().unwrap();
+        if !state.permitted.has(Cap::SETPCAP)
+            || !state.permitted.has(Cap::NET_BIND_SERVICE)
+            || !bounding::read(Cap::NET_BIND_SERVICE).unwrap()
+            || !ambient::is_supported()
+        {
+            return;
+        }
+
+        let status = child_status(
+            Command::new("cat").child_caps(
+                ChildCaps::new()
+                    .bounding_caps(capset!(Cap::NET_BIND_SERVICE, Cap::SETPCAP))
+                    .inheritable_caps(capset!())
+                    .ambient_caps(capset!(Cap::NET_BIND_SERVICE)),
+            ),
+        );
+
+        assert_eq!(status_field(&status, "CapAmb"), "0000000000000400");
+        assert_eq!(status_field(&status, "CapInh"), "0000000000000400");
+        assert_eq!(status_field(&status, "CapBnd"), "0000000000000500");
+
+        // The parent is unaffected
+        assert_eq!(CapState::get_current().unwrap(), state);
+        assert!(bounding::read(Cap::NET_RAW).unwrap_or(true));
+    }
+
+    #[test]
+    fn test_keep_caps_as_user() {
+        let state = CapState::get_current().unwrap();
+        if !state.permitted.has(Cap::SETUID)
+            || !state.permitted.has(Cap::SETGID)
+            || !state.permitted.has(Cap::NET_BIND_SERVICE)
+            || !ambient::is_supported()
+            // setgroups() is disabled in some user namespaces
+            || std::fs::read_to_string("/proc/self/setgroups").ok().as_deref() == Some("deny\n")
+        {
+            return;
+        }
+
+        let uid = unsafe { libc::geteuid() };
+        let gid = unsafe { libc::getegid() };
+
+        // The order in which the settings are given doesn't matter; the ambient set is still
+        // raised after the IDs change
+        let status = child_status(
+            Command::new("cat").child_caps(
+                ChildCaps::new()
+                    .ambient_caps(capset!(Cap::NET_BIND_SERVICE))
+                    .keep_caps_as_user(uid, gid),
+            ),
+        );
+
+        assert_eq!(status_field(&status, "Groups").trim(), "");
+        assert_eq!(status_field(&status, "CapAmb"), "0000000000000400");
+    }
+}
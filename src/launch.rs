@@ -0,0 +1,630 @@
+//! Launch programs with a specific capability configuration, similar to `libcap`'s `cap_launch()`.
+//!
+//! [`Launcher`] forks a child process, applies the requested changes in the child, and then
+//! executes the program. If any step fails in the child, the step and the error are sent back to
+//! the parent over a close-on-exec pipe, and returned as a [`LaunchError`].
+//!
+//! The steps are performed in the following order (skipping any that were not requested):
+//!
+//! 1. [`Chroot`](LaunchStep::Chroot) (raising `CAP_SYS_CHROOT` in the effective set first)
+//! 2. [`Chdir`](LaunchStep::Chdir) (to `/` if a new root directory was set, but no working
+//!    directory was)
+//! 3. [`Bounding`](LaunchStep::Bounding) (raising `CAP_SETPCAP` in the effective set first)
+//! 4. [`SetIds`](LaunchStep::SetIds) (using [`cap_set_ids()`])
+//! 5. [`Inheritable`](LaunchStep::Inheritable)
+//! 6. [`Ambient`](LaunchStep::Ambient)
+//! 7. [`SignalReset`](LaunchStep::SignalReset) (clearing the signal mask and resetting `SIGPIPE` to
+//!    the default action)
+//! 8. [`Exec`](LaunchStep::Exec)
+//!
+//! Example:
+//!
+//! ```no_run
+//! # use capctl::*;
+//! use capctl::launch::Launcher;
+//!
+//! let pid = Launcher::new("/usr/libexec/helper")
+//!     .arg("--verbose")
+//!     .uid(1000)
+//!     .gid(1000)
+//!     .groups(&[])
+//!     .bounding(capset!(Cap::NET_BIND_SERVICE))
+//!     .ambient(capset!(Cap::NET_BIND_SERVICE))
+//!     .launch()
+//!     .unwrap();
+//! ```
+
+use std::ffi::{CString, OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::caps::{cap_set_ids, Cap, CapSet, CapState};
+use crate::command::{set_ambient, set_bounding, try_raise_effective};
+
+/// A step performed while launching a program (see the [module-level documentation](self)).
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum LaunchStep {
+    /// Preparing to launch the program (converting arguments, creating the pipe, forking).
+    Setup,
+    /// Changing the root directory.
+    Chroot,
+    /// Changing the working directory.
+    Chdir,
+    /// Reducing the bounding set.
+    Bounding,
+    /// Changing the UID, GID, and/or supplementary groups.
+    SetIds,
+    /// Setting the inheritable set.
+    Inheritable,
+    /// Setting the ambient set.
+    Ambient,
+    /// Clearing the signal mask and resetting `SIGPIPE` to the default action.
+    SignalReset,
+    /// Executing the program.
+    Exec,
+}
+
+impl LaunchStep {
+    const ALL: [Self; 9] = [
+        Self::Setup,
+        Self::Chroot,
+        Self::Chdir,
+        Self::Bounding,
+        Self::SetIds,
+        Self::Inheritable,
+        Self::Ambient,
+        Self::SignalReset,
+        Self::Exec,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Setup => "setup",
+            Self::Chroot => "chroot",
+            Self::Chdir => "chdir",
+            Self::Bounding => "bounding",
+            Self::SetIds => "set_ids",
+            Self::Inheritable => "inheritable",
+            Self::Ambient => "ambient",
+            Self::SignalReset => "signal_reset",
+            Self::Exec => "exec",
+        }
+    }
+}
+
+impl fmt::Display for LaunchStep {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Represents an error that occurred while launching a program.
+#[derive(Debug)]
+pub struct LaunchError {
+    step: LaunchStep,
+    error: crate::Error,
+}
+
+impl LaunchError {
+    /// Get the step that failed.
+    #[inline]
+    pub fn step(&self) -> LaunchStep {
+        self.step
+    }
+
+    /// Get the error that occurred.
+    #[inline]
+    pub fn error(&self) -> &crate::Error {
+        &self.error
+    }
+
+    #[inline]
+    fn new(step: LaunchStep, eno: i32) -> Self {
+        Self {
+            step,
+            error: crate::Error::from_code(eno),
+        }
+    }
+}
+
+impl fmt::Display for LaunchError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} step failed: {}", self.step, self.error)
+    }
+}
+
+impl std::error::Error for LaunchError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<LaunchError> for io::Error {
+    #[inline]
+    fn from(e: LaunchError) -> Self {
+        Self::new(io::Error::from_raw_os_error(e.error.code()).kind(), e)
+    }
+}
+
+fn to_cstring(s: &OsStr) -> Result<CString, LaunchError> {
+    CString::new(s.as_bytes()).map_err(|_| LaunchError::new(LaunchStep::Setup, libc::EINVAL))
+}
+
+/// A builder for launching a program with a specific capability configuration.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct Launcher {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Option<Vec<OsString>>,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    groups: Option<Vec<libc::gid_t>>,
+    inheritable: Option<CapSet>,
+    ambient: Option<CapSet>,
+    bounding: Option<CapSet>,
+    chroot: Option<OsString>,
+    current_dir: Option<OsString>,
+}
+
+impl Launcher {
+    /// Create a new launcher for the program at the given path.
+    ///
+    /// The program is executed with `execve()`; `PATH` is not searched. The first argument
+    /// (`argv[0]`) is set to `program`.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().into(),
+            args: vec![program.as_ref().into()],
+            env: None,
+            uid: None,
+            gid: None,
+            groups: None,
+            inheritable: None,
+            ambient: None,
+            bounding: None,
+            chroot: None,
+            current_dir: None,
+        }
+    }
+
+    /// Add an argument to pass to the program.
+    #[inline]
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().into());
+        self
+    }
+
+    /// Add multiple arguments to pass to the program.
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(&mut self, args: I) -> &mut Self {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Set the environment of the program to exactly the given variables.
+    ///
+    /// By default, the program inherits the environment of the current process.
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env = Some(
+            vars.into_iter()
+                .map(|(key, val)| {
+                    let mut var = key.as_ref().to_os_string();
+                    var.push("=");
+                    var.push(val);
+                    var
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// Set the real, effective, and saved UIDs of the child to `uid`.
+    #[inline]
+    pub fn uid(&mut self, uid: libc::uid_t) -> &mut Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Set the real, effective, and saved GIDs of the child to `gid`.
+    #[inline]
+    pub fn gid(&mut self, gid: libc::gid_t) -> &mut Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Set the supplementary group list of the child to `groups`.
+    #[inline]
+    pub fn groups(&mut self, groups: &[libc::gid_t]) -> &mut Self {
+        self.groups = Some(groups.into());
+        self
+    }
+
+    /// Set the child's inheritable capability set to exactly `caps`.
+    ///
+    /// (Any capabilities set with [`ambient()`](#method.ambient) are also added to the inheritable
+    /// set.)
+    #[inline]
+    pub fn inheritable(&mut self, caps: CapSet) -> &mut Self {
+        self.inheritable = Some(caps);
+        self
+    }
+
+    /// Set the child's ambient capability set to exactly `caps`.
+    #[inline]
+    pub fn ambient(&mut self, caps: CapSet) -> &mut Self {
+        self.ambient = Some(caps);
+        self
+    }
+
+    /// Reduce the child's bounding capability set to `caps`.
+    ///
+    /// All other capabilities (including ones that this library is not aware of) are dropped.
+    #[inline]
+    pub fn bounding(&mut self, caps: CapSet) -> &mut Self {
+        self.bounding = Some(caps);
+        self
+    }
+
+    /// Change the child's root directory to `dir`.
+    ///
+    /// The program path, and the working directory set with
+    /// [`current_dir()`](#method.current_dir), are interpreted relative to the new root.
+    #[inline]
+    pub fn chroot<S: AsRef<OsStr>>(&mut self, dir: S) -> &mut Self {
+        self.chroot = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Change the child's working directory to `dir`.
+    #[inline]
+    pub fn current_dir<S: AsRef<OsStr>>(&mut self, dir: S) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Launch the program, returning the PID of the child process.
+    ///
+    /// The caller is responsible for waiting for the child (for example, with `waitpid()`). If
+    /// launching fails after the child has been created, it is waited for automatically.
+    pub fn launch(&self) -> Result<libc::pid_t, LaunchError> {
+        // Everything the child needs must be allocated before forking
+        let program = to_cstring(&self.program)?;
+        let args = self
+            .args
+            .iter()
+            .map(|arg| to_cstring(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let env = self
+            .env
+            .as_ref()
+            .map(|env| {
+                env.iter()
+                    .map(|var| to_cstring(var))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        let chroot = self.chroot.as_deref().map(to_cstring).transpose()?;
+        let current_dir = match (self.current_dir.as_deref(), chroot.is_some()) {
+            (Some(dir), _) => Some(to_cstring(dir)?),
+            (None, true) => Some(CString::new("/").unwrap()),
+            (None, false) => None,
+        };
+
+        let mut argv: Vec<*const libc::c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(core::ptr::null());
+        let envp = env.as_ref().map(|env| {
+            let mut envp: Vec<*const libc::c_char> = env.iter().map(|var| var.as_ptr()).collect();
+            envp.push(core::ptr::null());
+            envp
+        });
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(LaunchError::new(
+                LaunchStep::Setup,
+                crate::Error::last().code(),
+            ));
+        }
+        let [rfd, wfd] = fds;
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            let eno = crate::Error::last().code();
+            unsafe {
+                libc::close(rfd);
+                libc::close(wfd);
+            }
+            return Err(LaunchError::new(LaunchStep::Setup, eno));
+        }
+
+        if pid == 0 {
+            unsafe {
+                libc::close(rfd);
+            }
+
+            let (step, eno) = self.child(
+                &program,
+                &argv,
+                envp.as_deref(),
+                chroot.as_deref(),
+                current_dir.as_deref(),
+            );
+
+            let mut buf = [0u8; 8];
+            buf[..4].copy_from_slice(&(step as u32).to_ne_bytes());
+            buf[4..].copy_from_slice(&eno.to_ne_bytes());
+            unsafe {
+                libc::write(wfd, buf.as_ptr() as *const _, buf.len());
+                libc::_exit(127);
+            }
+        }
+
+        unsafe {
+            libc::close(wfd);
+        }
+
+        let mut buf = [0u8; 8];
+        let mut len = 0;
+        while len < buf.len() {
+            let n = unsafe { libc::read(rfd, buf[len..].as_mut_ptr() as *mut _, buf.len() - len) };
+            if n > 0 {
+                len += n as usize;
+            } else if n == 0 || crate::Error::last().code() != libc::EINTR {
+                break;
+            }
+        }
+
+        unsafe {
+            libc::close(rfd);
+        }
+
+        if len == 0 {
+            // The pipe was closed by a successful exec()
+            return Ok(pid);
+        }
+
+        let mut status = 0;
+        while unsafe { libc::waitpid(pid, &mut status, 0) } < 0
+            && crate::Error::last().code() == libc::EINTR
+        {}
+
+        if len != buf.len() {
+            return Err(LaunchError::new(LaunchStep::Setup, libc::EIO));
+        }
+
+        let mut step = [0; 4];
+        let mut eno = [0; 4];
+        step.copy_from_slice(&buf[..4]);
+        eno.copy_from_slice(&buf[4..]);
+
+        let step = LaunchStep::ALL
+            .get(u32::from_ne_bytes(step) as usize)
+            .copied()
+            .unwrap_or(LaunchStep::Setup);
+        Err(LaunchError::new(step, i32::from_ne_bytes(eno)))
+    }
+
+    /// Run in the child after `fork()`. Only returns on failure.
+    ///
+    /// This must not allocate memory.
+    fn child(
+        &self,
+        program: &CString,
+        argv: &[*const libc::c_char],
+        envp: Option<&[*const libc::c_char]>,
+        chroot: Option<&std::ffi::CStr>,
+        current_dir: Option<&std::ffi::CStr>,
+    ) -> (LaunchStep, i32) {
+        macro_rules! check {
+            ($step:expr, $res:expr) => {
+                if let Err(e) = $res {
+                    return ($step, e.code());
+                }
+            };
+        }
+
+        macro_rules! check_ret {
+            ($step:expr, $ret:expr) => {
+                if $ret < 0 {
+                    return ($step, crate::Error::last().code());
+                }
+            };
+        }
+
+        if let Some(dir) = chroot {
+            check!(LaunchStep::Chroot, try_raise_effective(Cap::SYS_CHROOT));
+            check_ret!(LaunchStep::Chroot, unsafe { libc::chroot(dir.as_ptr()) });
+        }
+
+        if let Some(dir) = current_dir {
+            check_ret!(LaunchStep::Chdir, unsafe { libc::chdir(dir.as_ptr()) });
+        }
+
+        if let Some(caps) = self.bounding {
+            check!(LaunchStep::Bounding, set_bounding(caps));
+        }
+
+        if self.uid.is_some() || self.gid.is_some() || self.groups.is_some() {
+            check!(
+                LaunchStep::SetIds,
+                cap_set_ids(self.uid, self.gid, self.groups.as_deref())
+            );
+        }
+
+        if let Some(caps) = self.inheritable {
+            check!(
+                LaunchStep::Inheritable,
+                CapState::get_current().and_then(|mut state| {
+                    state.inheritable = caps;
+                    state.set_current()
+                })
+            );
+        }
+
+        if let Some(caps) = self.ambient {
+            check!(LaunchStep::Ambient, set_ambient(caps));
+        }
+
+        // Don't pass on the signal mask or the ignored SIGPIPE (which the Rust runtime sets up) to
+        // the program
+        unsafe {
+            let mut set = core::mem::MaybeUninit::<libc::sigset_t>::uninit();
+            libc::sigemptyset(set.as_mut_ptr());
+            check_ret!(
+                LaunchStep::SignalReset,
+                libc::sigprocmask(libc::SIG_SETMASK, set.as_ptr(), core::ptr::null_mut())
+            );
+            if libc::signal(libc::SIGPIPE, libc::SIG_DFL) == libc::SIG_ERR {
+                return (LaunchStep::SignalReset, crate::Error::last().code());
+            }
+        }
+
+        unsafe {
+            match envp {
+                Some(envp) => libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr()),
+                None => libc::execv(program.as_ptr(), argv.as_ptr()),
+            };
+        }
+
+        (LaunchStep::Exec, crate::Error::last().code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::caps::ambient;
+    use crate::capset;
+
+    fn wait(pid: libc::pid_t) -> libc::c_int {
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        libc::WEXITSTATUS(status)
+    }
+
+    #[test]
+    fn test_launch() {
+        assert_eq!(wait(Launcher::new("/bin/true").launch().unwrap()), 0);
+        assert_eq!(
+            wait(
+                Launcher::new("/bin/sh")
+                    .args(["-c", "test \"$FOO\" = bar && test \"$(pwd)\" = /"])
+                    .envs(vec![("FOO", "bar")])
+                    .current_dir("/")
+                    .launch()
+                    .unwrap()
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn test_launch_signals() {
+        std::thread::spawn(|| {
+            unsafe {
+                let mut set = core::mem::MaybeUninit::<libc::sigset_t>::uninit();
+                libc::sigemptyset(set.as_mut_ptr());
+                libc::sigaddset(set.as_mut_ptr(), libc::SIGUSR1);
+                libc::pthread_sigmask(libc::SIG_BLOCK, set.as_ptr(), core::ptr::null_mut());
+            }
+
+            // Nothing is blocked, and SIGPIPE isn't ignored, in the child
+            assert_eq!(
+                wait(
+                    Launcher::new("/bin/sh")
+                        .args([
+                            "-c",
+                            // Only use builtins; the shell blocks signals while it forks
+                            "while read -r key val; do
+                                 case $key in
+                                     SigBlk:) blk=$val ;;
+                                     SigIgn:) ign=$val ;;
+                                 esac
+                             done </proc/self/status
+                             test $((0x$blk)) = 0 && test $((0x$ign & 0x1000)) = 0",
+                        ])
+                        .launch()
+                        .unwrap()
+                ),
+                0
+            );
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_launch_errors() {
+        let err = Launcher::new("/nonexistent").launch().unwrap_err();
+        assert_eq!(err.step(), LaunchStep::Exec);
+        assert_eq!(err.error().code(), libc::ENOENT);
+
+        let err = Launcher::new("/bin/true")
+            .current_dir("/nonexistent")
+            .launch()
+            .unwrap_err();
+        assert_eq!(err.step(), LaunchStep::Chdir);
+        assert_eq!(err.error().code(), libc::ENOENT);
+        assert_eq!(
+            io::Error::from(err).kind(),
+            io::Error::from_raw_os_error(libc::ENOENT).kind()
+        );
+
+        let err = Launcher::new("/bin/true\0").launch().unwrap_err();
+        assert_eq!(err.step(), LaunchStep::Setup);
+        assert_eq!(err.error().code(), libc::EINVAL);
+        assert_eq!(
+            err.to_string(),
+            format!("setup step failed: {}", err.error())
+        );
+    }
+
+    #[test]
+    fn test_launch_caps() {
+        let state = CapState::get_current().unwrap();
+        if !state.permitted.has(Cap::SETPCAP)
+            || !state.permitted.has(Cap::NET_BIND_SERVICE)
+            || !ambient::is_supported()
+        {
+            return;
+        }
+
+        let pid = Launcher::new("/bin/sh")
+            .args([
+                "-c",
+                "grep -q '^CapAmb:.0*400$' /proc/self/status \
+                    && grep -q '^CapBnd:.0*500$' /proc/self/status",
+            ])
+            .bounding(capset!(Cap::NET_BIND_SERVICE, Cap::SETPCAP))
+            .inheritable(capset!())
+            .ambient(capset!(Cap::NET_BIND_SERVICE))
+            .launch()
+            .unwrap();
+        assert_eq!(wait(pid), 0);
+
+        // The kernel refuses to add capabilities to the ambient set that are not in the
+        // bounding set
+        let err = Launcher::new("/bin/true")
+            .bounding(capset!(Cap::SETPCAP))
+            .ambient(capset!(Cap::NET_BIND_SERVICE))
+            .launch()
+            .unwrap_err();
+        assert_eq!(err.step(), LaunchStep::Ambient);
+        assert_eq!(err.error().code(), libc::EPERM);
+    }
+}
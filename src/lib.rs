@@ -19,7 +19,8 @@
 //!   in the returned capability sets.
 //! - Trying to include the unsupported capability(s) in the new permitted/effective/inheritable
 //!   sets with [`caps::CapState::set_current()`] will cause them to be silently removed from the
-//!   new sets. (This is a kernel limitation.)
+//!   new sets. (This is a kernel limitation.) [`caps::CapState::set_current_verified()`] and
+//!   [`caps::CapState::set_current_strict()`] can be used to detect this.
 //! - The following functions will return an `Error` with code `EINVAL` if passed the unsupported
 //!   capability:
 //!   - [`caps::bounding::drop()`]
@@ -36,7 +37,8 @@
 //!
 //! - If the permitted, effective, and/or inheritable capability sets of this process are modified
 //!   (in any way) using [`caps::CapState`], the unknown capability(s) will be removed from the
-//!   permitted, effective, and inheritable sets.
+//!   permitted, effective, and inheritable sets. [`caps::RawCapState`] can be used to modify these
+//!   sets while preserving the unknown capability(s).
 //! - The following functions are the **ONLY** functions in this crate that can be used to remove
 //!   the unknown capability(s) from the ambient/bounding sets (see their documentation for more
 //!   information):
@@ -47,6 +49,14 @@
 //!
 //! As a result, if you are trying to clear the ambient and/or bounding capability sets, you must
 //! call the `clear()` or `clear_unknown()` function for whichever set you want to clear.
+//!
+//! # Upgrading from 0.2
+//!
+//! - [`caps::FileCaps::get_for_fd()`], [`caps::FileCaps::set_for_fd()`], and
+//!   [`caps::FileCaps::remove_for_fd()`] now take any type that implements
+//!   [`AsFd`](std::os::unix::io::AsFd) instead of a `RawFd`. Pass a reference to the open file
+//!   (for example, `&file`), or wrap a raw file descriptor with
+//!   [`BorrowedFd::borrow_raw()`](std::os::unix::io::BorrowedFd::borrow_raw).
 
 #![cfg_attr(not(feature = "std"), no_std)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
@@ -55,7 +65,19 @@ mod err;
 mod sys;
 
 pub mod caps;
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+pub mod command;
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+pub mod launch;
 pub mod prctl;
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+pub mod procs;
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+pub mod threads;
 
 pub use caps::*;
 pub use err::*;
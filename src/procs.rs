@@ -0,0 +1,691 @@
+//! Enumerate the capabilities of the processes (and threads) running on the system.
+//!
+//! This provides the information displayed by tools like `getpcaps` and `pscap`, by reading the
+//! `/proc/<pid>/status` files.
+//!
+//! Example (list every process that has any capabilities):
+//!
+//! ```no_run
+//! # use capctl::procs::Processes;
+//! for info in Processes::new().unwrap().with_caps() {
+//!     let info = info.unwrap();
+//!     println!("{} ({}): {:?}", info.pid, info.comm, info.caps.permitted);
+//! }
+//! ```
+//!
+//! Processes that exit while they are being enumerated are skipped.
+//!
+//! [`ProcessSecurityStatus`] provides more detailed information about a single process (GIDs,
+//! supplementary groups, seccomp and speculation mitigation status, etc.).
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd};
+use std::path::Path;
+
+use crate::caps::FullCapState;
+
+type CapsFilter = Box<dyn FnMut(&FullCapState) -> bool>;
+
+/// Read the `/proc/<pid>/status` file (or similar) at `path`, calling `f` with the key and value
+/// of each line.
+///
+/// If the file does not exist, this fails with `ESRCH`.
+pub(crate) fn read_status<F: FnMut(&str, &str) -> io::Result<()>>(
+    path: &Path,
+    f: F,
+) -> io::Result<()> {
+    match fs::File::open(path) {
+        Ok(file) => parse_status(file, f),
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+            Err(io::Error::from_raw_os_error(libc::ESRCH))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Like [`read_status()`], but reads the `status` file in the given `/proc/<pid>` directory (see
+/// [`open_pidfd_dir()`]).
+pub(crate) fn read_status_at<F: FnMut(&str, &str) -> io::Result<()>>(
+    dir: &fs::File,
+    f: F,
+) -> io::Result<()> {
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            b"status\0".as_ptr() as *const libc::c_char,
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    };
+
+    if fd < 0 {
+        let e = io::Error::last_os_error();
+        return Err(if e.raw_os_error() == Some(libc::ENOENT) {
+            io::Error::from_raw_os_error(libc::ESRCH)
+        } else {
+            e
+        });
+    }
+
+    parse_status(unsafe { fs::File::from_raw_fd(fd) }, f)
+}
+
+fn parse_status<F: FnMut(&str, &str) -> io::Result<()>>(
+    file: fs::File,
+    mut f: F,
+) -> io::Result<()> {
+    let mut reader = io::BufReader::new(file);
+    let mut line = String::new();
+
+    while reader.read_line(&mut line)? > 0 {
+        if line.ends_with('\n') {
+            line.pop();
+        }
+
+        if let Some(i) = line.find(":\t") {
+            f(&line[..i], &line[i + 2..])?;
+        }
+
+        line.clear();
+    }
+
+    Ok(())
+}
+
+/// Get the PID of the process referred to by the given pidfd (in the current PID namespace).
+///
+/// This fails with `ESRCH` if the process has exited (or is not visible in the current PID
+/// namespace), and with `EINVAL` if `pidfd` is not a pidfd.
+pub(crate) fn pidfd_getpid(pidfd: BorrowedFd) -> io::Result<libc::pid_t> {
+    let mut pid = None;
+
+    read_status(
+        format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd()).as_ref(),
+        |key, value| {
+            if key == "Pid" {
+                pid = Some(parse_id::<libc::pid_t>(value)?);
+            }
+            Ok(())
+        },
+    )
+    .map_err(|e| {
+        if e.raw_os_error() == Some(libc::ESRCH) {
+            io::Error::from_raw_os_error(libc::EBADF)
+        } else {
+            e
+        }
+    })?;
+
+    match pid {
+        Some(pid) if pid > 0 => Ok(pid),
+        Some(_) => Err(io::Error::from_raw_os_error(libc::ESRCH)),
+        None => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+    }
+}
+
+/// Check that the process referred to by the given pidfd is still alive, failing with `ESRCH` if
+/// it is not.
+///
+/// While the process is alive, its PID cannot be reused. So if this succeeds after a PID returned
+/// by [`pidfd_getpid()`] has been used, the PID referred to the same process the whole time.
+pub(crate) fn pidfd_check_alive(pidfd: BorrowedFd) -> io::Result<()> {
+    if unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            0,
+            core::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    } < 0
+    {
+        let err = io::Error::last_os_error();
+        // The permission check is only done if the process exists, so EPERM means it's alive (we
+        // just aren't allowed to signal it)
+        if err.raw_os_error() != Some(libc::EPERM) {
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Open the `/proc/<pid>` directory of the process referred to by the given pidfd.
+///
+/// Files opened relative to the returned directory are guaranteed to refer to the same process as
+/// the pidfd (or reading them fails if it has exited).
+pub(crate) fn open_pidfd_dir(pidfd: BorrowedFd) -> io::Result<fs::File> {
+    let pid = pidfd_getpid(pidfd)?;
+
+    let dir = match fs::File::open(format!("/proc/{}", pid)) {
+        Ok(dir) => dir,
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+            return Err(io::Error::from_raw_os_error(libc::ESRCH))
+        }
+        Err(e) => return Err(e),
+    };
+
+    pidfd_check_alive(pidfd)?;
+    Ok(dir)
+}
+
+fn parse_id<T: core::str::FromStr>(value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+/// The real, effective, saved, and filesystem UIDs (or GIDs) of a process.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Ids<T> {
+    pub real: T,
+    pub effective: T,
+    pub saved: T,
+    pub filesystem: T,
+}
+
+impl<T: core::str::FromStr + Copy> Ids<T> {
+    /// Parse the value of a `Uid:` or `Gid:` line of a `/proc/<pid>/status` file.
+    pub(crate) fn parse(value: &str) -> io::Result<Self> {
+        let mut it = value.split('\t');
+        let mut next = || parse_id(it.next().unwrap_or(""));
+
+        Ok(Self {
+            real: next()?,
+            effective: next()?,
+            saved: next()?,
+            filesystem: next()?,
+        })
+    }
+}
+
+/// Information about the capabilities of a single process (or thread).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ProcessInfo {
+    /// The PID of the process (or the TID of the thread).
+    pub pid: libc::pid_t,
+    /// The thread group ID (i.e. the PID of the process this thread belongs to). For a process's
+    /// main thread, this is equal to `pid`.
+    pub tgid: libc::pid_t,
+    /// The PID of the parent process.
+    pub ppid: libc::pid_t,
+    /// The command name (see `proc(5)`).
+    pub comm: String,
+    /// The UIDs of the process.
+    pub uids: Ids<libc::uid_t>,
+    /// The capability state of the process.
+    pub caps: FullCapState,
+}
+
+impl ProcessInfo {
+    /// Get information about the process (or thread) with the given PID (or TID).
+    ///
+    /// This fails with `ESRCH` if the process does not exist.
+    pub fn get(pid: libc::pid_t) -> io::Result<Self> {
+        if pid <= 0 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        Self::read(&format!("/proc/{}/status", pid))
+    }
+
+    fn empty() -> Self {
+        Self {
+            pid: 0,
+            tgid: 0,
+            ppid: 0,
+            comm: String::new(),
+            uids: Ids::default(),
+            caps: FullCapState::empty(),
+        }
+    }
+
+    fn read(path: &str) -> io::Result<Self> {
+        let mut info = Self::empty();
+        read_status(path.as_ref(), |key, value| {
+            info.parse_status_field(key, value)
+        })?;
+        Ok(info)
+    }
+
+    fn parse_status_field(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match key {
+            "Name" => self.comm = unescape_name(value),
+            "Pid" => self.pid = parse_id(value)?,
+            "Tgid" => self.tgid = parse_id(value)?,
+            "PPid" => self.ppid = parse_id(value)?,
+            "Uid" => self.uids = Ids::parse(value)?,
+            _ => self.caps.parse_status_field(key, value)?,
+        }
+        Ok(())
+    }
+
+    /// Check whether this process has any capabilities in its permitted, effective, inheritable,
+    /// or ambient sets.
+    ///
+    /// (The bounding set is not considered, since it is usually full.)
+    #[inline]
+    pub fn has_caps(&self) -> bool {
+        has_caps(&self.caps)
+    }
+}
+
+/// The seccomp mode of a process.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum SeccompMode {
+    /// Seccomp is not in use.
+    Disabled,
+    /// Strict mode (`SECCOMP_MODE_STRICT`).
+    Strict,
+    /// Filter mode (`SECCOMP_MODE_FILTER`).
+    Filter,
+}
+
+/// Extended information about the security-related attributes of a single process (or thread).
+///
+/// All of the information except [`user_ns`](#structfield.user_ns) is read from a single pass over
+/// `/proc/<pid>/status`, so it is consistent.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ProcessSecurityStatus {
+    /// The basic information about the process (including its capabilities).
+    pub process: ProcessInfo,
+    /// The GIDs of the process.
+    pub gids: Ids<libc::gid_t>,
+    /// The supplementary groups of the process.
+    pub groups: Vec<libc::gid_t>,
+    /// The seccomp mode of the process, or `None` if the kernel was built without seccomp support.
+    pub seccomp: Option<SeccompMode>,
+    /// The number of seccomp filters attached to the process (Linux 5.9+).
+    pub seccomp_filters: Option<u32>,
+    /// The speculative store bypass mitigation status (for example, `"thread vulnerable"`), as
+    /// reported by the kernel (Linux 4.17+).
+    pub speculation_store_bypass: Option<String>,
+    /// The indirect branch speculation mitigation status (for example, `"conditional enabled"`),
+    /// as reported by the kernel (Linux 5.6+).
+    pub speculation_indirect_branch: Option<String>,
+    /// The PID of the process in each of the PID namespaces it is a member of, from the outermost
+    /// to the innermost (Linux 4.1+).
+    pub ns_pids: Vec<libc::pid_t>,
+    /// The inode number identifying the user namespace of the process, or `None` if it could not
+    /// be determined (for example, because of insufficient permissions).
+    ///
+    /// This is read from the `/proc/<pid>/ns/user` symlink, after the `status` file.
+    pub user_ns: Option<u64>,
+}
+
+impl ProcessSecurityStatus {
+    /// Get the security status of the process (or thread) with the given PID (or TID).
+    ///
+    /// This fails with `ESRCH` if the process does not exist.
+    pub fn get(pid: libc::pid_t) -> io::Result<Self> {
+        if pid <= 0 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        let mut status = Self {
+            process: ProcessInfo::empty(),
+            gids: Ids::default(),
+            groups: Vec::new(),
+            seccomp: None,
+            seccomp_filters: None,
+            speculation_store_bypass: None,
+            speculation_indirect_branch: None,
+            ns_pids: Vec::new(),
+            user_ns: None,
+        };
+
+        read_status(format!("/proc/{}/status", pid).as_ref(), |key, value| {
+            status.parse_status_field(key, value)
+        })?;
+
+        status.user_ns = fs::read_link(format!("/proc/{}/ns/user", pid))
+            .ok()
+            .and_then(|target| {
+                target
+                    .to_str()?
+                    .strip_prefix("user:[")?
+                    .strip_suffix(']')?
+                    .parse()
+                    .ok()
+            });
+
+        Ok(status)
+    }
+
+    fn parse_status_field(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match key {
+            "Gid" => self.gids = Ids::parse(value)?,
+            "Groups" => {
+                self.groups = value
+                    .split_whitespace()
+                    .map(parse_id)
+                    .collect::<io::Result<_>>()?
+            }
+            "NSpid" => {
+                self.ns_pids = value
+                    .split_whitespace()
+                    .map(parse_id)
+                    .collect::<io::Result<_>>()?
+            }
+            "Seccomp" => {
+                self.seccomp = Some(match value {
+                    "0" => SeccompMode::Disabled,
+                    "1" => SeccompMode::Strict,
+                    "2" => SeccompMode::Filter,
+                    _ => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+                })
+            }
+            "Seccomp_filters" => self.seccomp_filters = Some(parse_id(value)?),
+            "Speculation_Store_Bypass" => self.speculation_store_bypass = Some(value.into()),
+            "SpeculationIndirectBranch" => self.speculation_indirect_branch = Some(value.into()),
+            _ => self.process.parse_status_field(key, value)?,
+        }
+        Ok(())
+    }
+}
+
+fn has_caps(caps: &FullCapState) -> bool {
+    !(caps.permitted.is_empty()
+        && caps.effective.is_empty()
+        && caps.inheritable.is_empty()
+        && caps.ambient.is_empty())
+}
+
+/// The kernel escapes backslashes and newlines in the `Name:` field.
+fn unescape_name(value: &str) -> String {
+    if !value.contains('\\') {
+        return value.into();
+    }
+
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n') => res.push('\n'),
+                Some(ch) => res.push(ch),
+                None => res.push('\\'),
+            }
+        } else {
+            res.push(ch);
+        }
+    }
+    res
+}
+
+fn is_vanished(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ESRCH) | Some(libc::ENOENT))
+}
+
+fn parse_pid_entry(entry: io::Result<fs::DirEntry>) -> io::Result<Option<libc::pid_t>> {
+    Ok(entry?
+        .file_name()
+        .to_str()
+        .and_then(|name| name.parse().ok())
+        .filter(|&pid| pid > 0))
+}
+
+/// An iterator over the processes (and optionally threads) running on the system.
+///
+/// Each item is the [`ProcessInfo`] for one process, in the order they are listed in `/proc`.
+/// Processes that exit while they are being enumerated are skipped; other errors are returned
+/// (and enumeration can continue afterward).
+pub struct Processes {
+    procs: fs::ReadDir,
+    tasks: Option<(libc::pid_t, fs::ReadDir)>,
+    threads: bool,
+    filter: Option<CapsFilter>,
+}
+
+impl Processes {
+    /// Begin enumerating processes.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            procs: fs::read_dir("/proc")?,
+            tasks: None,
+            threads: false,
+            filter: None,
+        })
+    }
+
+    /// Set whether to list every thread of each process, instead of only the main thread (default
+    /// `false`).
+    ///
+    /// Since capabilities are per-thread attributes, the threads of a process may have different
+    /// capabilities.
+    pub fn threads(mut self, threads: bool) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Only list processes for which `filter` returns `true` when passed their capability state.
+    ///
+    /// This replaces any filter set previously (including by [`with_caps()`](#method.with_caps)).
+    pub fn caps_filter<F: FnMut(&FullCapState) -> bool + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Only list processes that have any capabilities (see [`ProcessInfo::has_caps()`]).
+    pub fn with_caps(self) -> Self {
+        self.caps_filter(has_caps)
+    }
+
+    /// Get the path of the next `status` file to read, or `None` if there are no more.
+    fn next_path(&mut self) -> Option<io::Result<String>> {
+        loop {
+            if let Some((pid, tasks)) = self.tasks.as_mut() {
+                if let Some(entry) = tasks.next() {
+                    match parse_pid_entry(entry) {
+                        Ok(Some(tid)) => {
+                            return Some(Ok(format!("/proc/{}/task/{}/status", pid, tid)))
+                        }
+                        Ok(None) => continue,
+                        Err(e) if is_vanished(&e) => (),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                self.tasks = None;
+            }
+
+            let pid = match parse_pid_entry(self.procs.next()?) {
+                Ok(Some(pid)) => pid,
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if !self.threads {
+                return Some(Ok(format!("/proc/{}/status", pid)));
+            }
+
+            match fs::read_dir(format!("/proc/{}/task", pid)) {
+                Ok(tasks) => self.tasks = Some((pid, tasks)),
+                Err(e) if is_vanished(&e) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Iterator for Processes {
+    type Item = io::Result<ProcessInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let path = match self.next_path()? {
+                Ok(path) => path,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match ProcessInfo::read(&path) {
+                Ok(info) => {
+                    if let Some(filter) = self.filter.as_mut() {
+                        if !filter(&info.caps) {
+                            continue;
+                        }
+                    }
+                    return Some(Ok(info));
+                }
+                Err(e) if is_vanished(&e) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl core::iter::FusedIterator for Processes {}
+
+impl core::fmt::Debug for Processes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Processes")
+            .field("threads", &self.threads)
+            .field("filter", &self.filter.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn getpid() -> libc::pid_t {
+        unsafe { libc::getpid() }
+    }
+
+    fn gettid() -> libc::pid_t {
+        unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+    }
+
+    #[test]
+    fn test_pidfd_check_alive() {
+        use std::os::unix::io::{AsFd, OwnedFd};
+
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, getpid(), 0) } as i32;
+        if pidfd < 0 {
+            return;
+        }
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd) };
+        pidfd_check_alive(pidfd.as_fd()).unwrap();
+
+        // A process that we aren't allowed to signal is still alive
+        match unsafe { libc::fork() } {
+            -1 => panic!("{}", io::Error::last_os_error()),
+            0 => unsafe {
+                // Skip the test if we can't switch UIDs (e.g. not root, or 65534 isn't mapped)
+                if libc::setresuid(65534, 65534, 65534) < 0 {
+                    libc::_exit(0);
+                }
+                if libc::kill(libc::getppid(), 0) == 0 {
+                    libc::_exit(2);
+                }
+                libc::_exit(if pidfd_check_alive(pidfd.as_fd()).is_ok() {
+                    0
+                } else {
+                    1
+                });
+            },
+            pid => {
+                let mut wstatus = 0;
+                if unsafe { libc::waitpid(pid, &mut wstatus, 0) } != pid {
+                    panic!("{}", io::Error::last_os_error());
+                }
+
+                assert!(libc::WIFEXITED(wstatus));
+                assert_eq!(libc::WEXITSTATUS(wstatus), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_info() {
+        let info = ProcessInfo::get(getpid()).unwrap();
+        assert_eq!(info.pid, getpid());
+        assert_eq!(info.tgid, getpid());
+        assert_eq!(info.ppid, unsafe { libc::getppid() });
+        assert_eq!(info.uids.real, unsafe { libc::getuid() });
+        assert_eq!(info.uids.effective, unsafe { libc::geteuid() });
+        assert!(!info.comm.is_empty());
+
+        assert_eq!(
+            ProcessInfo::get(0).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            ProcessInfo::get(libc::pid_t::MAX)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ESRCH)
+        );
+    }
+
+    #[test]
+    fn test_process_security_status() {
+        let status = ProcessSecurityStatus::get(getpid()).unwrap();
+        assert_eq!(status.process, ProcessInfo::get(getpid()).unwrap());
+        assert_eq!(status.gids.real, unsafe { libc::getgid() });
+        assert_eq!(status.gids.effective, unsafe { libc::getegid() });
+
+        let mut groups = vec![0; 65536];
+        let n = unsafe { libc::getgroups(groups.len() as libc::c_int, groups.as_mut_ptr()) };
+        groups.truncate(n as usize);
+        assert_eq!(status.groups, groups);
+
+        if let Some(pid) = status.ns_pids.first() {
+            assert_eq!(*pid, getpid());
+        }
+
+        let user_ns = std::os::unix::fs::MetadataExt::ino(
+            &fs::metadata(format!("/proc/{}/ns/user", getpid())).unwrap(),
+        );
+        assert_eq!(status.user_ns, Some(user_ns));
+
+        assert_eq!(
+            ProcessSecurityStatus::get(libc::pid_t::MAX)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ESRCH)
+        );
+    }
+
+    #[test]
+    fn test_processes() {
+        let infos = Processes::new()
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert!(infos.iter().any(|info| info.pid == getpid()));
+        assert!(infos.iter().all(|info| info.pid == info.tgid));
+
+        let tid = gettid();
+        let state = FullCapState::get_current().unwrap();
+        let info = Processes::new()
+            .unwrap()
+            .threads(true)
+            .map(|info| info.unwrap())
+            .find(|info| info.pid == tid)
+            .unwrap();
+        assert_eq!(info.tgid, getpid());
+        assert_eq!(info.caps, state);
+
+        assert_eq!(Processes::new().unwrap().caps_filter(|_| false).count(), 0);
+
+        for info in Processes::new().unwrap().with_caps() {
+            assert!(info.unwrap().has_caps());
+        }
+    }
+
+    #[test]
+    fn test_unescape_name() {
+        assert_eq!(unescape_name("abc"), "abc");
+        assert_eq!(unescape_name("a\\nb\\\\c"), "a\nb\\c");
+        assert_eq!(unescape_name("a\\"), "a\\");
+    }
+}
@@ -0,0 +1,884 @@
+//! Apply capability-related changes to every thread in the current process.
+//!
+//! On Linux, capabilities, UIDs/GIDs, the "keep capabilities" flag, the "no new privileges" flag,
+//! and the "securebits" flags are all per-**thread** attributes. Functions like
+//! [`CapState::set_current()`], [`ambient::raise()`](crate::caps::ambient::raise), and
+//! [`cap_set_ids()`](crate::caps::cap_set_ids) only change the calling thread, which can leave
+//! other threads behind with more privileges than intended.
+//!
+//! The functions in this module work like `libcap`'s "psx" mechanism: the change is made on the
+//! calling thread, and then a signal is sent to every other thread listed in `/proc/self/task`
+//! whose handler performs the same system call on that thread. Threads created while the change is
+//! being broadcast are picked up by rescanning `/proc/self/task` until no new threads appear.
+//!
+//! Each function returns a [`Report`] listing the result for every thread, along with the
+//! (common) capability state of all threads after the change, if they ended up consistent.
+//!
+//! # Caveats
+//!
+//! - This module installs a handler for `SIGRTMAX` the first time one of its functions is called,
+//!   and leaves it installed. If another handler has already been installed for that signal, the
+//!   functions in this module will fail with `EBUSY`.
+//! - Threads that block `SIGRTMAX` (or that are stopped) will not be able to respond. They are
+//!   reported as failing with `ETIMEDOUT` after a few seconds.
+//! - Only one broadcast can run at a time; concurrent calls from multiple threads are serialized.
+//! - Changes are not atomic across threads. If a change fails on some threads, the others are
+//!   **not** rolled back; check the [`Report`] and abort if necessary.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicIsize, AtomicPtr, AtomicU8, AtomicUsize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::caps::{Cap, CapState, FullCapState, RawCapState};
+use crate::prctl::Secbits;
+use crate::sys;
+
+/// How long to wait for the other threads to respond before giving up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to check whether threads that haven't responded yet have exited.
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+cfg_if::cfg_if! {
+    if #[cfg(all(
+        target_pointer_width = "32",
+        any(target_arch = "arm", target_arch = "sparc", target_arch = "x86")
+    ))] {
+        const SYS_SETRESUID: libc::c_long = libc::SYS_setresuid32;
+        const SYS_SETRESGID: libc::c_long = libc::SYS_setresgid32;
+        const SYS_SETGROUPS: libc::c_long = libc::SYS_setgroups32;
+    } else {
+        const SYS_SETRESUID: libc::c_long = libc::SYS_setresuid;
+        const SYS_SETRESGID: libc::c_long = libc::SYS_setresgid;
+        const SYS_SETGROUPS: libc::c_long = libc::SYS_setgroups;
+    }
+}
+
+/// A system call (with arguments) to be performed on every thread.
+#[derive(Copy, Clone, Debug)]
+struct Syscall {
+    nr: libc::c_long,
+    args: [libc::c_ulong; 5],
+}
+
+impl Syscall {
+    #[inline]
+    fn prctl(option: libc::c_int, arg2: libc::c_ulong) -> Self {
+        Self {
+            nr: libc::SYS_prctl,
+            args: [option as libc::c_ulong, arg2, 0, 0, 0],
+        }
+    }
+
+    /// Perform the system call on the current thread.
+    ///
+    /// This is async-signal-safe, and it preserves `errno`.
+    #[inline]
+    unsafe fn invoke(&self) -> Result<libc::c_long, i32> {
+        let errno = libc::__errno_location();
+        let saved_errno = *errno;
+
+        let res = libc::syscall(
+            self.nr,
+            self.args[0],
+            self.args[1],
+            self.args[2],
+            self.args[3],
+            self.args[4],
+        );
+        let res = if res < 0 { Err(*errno) } else { Ok(res) };
+
+        *errno = saved_errno;
+        res
+    }
+}
+
+const SLOT_PENDING: u8 = 0;
+const SLOT_RUNNING: u8 = 1;
+const SLOT_DONE: u8 = 2;
+const SLOT_EXITED: u8 = 3;
+
+/// Tracks the progress of a single thread during a broadcast.
+struct Slot {
+    tid: libc::pid_t,
+    state: AtomicU8,
+    ret: AtomicIsize,
+    errno: AtomicI32,
+}
+
+struct Broadcast {
+    call: Syscall,
+    slots: Vec<Slot>,
+}
+
+/// The broadcast currently in progress (or null if there is none).
+static CURRENT: AtomicPtr<Broadcast> = AtomicPtr::new(core::ptr::null_mut());
+/// The number of signal handlers that may currently be looking at `CURRENT`.
+static ACTIVE_HANDLERS: AtomicUsize = AtomicUsize::new(0);
+/// Serializes broadcasts (and installation of the signal handler).
+static LOCK: Mutex<()> = Mutex::new(());
+static HANDLER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+fn gettid() -> libc::pid_t {
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+}
+
+extern "C" fn handle_signal(_sig: libc::c_int) {
+    // Everything in here must be async-signal-safe. In particular, we can't allocate memory or
+    // take any locks.
+
+    // This MUST happen before loading CURRENT; see the comment in broadcast()
+    ACTIVE_HANDLERS.fetch_add(1, Ordering::SeqCst);
+
+    let bc = CURRENT.load(Ordering::SeqCst);
+    if !bc.is_null() {
+        let bc = unsafe { &*bc };
+        let tid = gettid();
+
+        if let Some(slot) = bc.slots.iter().find(|slot| slot.tid == tid) {
+            // Make sure we only perform the syscall once, even if the signal is delivered twice
+            if slot
+                .state
+                .compare_exchange(
+                    SLOT_PENDING,
+                    SLOT_RUNNING,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+            {
+                match unsafe { bc.call.invoke() } {
+                    Ok(ret) => slot.ret.store(ret as isize, Ordering::SeqCst),
+                    Err(eno) => slot.errno.store(eno, Ordering::SeqCst),
+                }
+                slot.state.store(SLOT_DONE, Ordering::SeqCst);
+            }
+        }
+    }
+
+    ACTIVE_HANDLERS.fetch_sub(1, Ordering::SeqCst);
+}
+
+fn install_handler() -> io::Result<()> {
+    if HANDLER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let sig = libc::SIGRTMAX();
+
+    unsafe {
+        let mut old_act: libc::sigaction = core::mem::zeroed();
+        if libc::sigaction(sig, core::ptr::null(), &mut old_act) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if old_act.sa_sigaction != libc::SIG_DFL && old_act.sa_sigaction != libc::SIG_IGN {
+            // Somebody else is using this signal
+            return Err(io::Error::from_raw_os_error(libc::EBUSY));
+        }
+
+        let mut act: libc::sigaction = core::mem::zeroed();
+        act.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        act.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut act.sa_mask);
+
+        if libc::sigaction(sig, &act, core::ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    HANDLER_INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// List the TIDs of all the threads in the current process.
+fn list_tids() -> io::Result<Vec<libc::pid_t>> {
+    let mut tids = Vec::new();
+
+    for entry in std::fs::read_dir("/proc/self/task")? {
+        if let Some(tid) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            tids.push(tid);
+        }
+    }
+
+    Ok(tids)
+}
+
+/// Perform the given system call on every thread in the process.
+///
+/// Returns the result for every thread that was found (threads that exited before they could be
+/// signaled are omitted).
+fn broadcast(call: Syscall) -> io::Result<Vec<(libc::pid_t, Result<libc::c_long, i32>)>> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    install_handler()?;
+
+    let pid = unsafe { libc::getpid() };
+    let self_tid = gettid();
+
+    let mut results = vec![(self_tid, unsafe { call.invoke() })];
+    let mut seen = vec![self_tid];
+
+    loop {
+        let new_tids: Vec<libc::pid_t> = list_tids()?
+            .into_iter()
+            .filter(|tid| !seen.contains(tid))
+            .collect();
+        if new_tids.is_empty() {
+            break;
+        }
+        seen.extend_from_slice(&new_tids);
+
+        let bc = Box::new(Broadcast {
+            call,
+            slots: new_tids
+                .into_iter()
+                .map(|tid| Slot {
+                    tid,
+                    state: AtomicU8::new(SLOT_PENDING),
+                    ret: AtomicIsize::new(0),
+                    errno: AtomicI32::new(0),
+                })
+                .collect(),
+        });
+
+        CURRENT.store(&*bc as *const _ as *mut _, Ordering::SeqCst);
+
+        for slot in bc.slots.iter() {
+            if unsafe { libc::syscall(libc::SYS_tgkill, pid, slot.tid, libc::SIGRTMAX()) } < 0 {
+                // The thread must have exited (ESRCH); don't wait for it
+                let _ = slot.state.compare_exchange(
+                    SLOT_PENDING,
+                    SLOT_EXITED,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                );
+            }
+        }
+
+        let deadline = Instant::now() + TIMEOUT;
+        let mut next_exit_check = Instant::now() + EXIT_CHECK_INTERVAL;
+        while bc.slots.iter().any(|slot| {
+            let state = slot.state.load(Ordering::SeqCst);
+            state == SLOT_PENDING || state == SLOT_RUNNING
+        }) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            if now >= next_exit_check {
+                // A thread may have exited after it was signaled, but before the handler ran
+                for slot in bc.slots.iter() {
+                    if slot.state.load(Ordering::SeqCst) == SLOT_PENDING && !thread_exists(slot.tid)
+                    {
+                        let _ = slot.state.compare_exchange(
+                            SLOT_PENDING,
+                            SLOT_EXITED,
+                            Ordering::SeqCst,
+                            Ordering::SeqCst,
+                        );
+                    }
+                }
+                next_exit_check = now + EXIT_CHECK_INTERVAL;
+            }
+
+            std::thread::sleep(Duration::from_micros(100));
+        }
+
+        // Detach the broadcast and wait for any handlers that might still be looking at it. Since
+        // the handler increments ACTIVE_HANDLERS *before* loading CURRENT, once we see the count
+        // drop to 0 no handler can be holding a reference to `bc`.
+        CURRENT.store(core::ptr::null_mut(), Ordering::SeqCst);
+        while ACTIVE_HANDLERS.load(Ordering::SeqCst) != 0 {
+            core::hint::spin_loop();
+        }
+
+        for slot in bc.slots.iter() {
+            match slot.state.load(Ordering::SeqCst) {
+                SLOT_DONE => {
+                    let eno = slot.errno.load(Ordering::SeqCst);
+                    let res = if eno != 0 {
+                        Err(eno)
+                    } else {
+                        Ok(slot.ret.load(Ordering::SeqCst) as libc::c_long)
+                    };
+                    results.push((slot.tid, res));
+                }
+                SLOT_EXITED => (),
+                _ => {
+                    if thread_exists(slot.tid) {
+                        results.push((slot.tid, Err(libc::ETIMEDOUT)));
+                    }
+                }
+            }
+        }
+    }
+
+    results.sort_by_key(|&(tid, _)| tid);
+    Ok(results)
+}
+
+#[inline]
+fn thread_exists(tid: libc::pid_t) -> bool {
+    std::path::Path::new(&format!("/proc/self/task/{}", tid)).exists()
+}
+
+/// The result of performing an operation on a single thread.
+#[derive(Debug)]
+pub struct ThreadResult {
+    /// The thread's TID.
+    pub tid: libc::pid_t,
+    /// Whether the operation succeeded on this thread.
+    pub result: crate::Result<()>,
+}
+
+/// A report on the results of applying a change to every thread in the process.
+///
+/// Threads that exited while the change was being applied are not included.
+#[derive(Debug)]
+pub struct Report {
+    threads: Vec<ThreadResult>,
+    state: Option<FullCapState>,
+}
+
+impl Report {
+    fn from_results(results: BTreeMap<libc::pid_t, crate::Result<()>>) -> io::Result<Self> {
+        Ok(Self {
+            threads: results
+                .into_iter()
+                .map(|(tid, result)| ThreadResult { tid, result })
+                .collect(),
+            state: common_state()?,
+        })
+    }
+
+    /// Get the results for every thread, sorted by TID.
+    #[inline]
+    pub fn threads(&self) -> &[ThreadResult] {
+        &self.threads
+    }
+
+    /// Get an iterator over the results for the threads on which the operation failed.
+    #[inline]
+    pub fn failures(&self) -> impl Iterator<Item = &ThreadResult> {
+        self.threads.iter().filter(|t| t.result.is_err())
+    }
+
+    /// Get the capability state that all threads in the process were found to share after the
+    /// operation, or `None` if the threads' states differ.
+    ///
+    /// Note that this does not include the "securebits" flags, since the kernel does not expose
+    /// them for other threads.
+    #[inline]
+    pub fn consistent_state(&self) -> Option<&FullCapState> {
+        self.state.as_ref()
+    }
+
+    /// Returns `true` if the operation succeeded on every thread and all threads ended up in the
+    /// same capability state.
+    #[inline]
+    pub fn is_success(&self) -> bool {
+        self.state.is_some() && self.failures().next().is_none()
+    }
+}
+
+/// Get the full capability state of every thread in the current process.
+///
+/// Threads that exit while this function is running are omitted.
+pub fn thread_states() -> io::Result<Vec<(libc::pid_t, FullCapState)>> {
+    let mut states = Vec::new();
+
+    for tid in list_tids()? {
+        match FullCapState::get_for_pid(tid) {
+            Ok(state) => states.push((tid, state)),
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(states)
+}
+
+/// Get the state shared by all threads, or `None` if they differ.
+fn common_state() -> io::Result<Option<FullCapState>> {
+    let states = thread_states()?;
+
+    Ok(match states.split_first() {
+        Some(((_, first), rest)) if rest.iter().all(|(_, state)| state == first) => Some(*first),
+        _ => None,
+    })
+}
+
+/// Broadcast `call`, recording the first error for each thread in `results`.
+///
+/// Returns `true` if the call succeeded on every thread.
+fn step(results: &mut BTreeMap<libc::pid_t, crate::Result<()>>, call: Syscall) -> io::Result<bool> {
+    let mut success = true;
+
+    for (tid, res) in broadcast(call)? {
+        let entry = results.entry(tid).or_insert(Ok(()));
+        if let Err(eno) = res {
+            success = false;
+            if entry.is_ok() {
+                *entry = Err(crate::Error::from_code(eno));
+            }
+        }
+    }
+
+    Ok(success)
+}
+
+fn run(call: Syscall) -> io::Result<Report> {
+    let mut results = BTreeMap::new();
+    step(&mut results, call)?;
+    Report::from_results(results)
+}
+
+/// Build the capset() arguments for the given state.
+///
+/// The returned header and data must outlive the broadcast.
+fn capset_args(state: &CapState) -> (sys::cap_user_header_t, [sys::cap_user_data_t; 2]) {
+    let header = sys::cap_user_header_t {
+        version: sys::_LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+
+    let RawCapState {
+        effective: eff,
+        permitted: perm,
+        inheritable: inh,
+    } = RawCapState::from(*state);
+
+    let data = [
+        sys::cap_user_data_t {
+            effective: eff as u32,
+            permitted: perm as u32,
+            inheritable: inh as u32,
+        },
+        sys::cap_user_data_t {
+            effective: (eff >> 32) as u32,
+            permitted: (perm >> 32) as u32,
+            inheritable: (inh >> 32) as u32,
+        },
+    ];
+
+    (header, data)
+}
+
+fn step_capset(
+    results: &mut BTreeMap<libc::pid_t, crate::Result<()>>,
+    state: &CapState,
+) -> io::Result<bool> {
+    let (mut header, data) = capset_args(state);
+
+    step(
+        results,
+        Syscall {
+            nr: libc::SYS_capset,
+            args: [
+                &mut header as *mut _ as libc::c_ulong,
+                data.as_ptr() as libc::c_ulong,
+                0,
+                0,
+                0,
+            ],
+        },
+    )
+}
+
+/// Set the permitted, effective, and inheritable capability sets of every thread in the process.
+///
+/// This is the process-wide equivalent of [`CapState::set_current()`].
+pub fn set_capstate(state: &CapState) -> io::Result<Report> {
+    let mut results = BTreeMap::new();
+    step_capset(&mut results, state)?;
+    Report::from_results(results)
+}
+
+/// Raise the given capability in the ambient set of every thread in the process.
+///
+/// This is the process-wide equivalent of [`ambient::raise()`](crate::caps::ambient::raise).
+#[inline]
+pub fn ambient_raise(cap: Cap) -> io::Result<Report> {
+    run(Syscall {
+        nr: libc::SYS_prctl,
+        args: [
+            libc::PR_CAP_AMBIENT as libc::c_ulong,
+            libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+            cap as libc::c_ulong,
+            0,
+            0,
+        ],
+    })
+}
+
+/// Lower the given capability in the ambient set of every thread in the process.
+///
+/// This is the process-wide equivalent of [`ambient::lower()`](crate::caps::ambient::lower).
+#[inline]
+pub fn ambient_lower(cap: Cap) -> io::Result<Report> {
+    run(Syscall {
+        nr: libc::SYS_prctl,
+        args: [
+            libc::PR_CAP_AMBIENT as libc::c_ulong,
+            libc::PR_CAP_AMBIENT_LOWER as libc::c_ulong,
+            cap as libc::c_ulong,
+            0,
+            0,
+        ],
+    })
+}
+
+/// Clear the ambient set of every thread in the process.
+///
+/// This is the process-wide equivalent of [`ambient::clear()`](crate::caps::ambient::clear).
+#[inline]
+pub fn ambient_clear() -> io::Result<Report> {
+    run(Syscall::prctl(
+        libc::PR_CAP_AMBIENT,
+        libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+    ))
+}
+
+/// Remove the given capability from the bounding set of every thread in the process.
+///
+/// This is the process-wide equivalent of [`bounding::drop()`](crate::caps::bounding::drop).
+#[inline]
+pub fn bounding_drop(cap: Cap) -> io::Result<Report> {
+    run(Syscall::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong))
+}
+
+/// Set the "securebits" flags of every thread in the process.
+///
+/// This is the process-wide equivalent of [`set_securebits()`](crate::prctl::set_securebits).
+#[inline]
+pub fn set_securebits(flags: Secbits) -> io::Result<Report> {
+    run(Syscall::prctl(libc::PR_SET_SECUREBITS, flags.bits()))
+}
+
+/// Set the "keep capabilities" flag of every thread in the process.
+///
+/// This is the process-wide equivalent of [`set_keepcaps()`](crate::prctl::set_keepcaps).
+#[inline]
+pub fn set_keepcaps(keep: bool) -> io::Result<Report> {
+    run(Syscall::prctl(libc::PR_SET_KEEPCAPS, keep as libc::c_ulong))
+}
+
+/// Set the "no new privileges" flag of every thread in the process.
+///
+/// This is the process-wide equivalent of [`set_no_new_privs()`](crate::prctl::set_no_new_privs).
+#[inline]
+pub fn set_no_new_privs() -> io::Result<Report> {
+    run(Syscall::prctl(libc::PR_SET_NO_NEW_PRIVS, 1))
+}
+
+/// Set the UID/GID/supplementary groups of every thread in the process while preserving
+/// permitted capabilities.
+///
+/// This is the process-wide equivalent of [`cap_set_ids()`](crate::caps::cap_set_ids), and it
+/// performs the same steps in the same order, broadcasting each one to every thread. Note that
+/// (since CAP_SETUID/CAP_SETGID must be raised in the effective set) every thread is first given
+/// the *calling* thread's permitted and inheritable capability sets.
+///
+/// If a step fails on any thread, the remaining ID changes are skipped, but the effective
+/// capability set is still cleared and the "keep capabilities" flag is still restored. As with
+/// `cap_set_ids()`, if this happens the threads are in an unknown and possibly inconsistent state,
+/// and you should abort as soon as possible if you are unable to revert the changes.
+pub fn cap_set_ids(
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    groups: Option<&[libc::gid_t]>,
+) -> io::Result<Report> {
+    let mut capstate = CapState::get_current()?;
+    let orig_keepcaps = crate::prctl::get_keepcaps()?;
+
+    let mut results = BTreeMap::new();
+
+    if !step(&mut results, Syscall::prctl(libc::PR_SET_KEEPCAPS, 1))? {
+        step(
+            &mut results,
+            Syscall::prctl(libc::PR_SET_KEEPCAPS, orig_keepcaps as libc::c_ulong),
+        )?;
+        return Report::from_results(results);
+    }
+
+    if gid.is_some() || groups.is_some() {
+        capstate.effective.add(Cap::SETGID);
+    }
+    if uid.is_some() {
+        capstate.effective.add(Cap::SETUID);
+    }
+
+    let mut ok = step_capset(&mut results, &capstate)?;
+
+    if ok {
+        if let Some(gid) = gid {
+            let gid = gid as libc::c_ulong;
+            ok = step(
+                &mut results,
+                Syscall {
+                    nr: SYS_SETRESGID,
+                    args: [gid, gid, gid, 0, 0],
+                },
+            )?;
+        }
+    }
+
+    if ok {
+        if let Some(groups) = groups {
+            ok = step(
+                &mut results,
+                Syscall {
+                    nr: SYS_SETGROUPS,
+                    args: [
+                        groups.len() as libc::c_ulong,
+                        groups.as_ptr() as libc::c_ulong,
+                        0,
+                        0,
+                        0,
+                    ],
+                },
+            )?;
+        }
+    }
+
+    if ok {
+        if let Some(uid) = uid {
+            let uid = uid as libc::c_ulong;
+            step(
+                &mut results,
+                Syscall {
+                    nr: SYS_SETRESUID,
+                    args: [uid, uid, uid, 0, 0],
+                },
+            )?;
+        }
+    }
+
+    capstate.effective.clear();
+    step_capset(&mut results, &capstate)?;
+    step(
+        &mut results,
+        Syscall::prctl(libc::PR_SET_KEEPCAPS, orig_keepcaps as libc::c_ulong),
+    )?;
+
+    Report::from_results(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Barrier};
+
+    use crate::capset;
+
+    #[test]
+    fn test_broadcast_gettid() {
+        let barrier = Arc::new(Barrier::new(4));
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let tid = gettid();
+                    barrier.wait();
+                    // Wait until the broadcast is done
+                    barrier.wait();
+                    tid
+                })
+            })
+            .collect();
+
+        barrier.wait();
+
+        let results = broadcast(Syscall {
+            nr: libc::SYS_gettid,
+            args: [0; 5],
+        })
+        .unwrap();
+
+        barrier.wait();
+        let tids: Vec<libc::pid_t> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Every thread should have run the syscall itself
+        for (tid, res) in results.iter() {
+            assert_eq!(*res, Ok(*tid as libc::c_long));
+        }
+
+        for tid in tids.iter().copied().chain(core::iter::once(gettid())) {
+            assert!(results.iter().any(|&(t, _)| t == tid), "{}", tid);
+        }
+    }
+
+    #[test]
+    fn test_broadcast_thread_exits() {
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let handle = std::thread::spawn(move || {
+            // Block the signal so the handler never runs, then exit once it arrives
+            unsafe {
+                let mut set: libc::sigset_t = core::mem::zeroed();
+                libc::sigemptyset(&mut set);
+                libc::sigaddset(&mut set, libc::SIGRTMAX());
+                libc::pthread_sigmask(libc::SIG_BLOCK, &set, core::ptr::null_mut());
+            }
+            tx.send(gettid()).unwrap();
+
+            loop {
+                let mut pending: libc::sigset_t = unsafe { core::mem::zeroed() };
+                unsafe {
+                    libc::sigpending(&mut pending);
+                }
+                if unsafe { libc::sigismember(&pending, libc::SIGRTMAX()) } == 1 {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let tid = rx.recv().unwrap();
+
+        let start = Instant::now();
+        let results = broadcast(Syscall {
+            nr: libc::SYS_gettid,
+            args: [0; 5],
+        })
+        .unwrap();
+        assert!(start.elapsed() < TIMEOUT, "{:?}", start.elapsed());
+        assert!(results.iter().all(|&(t, _)| t != tid));
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_thread_states() {
+        let states = thread_states().unwrap();
+
+        let tid = gettid();
+        let (_, state) = states.iter().find(|&&(t, _)| t == tid).unwrap();
+        assert_eq!(state.permitted, CapState::get_current().unwrap().permitted);
+    }
+
+    /// Get the security status of every thread in the process.
+    fn all_threads() -> Vec<crate::procs::ProcessSecurityStatus> {
+        list_tids()
+            .unwrap()
+            .into_iter()
+            .map(|tid| crate::procs::ProcessSecurityStatus::get(tid).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_process_wide() {
+        // These tests change every thread in the process, so they have to run in a separate process
+        // (with no other tests running)
+        if std::env::var_os("CAPCTL_TEST_PROCESS_WIDE").is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "threads::tests::test_process_wide",
+                    "--test-threads=1",
+                ])
+                .env("CAPCTL_TEST_PROCESS_WIDE", "1")
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return;
+        }
+
+        let barrier = Arc::new(Barrier::new(4));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || barrier.wait())
+            })
+            .collect();
+
+        let orig = CapState::get_current().unwrap();
+        assert!(all_threads().len() >= 4);
+
+        // Threads that end up in different states
+        let mut state = orig;
+        state.effective.clear();
+        state.set_current().unwrap();
+        let report = ambient_lower(Cap::CHOWN).unwrap();
+        assert_eq!(report.failures().count(), 0);
+        assert!(report.consistent_state().is_none());
+        assert!(!report.is_success());
+
+        // set_capstate() brings them back in line
+        let report = set_capstate(&state).unwrap();
+        assert!(report.is_success(), "{:?}", report);
+        assert_eq!(report.threads().len(), all_threads().len());
+        assert_eq!(
+            report.consistent_state().unwrap().effective,
+            state.effective
+        );
+        for status in all_threads() {
+            assert_eq!(status.process.caps.effective, state.effective);
+            assert_eq!(status.process.caps.permitted, state.permitted);
+        }
+
+        if state.permitted.has(Cap::NET_RAW) {
+            state.inheritable.add(Cap::NET_RAW);
+            assert!(set_capstate(&state).unwrap().is_success());
+
+            let report = ambient_raise(Cap::NET_RAW).unwrap();
+            assert!(report.is_success(), "{:?}", report);
+            assert!(report.consistent_state().unwrap().ambient.has(Cap::NET_RAW));
+            for status in all_threads() {
+                assert!(status.process.caps.ambient.has(Cap::NET_RAW));
+            }
+
+            assert!(ambient_clear().unwrap().is_success());
+        } else {
+            // Not in the permitted set, so it can't be raised anywhere
+            let report = ambient_raise(Cap::NET_RAW).unwrap();
+            assert!(!report.is_success());
+            assert_eq!(report.failures().count(), report.threads().len());
+            for res in report.failures() {
+                assert_eq!(res.result.as_ref().unwrap_err().code(), libc::EPERM);
+            }
+        }
+
+        // Do this last, since it can't be undone
+        let report = cap_set_ids(Some(1000), Some(1000), Some(&[])).unwrap();
+        if report.failures().next().is_none() {
+            assert!(report.is_success(), "{:?}", report);
+            for status in all_threads() {
+                assert_eq!(status.process.uids.real, 1000);
+                assert_eq!(status.process.uids.effective, 1000);
+                assert_eq!(status.gids.real, 1000);
+                assert_eq!(status.groups, vec![]);
+                assert_eq!(status.process.caps.permitted, state.permitted);
+                assert_eq!(status.process.caps.effective, capset!());
+            }
+        } else {
+            // For example, not root, or UID 1000 isn't mapped
+            assert!(!report.is_success());
+            for status in all_threads() {
+                assert_eq!(status.process.uids.real, unsafe { libc::getuid() });
+            }
+        }
+
+        barrier.wait();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}